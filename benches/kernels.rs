@@ -0,0 +1,63 @@
+//! The per-bin kernels of the weight update, scalar against runtime-dispatched.
+//!
+//! Run with `cargo bench --bench kernels`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fdaf_aec::kernels::{self, scalar};
+use num_complex::Complex;
+
+/// The bins of a 2048-point FFT.
+const BINS: usize = 1025;
+
+fn spectrum(frequency: f32) -> Vec<Complex<f32>> {
+    (0..BINS)
+        .map(|i| Complex::from_polar(1.0, i as f32 * frequency))
+        .collect()
+}
+
+fn bench_kernels(c: &mut Criterion) {
+    let (a, b) = (spectrum(0.05), spectrum(0.07));
+    // Unit divisors keep the repeatedly normalized output from decaying into subnormals.
+    let psd = vec![1.0; BINS];
+    let mut output = vec![Complex::default(); BINS];
+    let mut smoothed = vec![1.0; BINS];
+
+    let mut group = c.benchmark_group("smooth_psd");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::smooth_psd(&mut smoothed, black_box(&a), 0.9))
+    });
+    group.bench_function("dispatched", |bench| {
+        bench.iter(|| kernels::smooth_psd(&mut smoothed, black_box(&a), 0.9))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("complex_mul");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::complex_mul(&mut output, black_box(&a), black_box(&b)))
+    });
+    group.bench_function("dispatched", |bench| {
+        bench.iter(|| kernels::complex_mul(&mut output, black_box(&a), black_box(&b)))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("normalize_gradient");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::normalize_gradient(&mut output, black_box(&psd), 0.0))
+    });
+    group.bench_function("dispatched", |bench| {
+        bench.iter(|| kernels::normalize_gradient(&mut output, black_box(&psd), 0.0))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("leaky_update");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::leaky_update(&mut output, black_box(&a), 0.5, 0.999))
+    });
+    group.bench_function("dispatched", |bench| {
+        bench.iter(|| kernels::leaky_update(&mut output, black_box(&a), 0.5, 0.999))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_kernels);
+criterion_main!(benches);
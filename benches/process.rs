@@ -0,0 +1,164 @@
+//! Per-frame cost of the cancellers at common FFT sizes.
+//!
+//! Run with `cargo bench --bench process`.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use fdaf_aec::{FdafAec, PartitionedFdafAec};
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+fn signal(len: usize, frequency: f32) -> Vec<f32> {
+    (0..len).map(|i| (i as f32 * frequency).sin()).collect()
+}
+
+fn bench_fdaf<const FFT_SIZE: usize, const FRAME_SIZE: usize>(c: &mut Criterion) {
+    let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+    let far_end: [f32; FRAME_SIZE] = signal(FRAME_SIZE, 0.05).try_into().unwrap();
+    let mic: [f32; FRAME_SIZE] = signal(FRAME_SIZE, 0.07).try_into().unwrap();
+    let mut error = [0.0; FRAME_SIZE];
+
+    c.bench_with_input(
+        BenchmarkId::new("fdaf_process", FFT_SIZE),
+        &FFT_SIZE,
+        |b, _| b.iter(|| aec.process(&mut error, black_box(&far_end), black_box(&mic))),
+    );
+}
+
+/// The canceller as it was before the half-spectrum path: full complex FFTs over all
+/// `FFT_SIZE` bins of the zero-imaginary signals. Kept as the baseline `fdaf_process` is
+/// measured against.
+struct ComplexFdaf<const FFT_SIZE: usize> {
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    scratch: Vec<Complex<f32>>,
+    weights: Vec<Complex<f32>>,
+    far_end_buffer: Vec<f32>,
+    x_f: Vec<Complex<f32>>,
+    y: Vec<Complex<f32>>,
+    e_f: Vec<Complex<f32>>,
+    gradient: Vec<Complex<f32>>,
+    psd: Vec<f32>,
+}
+
+impl<const FFT_SIZE: usize> ComplexFdaf<FFT_SIZE> {
+    const MU: f32 = 0.5;
+    const SMOOTHING_FACTOR: f32 = 0.9;
+    const REGULARIZATION_FACTOR: f32 = 10e-4;
+    const LEAK: f32 = 10e-4;
+
+    fn new() -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let ifft = planner.plan_fft_inverse(FFT_SIZE);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+        let spectrum = vec![Complex::new(0.0, 0.0); FFT_SIZE];
+        Self {
+            fft,
+            ifft,
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            weights: spectrum.clone(),
+            far_end_buffer: vec![0.0; FFT_SIZE],
+            x_f: spectrum.clone(),
+            y: spectrum.clone(),
+            e_f: spectrum.clone(),
+            gradient: spectrum,
+            psd: vec![1.0; FFT_SIZE],
+        }
+    }
+
+    fn process(&mut self, error_signal: &mut [f32], far_end_frame: &[f32], mic_frame: &[f32]) {
+        let frame_size = FFT_SIZE / 2;
+        let scale = 1.0 / FFT_SIZE as f32;
+
+        self.far_end_buffer.copy_within(frame_size.., 0);
+        self.far_end_buffer[frame_size..].copy_from_slice(far_end_frame);
+        for (x_f, &x) in self.x_f.iter_mut().zip(&self.far_end_buffer) {
+            *x_f = Complex::new(x, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.x_f, &mut self.scratch);
+        for (p, x) in self.psd.iter_mut().zip(&self.x_f) {
+            *p = Self::SMOOTHING_FACTOR * *p + (1.0 - Self::SMOOTHING_FACTOR) * x.norm_sqr();
+        }
+
+        for ((y, w), x) in self.y.iter_mut().zip(&self.weights).zip(&self.x_f) {
+            *y = w * x;
+        }
+        self.ifft
+            .process_with_scratch(&mut self.y, &mut self.scratch);
+        for ((e, d), y) in error_signal
+            .iter_mut()
+            .zip(mic_frame)
+            .zip(&self.y[frame_size..])
+        {
+            *e = d - y.re * scale;
+        }
+
+        self.e_f[..frame_size].fill(Complex::new(0.0, 0.0));
+        for (e_f, &e) in self.e_f[frame_size..].iter_mut().zip(error_signal.iter()) {
+            *e_f = Complex::new(e, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.e_f, &mut self.scratch);
+        for (((g, x), e), p) in self
+            .gradient
+            .iter_mut()
+            .zip(&self.x_f)
+            .zip(&self.e_f)
+            .zip(&self.psd)
+        {
+            *g = x.conj() * e / (p + Self::REGULARIZATION_FACTOR);
+        }
+        self.ifft
+            .process_with_scratch(&mut self.gradient, &mut self.scratch);
+        for g in &mut self.gradient[..frame_size] {
+            *g *= scale;
+        }
+        self.gradient[frame_size..].fill(Complex::new(0.0, 0.0));
+        self.fft
+            .process_with_scratch(&mut self.gradient, &mut self.scratch);
+        for (w, g) in self.weights.iter_mut().zip(&self.gradient) {
+            *w = *w * (1.0 - Self::LEAK) + g * Self::MU;
+        }
+    }
+}
+
+fn bench_complex_fdaf<const FFT_SIZE: usize, const FRAME_SIZE: usize>(c: &mut Criterion) {
+    let mut aec = ComplexFdaf::<FFT_SIZE>::new();
+    let far_end = signal(FRAME_SIZE, 0.05);
+    let mic = signal(FRAME_SIZE, 0.07);
+    let mut error = [0.0; FRAME_SIZE];
+
+    c.bench_with_input(
+        BenchmarkId::new("complex_fdaf_process", FFT_SIZE),
+        &FFT_SIZE,
+        |b, _| b.iter(|| aec.process(&mut error, black_box(&far_end), black_box(&mic))),
+    );
+}
+
+fn bench_partitioned(c: &mut Criterion) {
+    let mut aec = PartitionedFdafAec::<256, 8>::new(0.5, 0.9, 10e-4, 10e-4);
+    let far_end: [f32; 256] = signal(256, 0.05).try_into().unwrap();
+    let mic: [f32; 256] = signal(256, 0.07).try_into().unwrap();
+    let mut error = [0.0; 256];
+
+    c.bench_function("partitioned_process/256x8", |b| {
+        b.iter(|| aec.process(&mut error, black_box(&far_end), black_box(&mic)))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_fdaf::<512, 256>,
+    bench_fdaf::<1024, 512>,
+    bench_fdaf::<2048, 1024>,
+    bench_complex_fdaf::<512, 256>,
+    bench_complex_fdaf::<1024, 512>,
+    bench_complex_fdaf::<2048, 1024>,
+    bench_partitioned
+);
+criterion_main!(benches);
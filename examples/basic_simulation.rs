@@ -33,9 +33,13 @@ fn main() {
     // 2. Near-end signal: An 880Hz sine wave, representing the local user's voice.
     //    It starts after 0.5 seconds to create a period of single-talk (echo only).
     let mut near_end_signal = vec![0.0; (SAMPLE_RATE * 2) as usize];
-    for i in (SAMPLE_RATE / 2) as usize..near_end_signal.len() {
+    for (i, sample) in near_end_signal
+        .iter_mut()
+        .enumerate()
+        .skip((SAMPLE_RATE / 2) as usize)
+    {
         let t = i as f32 / SAMPLE_RATE as f32;
-        near_end_signal[i] = 0.4 * (2.0 * std::f32::consts::PI * 880.0 * t).sin();
+        *sample = 0.4 * (2.0 * std::f32::consts::PI * 880.0 * t).sin();
     }
 
     // 3. Microphone signal: A mix of the near-end signal and a delayed, attenuated
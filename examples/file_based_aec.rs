@@ -19,10 +19,11 @@
 //!   --mic your_mic_file.wav \
 //!   --output your_output_file.wav
 //! ```
+//! Add `--kalman` to adapt with the frequency-domain Kalman filter instead of NLMS.
 //! The `--release` flag is recommended for faster processing.
 
 use clap::Parser;
-use fdaf_aec::FdafAec;
+use fdaf_aec::{FdafAec, KalmanConfig};
 use hound::{WavReader, WavSpec, WavWriter};
 use std::path::PathBuf;
 
@@ -44,6 +45,10 @@ struct Args {
     /// Step size (learning rate) for the adaptive filter.
     #[clap(long, value_parser, default_value_t = 0.02)]
     step_size: f32,
+
+    /// Adapt with the frequency-domain Kalman filter instead of NLMS.
+    #[clap(long)]
+    kalman: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -53,6 +58,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("- Mic file:     {}", args.mic.display());
     println!("- Output file:  {}", args.output.display());
     println!("- Step size:    {}", args.step_size);
+    println!(
+        "- Adaptation:   {}",
+        if args.kalman { "Kalman" } else { "NLMS" }
+    );
 
     const FFT_SIZE: usize = 1024;
     const FRAME_SIZE: usize = FFT_SIZE / 2;
@@ -71,6 +80,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // --- 2. Initialize AEC ---
     let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+    if args.kalman {
+        aec.enable_kalman_adaptation(KalmanConfig::default())?;
+    }
 
     // --- 3. Process Signals Frame by Frame ---
     let mut processed_signal: Vec<f32> = Vec::new();
@@ -15,7 +15,6 @@
 //! The `--release` flag is recommended for faster processing.
 
 use fdaf_aec::FdafAec;
-use hound;
 use rand::{rng, Rng};
 
 const SAMPLE_RATE: u32 = 16000;
@@ -43,9 +42,14 @@ fn main() {
     let mut near_end_signal = vec![0.0; TOTAL_SAMPLES];
     let start_sample = (SAMPLE_RATE * 2) as usize;
     let end_sample = (SAMPLE_RATE * 4) as usize;
-    for i in start_sample..end_sample {
+    for (i, sample) in near_end_signal
+        .iter_mut()
+        .enumerate()
+        .take(end_sample)
+        .skip(start_sample)
+    {
         let t = i as f32 / SAMPLE_RATE as f32;
-        near_end_signal[i] = 0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin();
+        *sample = 0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin();
     }
 
     // --- 2. Echo Simulation ---
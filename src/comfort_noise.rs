@@ -0,0 +1,274 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+
+use crate::{float, ConfigError, Float};
+
+/// Smoothing factor of the error PSD the noise floor is tracked on. Per-bin powers of a
+/// single frame fluctuate too much to take their lower envelope directly.
+const ERROR_SMOOTHING: f32 = 0.9;
+/// Smoothing factor of the noise floor tracker when the error PSD drops below it.
+const NOISE_FALL_SMOOTHING: f32 = 0.7;
+/// Smoothing factor of the noise floor tracker when the error PSD rises above it. Rising
+/// slowly keeps residual echo bursts from being mistaken for background noise.
+const NOISE_RISE_SMOOTHING: f32 = 0.99;
+
+/// Configuration of the comfort noise generator.
+///
+/// See [`FdafAec::enable_comfort_noise`](crate::FdafAec::enable_comfort_noise).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ComfortNoiseConfig {
+    /// Seed of the noise generator. For a given seed and input the output is fully
+    /// deterministic, which keeps regression tests reproducible.
+    pub seed: u32,
+    /// Gain applied to the tracked background noise spectrum; `1.0` fills attenuated bins
+    /// back up to the original background level. Must be finite and non-negative.
+    pub level: f32,
+    /// Mean-square power of a far-end frame above which the far-end is considered active.
+    /// Must be finite and non-negative.
+    pub far_end_threshold: f32,
+}
+
+impl Default for ComfortNoiseConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0x2545_f491,
+            level: 1.0,
+            far_end_threshold: 1e-6,
+        }
+    }
+}
+
+impl ComfortNoiseConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.level.is_finite() && self.level >= 0.0) {
+            return Err(ConfigError::InvalidNoiseLevel(self.level));
+        }
+        if !(self.far_end_threshold.is_finite() && self.far_end_threshold >= 0.0) {
+            return Err(ConfigError::InvalidFarEndThreshold(self.far_end_threshold));
+        }
+        Ok(())
+    }
+}
+
+/// Fills bins attenuated below the near-end background noise level with spectrally shaped
+/// noise, so heavy suppression does not produce digital silence.
+///
+/// The background noise spectrum is tracked from the linear error signal while only the
+/// far-end is active, following its lower envelope so residual echo does not leak into it.
+#[derive(Clone)]
+pub(crate) struct ComfortNoiseGenerator<T: Float> {
+    config: ComfortNoiseConfig,
+    error_psd: Vec<T>,
+    noise_psd: Vec<T>,
+    initialized: bool,
+    state: u32,
+}
+
+impl<T: Float> ComfortNoiseGenerator<T> {
+    pub(crate) fn new(config: ComfortNoiseConfig, fft_size: usize) -> Self {
+        Self {
+            config,
+            error_psd: vec![T::zero(); fft_size / 2 + 1],
+            noise_psd: vec![T::zero(); fft_size / 2 + 1],
+            initialized: false,
+            state: initial_state(config.seed),
+        }
+    }
+
+    /// Forgets the noise estimate and restarts the noise sequence from the seed.
+    pub(crate) fn reset(&mut self) {
+        self.error_psd.fill(T::zero());
+        self.noise_psd.fill(T::zero());
+        self.initialized = false;
+        self.state = initial_state(self.config.seed);
+    }
+
+    /// Updates the noise estimate and injects comfort noise into `output_spectrum`.
+    ///
+    /// `error_spectrum` and `output_spectrum` are half spectra of zero-padded frames of
+    /// `frame_size` samples, before and after suppression respectively.
+    pub(crate) fn process(
+        &mut self,
+        far_end_frame: &[T],
+        double_talk: bool,
+        error_spectrum: &[Complex<T>],
+        output_spectrum: &mut [Complex<T>],
+    ) {
+        let fft_size = (self.noise_psd.len() - 1) * 2;
+        let frame_size = far_end_frame.len();
+
+        let far_end_power =
+            far_end_frame.iter().map(|&x| x * x).sum::<T>() / float(frame_size as f64);
+        if far_end_power > float(self.config.far_end_threshold) && !double_talk {
+            for ((noise, error_psd), e) in self
+                .noise_psd
+                .iter_mut()
+                .zip(self.error_psd.iter_mut())
+                .zip(error_spectrum)
+            {
+                let (a, b): (T, T) = if !self.initialized {
+                    (T::zero(), T::zero())
+                } else if *error_psd < *noise {
+                    (float(ERROR_SMOOTHING), float(NOISE_FALL_SMOOTHING))
+                } else {
+                    (float(ERROR_SMOOTHING), float(NOISE_RISE_SMOOTHING))
+                };
+                *error_psd = a * *error_psd + (T::one() - a) * e.norm_sqr();
+                *noise = b * *noise + (T::one() - b) * *error_psd;
+            }
+            self.initialized = true;
+        }
+
+        // Only the last `frame_size` samples of the inverse transform are output, so the
+        // injected noise power is scaled up to compensate for the discarded part. The
+        // uniform samples have a power of 2/3 per complex bin, normalized here as well.
+        let scale: T = float(1.5 * fft_size as f32 / frame_size as f32);
+        let level: T = float(self.config.level);
+        // The DC and Nyquist bins are left alone, as they have to stay real.
+        let bins = 1..fft_size / 2;
+        let state = &mut self.state;
+        for (out, &noise) in output_spectrum[bins.clone()]
+            .iter_mut()
+            .zip(&self.noise_psd[bins])
+        {
+            let deficit = level * noise - out.norm_sqr();
+            if deficit > T::zero() {
+                let magnitude = (deficit * scale).sqrt();
+                let noise = Complex::new(float(next_uniform(state)), float(next_uniform(state)));
+                *out += noise * magnitude;
+            }
+        }
+    }
+}
+
+/// Returns the xorshift state for `seed`. Xorshift gets stuck at zero, so that one seed is
+/// remapped.
+fn initial_state(seed: u32) -> u32 {
+    if seed == 0 {
+        1
+    } else {
+        seed
+    }
+}
+
+/// Advances the xorshift `state` and returns a uniformly distributed sample in `[-1, 1)`.
+fn next_uniform(state: &mut u32) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    (*state as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{energy, noise};
+    use crate::{FdafAec, ResidualEchoSuppressorConfig};
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Far-end noise with a strongly over-suppressed echo on top of a quiet background.
+    fn run(comfort_noise: Option<ComfortNoiseConfig>) -> (Vec<f32>, Vec<f32>) {
+        let frames = 120;
+        let far_end = noise(FRAME_SIZE * frames, 13);
+        let background: Vec<f32> = noise(FRAME_SIZE * frames, 17)
+            .iter()
+            .map(|x| 0.05 * x)
+            .collect();
+        let mut mic = background.clone();
+        for i in 15..mic.len() {
+            mic[i] += 0.5 * far_end[i - 15];
+        }
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_residual_echo_suppressor(ResidualEchoSuppressorConfig {
+            over_suppression: 1000.0,
+            gain_floor: 0.001,
+            smoothing_factor: 0.6,
+        })
+        .unwrap();
+        if let Some(config) = comfort_noise {
+            aec.enable_comfort_noise(config).unwrap();
+        }
+
+        let mut output = Vec::with_capacity(mic.len());
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let mut error = [0.0; FRAME_SIZE];
+            aec.process(
+                &mut error,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            output.extend_from_slice(&error);
+        }
+        (output, background)
+    }
+
+    #[test]
+    fn fills_suppressed_bins_with_background_level() {
+        let (suppressed, background) = run(None);
+        let (filled, _) = run(Some(ComfortNoiseConfig::default()));
+
+        let tail = FRAME_SIZE * 80..FRAME_SIZE * 120;
+        let background_energy = energy(&background[tail.clone()]);
+        let suppressed_ratio = energy(&suppressed[tail.clone()]) / background_energy;
+        let filled_ratio = energy(&filled[tail]) / background_energy;
+        assert!(suppressed_ratio < 0.25, "suppressed {suppressed_ratio}");
+        assert!(
+            (0.5..2.0).contains(&filled_ratio),
+            "comfort noise at {filled_ratio} of background"
+        );
+    }
+
+    #[test]
+    fn seed_makes_output_reproducible() {
+        let config = ComfortNoiseConfig::default();
+        let (first, _) = run(Some(config));
+        let (second, _) = run(Some(config));
+        let (other_seed, _) = run(Some(ComfortNoiseConfig { seed: 7, ..config }));
+        assert_eq!(first, second);
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = ComfortNoiseConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            ComfortNoiseConfig {
+                level: f32::INFINITY,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidNoiseLevel(f32::INFINITY))
+        );
+        assert!(matches!(
+            ComfortNoiseConfig {
+                far_end_threshold: f32::NAN,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidFarEndThreshold(_))
+        ));
+
+        // A rejected config leaves the canceller passing the microphone signal through.
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert!(aec
+            .enable_comfort_noise(ComfortNoiseConfig {
+                level: -1.0,
+                ..config
+            })
+            .is_err());
+        let frame = [0.1; FRAME_SIZE];
+        let mut error = [0.0; FRAME_SIZE];
+        aec.process(&mut error, &[0.0; FRAME_SIZE], &frame);
+        assert_eq!(error, frame);
+    }
+}
@@ -0,0 +1,464 @@
+use core::fmt;
+
+#[cfg(feature = "rustfft")]
+use crate::DynFdafAec;
+use crate::{FdafAec, FftBackend, Float};
+#[cfg(feature = "alloc")]
+use crate::{MultiMicFdafAec, MultichannelFdafAec, PartitionedFdafAec};
+
+/// Error returned for a parameter outside its documented range.
+///
+/// It is returned by the `try_build` methods of [`FdafAecConfig`], the parameter setters,
+/// the `enable_*` methods of the cancellers and the validated constructors, e.g. of the
+/// double-talk detectors. A rejected call leaves the canceller unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The FFT size is not a power of two of at least 2.
+    InvalidFftSize(usize),
+    /// The block size of a partitioned canceller is not a power of two.
+    InvalidBlockSize(usize),
+    /// A partitioned canceller needs at least one partition.
+    ZeroPartitions,
+    /// A multichannel canceller needs at least one far-end channel.
+    ZeroChannels,
+    /// A multi-microphone canceller needs at least one microphone.
+    ZeroMicrophones,
+    /// The step size is not a finite value in `[0, 2)`.
+    InvalidStepSize(f32),
+    /// The PSD smoothing factor is not a finite value in `[0, 1)`.
+    InvalidSmoothingFactor(f32),
+    /// The regularization factor is not a finite, non-negative value, or is zero where a
+    /// positive one is required.
+    InvalidRegularizationFactor(f32),
+    /// The leak is not a finite value in `[0, 1)`.
+    InvalidLeak(f32),
+    /// The over-suppression factor of the residual echo suppressor is not a finite,
+    /// non-negative value.
+    InvalidOverSuppression(f32),
+    /// The gain floor of the residual echo suppressor is not in `[0, 1]`.
+    InvalidGainFloor(f32),
+    /// The level of the comfort noise is not a finite, non-negative value.
+    InvalidNoiseLevel(f32),
+    /// The far-end activity threshold of the comfort noise is not a finite, non-negative
+    /// value.
+    InvalidFarEndThreshold(f32),
+    /// The window of the delay estimator is empty or shorter than the canceller's frame
+    /// size.
+    InvalidDelayWindow(usize),
+    /// The delay range of the delay estimator is too large to be analysed on this target.
+    InvalidMaxDelay(usize),
+    /// The minimum confidence of the delay estimator is not in `[0, 1]`.
+    InvalidConfidence(f32),
+    /// The delay estimator needs at least one estimate to confirm a new delay.
+    ZeroHysteresis,
+    /// A time constant, in frames, is not finite or below its documented minimum.
+    InvalidTimeConstant(f32),
+    /// The transition factor of the Kalman filter is not in `(0, 1]`.
+    InvalidTransitionFactor(f32),
+    /// The initial state uncertainty of the Kalman filter is not a finite, positive value.
+    InvalidUncertainty(f32),
+    /// The process noise of the Kalman filter is not a finite, non-negative value.
+    InvalidProcessNoise(f32),
+    /// A power ratio of the divergence monitor or the foreground filter is outside its
+    /// documented range.
+    InvalidPowerRatio(f32),
+    /// The divergence monitor or the foreground filter needs at least one hold frame.
+    ZeroHoldFrames,
+    /// The step size factor of a divergence recovery is not in `(0, 1]`.
+    InvalidShrinkFactor(f32),
+    /// The headroom of the delay compensation exceeds its largest delay, or the headroom of
+    /// the drift compensation is too small for its interpolator or does not leave room for
+    /// the echo path within the frame size.
+    InvalidHeadroom(usize),
+    /// The drift compensation needs at least one frame to settle before it locks.
+    ZeroSettleFrames,
+    /// The largest tracked clock skew is not a finite, non-negative value.
+    InvalidMaxSkew(f32),
+    /// The proportionality of the proportionate update is not in `[-1, 1]`.
+    InvalidProportionality(f32),
+    /// The strength of the multichannel decorrelation is not in `[0, 1]`.
+    InvalidDecorrelationStrength(f32),
+    /// The threshold of a double-talk detector is not a finite, positive value.
+    InvalidThreshold(f32),
+    /// The step size factor a double-talk detector applies is not in `[0, 1]`.
+    InvalidStepScale(f32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFftSize(size) => {
+                write!(f, "FFT size {size} is not a power of two of at least 2")
+            }
+            Self::InvalidBlockSize(size) => write!(f, "block size {size} is not a power of two"),
+            Self::ZeroPartitions => f.write_str("partition count must be at least one"),
+            Self::ZeroChannels => f.write_str("channel count must be at least one"),
+            Self::ZeroMicrophones => f.write_str("microphone count must be at least one"),
+            Self::InvalidStepSize(value) => write!(f, "step size {value} is not in [0, 2)"),
+            Self::InvalidSmoothingFactor(value) => {
+                write!(f, "smoothing factor {value} is not in [0, 1)")
+            }
+            Self::InvalidRegularizationFactor(value) => {
+                write!(f, "regularization factor {value} is out of range")
+            }
+            Self::InvalidLeak(value) => write!(f, "leak {value} is not in [0, 1)"),
+            Self::InvalidOverSuppression(value) => {
+                write!(f, "over-suppression {value} is not a non-negative value")
+            }
+            Self::InvalidGainFloor(value) => write!(f, "gain floor {value} is not in [0, 1]"),
+            Self::InvalidNoiseLevel(value) => {
+                write!(f, "comfort noise level {value} is not a non-negative value")
+            }
+            Self::InvalidFarEndThreshold(value) => {
+                write!(f, "far-end threshold {value} is not a non-negative value")
+            }
+            Self::InvalidDelayWindow(window) => {
+                write!(f, "delay window {window} is shorter than the frame size")
+            }
+            Self::InvalidMaxDelay(delay) => write!(f, "maximum delay {delay} is too large"),
+            Self::InvalidConfidence(value) => {
+                write!(f, "minimum confidence {value} is not in [0, 1]")
+            }
+            Self::ZeroHysteresis => f.write_str("hysteresis must be at least one estimate"),
+            Self::InvalidTimeConstant(value) => {
+                write!(f, "time constant {value} is out of range")
+            }
+            Self::InvalidTransitionFactor(value) => {
+                write!(f, "transition factor {value} is not in (0, 1]")
+            }
+            Self::InvalidUncertainty(value) => {
+                write!(f, "initial uncertainty {value} is not positive")
+            }
+            Self::InvalidProcessNoise(value) => {
+                write!(f, "process noise {value} is not a non-negative value")
+            }
+            Self::InvalidPowerRatio(value) => write!(f, "power ratio {value} is out of range"),
+            Self::ZeroHoldFrames => f.write_str("hold frame count must be at least one"),
+            Self::InvalidShrinkFactor(value) => {
+                write!(f, "step size factor {value} is not in (0, 1]")
+            }
+            Self::InvalidHeadroom(headroom) => write!(f, "headroom {headroom} is out of range"),
+            Self::ZeroSettleFrames => f.write_str("settle frame count must be at least one"),
+            Self::InvalidMaxSkew(value) => {
+                write!(f, "maximum skew {value} ppm is not a non-negative value")
+            }
+            Self::InvalidProportionality(value) => {
+                write!(f, "proportionality {value} is not in [-1, 1]")
+            }
+            Self::InvalidDecorrelationStrength(value) => {
+                write!(f, "decorrelation strength {value} is not in [0, 1]")
+            }
+            Self::InvalidThreshold(value) => {
+                write!(f, "detection threshold {value} is not positive")
+            }
+            Self::InvalidStepScale(value) => {
+                write!(f, "double-talk step scale {value} is not in [0, 1]")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+/// Builder for the adaptive filter parameters shared by [`FdafAec`], [`DynFdafAec`],
+/// [`PartitionedFdafAec`], [`MultichannelFdafAec`] and [`MultiMicFdafAec`].
+///
+/// Unlike the panicking constructors, [`try_build`](Self::try_build) reports every invalid
+/// parameter as a [`ConfigError`], so a canceller can be created from untrusted settings
+/// without risking a panic.
+///
+/// ```
+/// use fdaf_aec::{ConfigError, FdafAecConfig};
+///
+/// let aec = FdafAecConfig::new().step_size(0.3).try_build::<512>();
+/// assert!(aec.is_ok());
+///
+/// let err = FdafAecConfig::new().leak(1.0).try_build::<512>().err();
+/// assert_eq!(err, Some(ConfigError::InvalidLeak(1.0)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FdafAecConfig {
+    pub(crate) step_size: f32,
+    pub(crate) smoothing_factor: f32,
+    pub(crate) regularization_factor: f32,
+    pub(crate) leak: f32,
+}
+
+impl Default for FdafAecConfig {
+    fn default() -> Self {
+        Self {
+            step_size: 0.5,
+            smoothing_factor: 0.9,
+            regularization_factor: 10e-4,
+            leak: 10e-4,
+        }
+    }
+}
+
+impl FdafAecConfig {
+    /// Creates a configuration with the default parameters used throughout the examples.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the learning rate (mu) of the adaptive filter. Must be in `[0, 2)`.
+    pub fn step_size(mut self, step_size: f32) -> Self {
+        self.step_size = step_size;
+        self
+    }
+
+    /// Sets the smoothing factor of the far-end PSD estimate. Must be in `[0, 1)`.
+    pub fn smoothing_factor(mut self, smoothing_factor: f32) -> Self {
+        self.smoothing_factor = smoothing_factor;
+        self
+    }
+
+    /// Sets the regularization added to the PSD before normalizing the gradient. Must be
+    /// finite and non-negative.
+    pub fn regularization_factor(mut self, regularization_factor: f32) -> Self {
+        self.regularization_factor = regularization_factor;
+        self
+    }
+
+    /// Sets the leak applied to the weights on every update. Must be in `[0, 1)`.
+    pub fn leak(mut self, leak: f32) -> Self {
+        self.leak = leak;
+        self
+    }
+
+    /// Checks the parameters that do not depend on the filter size.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_step_size(self.step_size)?;
+        check_smoothing_factor(self.smoothing_factor)?;
+        check_regularization_factor(self.regularization_factor)?;
+        check_leak(self.leak)?;
+        Ok(())
+    }
+
+    /// Validates the configuration and creates an [`FdafAec`] with an FFT of `FFT_SIZE`.
+    pub fn try_build<const FFT_SIZE: usize>(self) -> Result<FdafAec<FFT_SIZE>, ConfigError> {
+        self.try_build_as()
+    }
+
+    /// Like [`try_build`](Self::try_build), but creates a canceller that computes in the
+    /// sample type `T`, e.g. `f64`.
+    pub fn try_build_as<const FFT_SIZE: usize, T: Float, F: FftBackend<T>>(
+        self,
+    ) -> Result<FdafAec<FFT_SIZE, T, F>, ConfigError> {
+        if FFT_SIZE < 2 || !FFT_SIZE.is_power_of_two() {
+            return Err(ConfigError::InvalidFftSize(FFT_SIZE));
+        }
+        self.validate()?;
+        Ok(FdafAec::from_config(self))
+    }
+
+    /// Validates the configuration and creates a [`DynFdafAec`] with an FFT of `fft_size`,
+    /// which may be chosen at runtime.
+    #[cfg(feature = "rustfft")]
+    pub fn try_build_dyn(self, fft_size: usize) -> Result<DynFdafAec, ConfigError> {
+        self.try_build_dyn_as(fft_size)
+    }
+
+    /// Like [`try_build_dyn`](Self::try_build_dyn), but creates a canceller that computes
+    /// in the sample type `T`, e.g. `f64`.
+    #[cfg(feature = "rustfft")]
+    pub fn try_build_dyn_as<T: Float>(self, fft_size: usize) -> Result<DynFdafAec<T>, ConfigError> {
+        if fft_size < 2 || !fft_size.is_power_of_two() {
+            return Err(ConfigError::InvalidFftSize(fft_size));
+        }
+        self.validate()?;
+        Ok(DynFdafAec::from_config(fft_size, self))
+    }
+
+    /// Validates the configuration and creates a [`PartitionedFdafAec`] with `PARTITIONS`
+    /// partitions of `BLOCK` taps.
+    #[cfg(feature = "rustfft")]
+    pub fn try_build_partitioned<const BLOCK: usize, const PARTITIONS: usize>(
+        self,
+    ) -> Result<PartitionedFdafAec<BLOCK, PARTITIONS>, ConfigError> {
+        self.try_build_partitioned_as()
+    }
+
+    /// Like [`try_build_partitioned`](Self::try_build_partitioned), but creates a canceller
+    /// that computes in the sample type `T` on the FFT backend `F`.
+    #[cfg(feature = "alloc")]
+    pub fn try_build_partitioned_as<
+        const BLOCK: usize,
+        const PARTITIONS: usize,
+        T: Float,
+        F: FftBackend<T>,
+    >(
+        self,
+    ) -> Result<PartitionedFdafAec<BLOCK, PARTITIONS, T, F>, ConfigError> {
+        if BLOCK == 0 || !BLOCK.is_power_of_two() {
+            return Err(ConfigError::InvalidBlockSize(BLOCK));
+        }
+        if PARTITIONS == 0 {
+            return Err(ConfigError::ZeroPartitions);
+        }
+        self.validate()?;
+        Ok(PartitionedFdafAec::from_config(self))
+    }
+
+    /// Validates the configuration and creates a [`MultichannelFdafAec`] with an FFT of
+    /// `FFT_SIZE` for `CHANNELS` far-end channels.
+    #[cfg(feature = "alloc")]
+    pub fn try_build_multichannel<const FFT_SIZE: usize, const CHANNELS: usize>(
+        self,
+    ) -> Result<MultichannelFdafAec<FFT_SIZE, CHANNELS>, ConfigError> {
+        self.try_build_multichannel_as()
+    }
+
+    /// Like [`try_build_multichannel`](Self::try_build_multichannel), but creates a
+    /// canceller that computes in the sample type `T` on the FFT backend `F`.
+    #[cfg(feature = "alloc")]
+    pub fn try_build_multichannel_as<
+        const FFT_SIZE: usize,
+        const CHANNELS: usize,
+        T: Float,
+        F: FftBackend<T>,
+    >(
+        self,
+    ) -> Result<MultichannelFdafAec<FFT_SIZE, CHANNELS, T, F>, ConfigError> {
+        if FFT_SIZE < 2 || !FFT_SIZE.is_power_of_two() {
+            return Err(ConfigError::InvalidFftSize(FFT_SIZE));
+        }
+        if CHANNELS == 0 {
+            return Err(ConfigError::ZeroChannels);
+        }
+        self.validate()?;
+        Ok(MultichannelFdafAec::from_config(self))
+    }
+
+    /// Validates the configuration and creates a [`MultiMicFdafAec`] with an FFT of
+    /// `FFT_SIZE` for `MICS` microphones.
+    #[cfg(feature = "alloc")]
+    pub fn try_build_multi_mic<const FFT_SIZE: usize, const MICS: usize>(
+        self,
+    ) -> Result<MultiMicFdafAec<FFT_SIZE, MICS>, ConfigError> {
+        self.try_build_multi_mic_as()
+    }
+
+    /// Like [`try_build_multi_mic`](Self::try_build_multi_mic), but creates a canceller
+    /// that computes in the sample type `T` on the FFT backend `F`.
+    #[cfg(feature = "alloc")]
+    pub fn try_build_multi_mic_as<
+        const FFT_SIZE: usize,
+        const MICS: usize,
+        T: Float,
+        F: FftBackend<T>,
+    >(
+        self,
+    ) -> Result<MultiMicFdafAec<FFT_SIZE, MICS, T, F>, ConfigError> {
+        if FFT_SIZE < 2 || !FFT_SIZE.is_power_of_two() {
+            return Err(ConfigError::InvalidFftSize(FFT_SIZE));
+        }
+        if MICS == 0 {
+            return Err(ConfigError::ZeroMicrophones);
+        }
+        self.validate()?;
+        Ok(MultiMicFdafAec::from_config(self))
+    }
+}
+
+pub(crate) fn check_step_size(step_size: f32) -> Result<f32, ConfigError> {
+    if (0.0..2.0).contains(&step_size) {
+        Ok(step_size)
+    } else {
+        Err(ConfigError::InvalidStepSize(step_size))
+    }
+}
+
+pub(crate) fn check_smoothing_factor(smoothing_factor: f32) -> Result<f32, ConfigError> {
+    if (0.0..1.0).contains(&smoothing_factor) {
+        Ok(smoothing_factor)
+    } else {
+        Err(ConfigError::InvalidSmoothingFactor(smoothing_factor))
+    }
+}
+
+pub(crate) fn check_regularization_factor(regularization_factor: f32) -> Result<f32, ConfigError> {
+    if regularization_factor.is_finite() && regularization_factor >= 0.0 {
+        Ok(regularization_factor)
+    } else {
+        Err(ConfigError::InvalidRegularizationFactor(
+            regularization_factor,
+        ))
+    }
+}
+
+pub(crate) fn check_leak(leak: f32) -> Result<f32, ConfigError> {
+    if (0.0..1.0).contains(&leak) {
+        Ok(leak)
+    } else {
+        Err(ConfigError::InvalidLeak(leak))
+    }
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_each_invalid_parameter() {
+        let config = FdafAecConfig::new();
+        let cases = [
+            (config.step_size(-0.1), ConfigError::InvalidStepSize(-0.1)),
+            (
+                config.smoothing_factor(1.0),
+                ConfigError::InvalidSmoothingFactor(1.0),
+            ),
+            (
+                config.regularization_factor(-1.0),
+                ConfigError::InvalidRegularizationFactor(-1.0),
+            ),
+            (config.leak(1.5), ConfigError::InvalidLeak(1.5)),
+        ];
+        for (config, expected) in cases {
+            assert_eq!(config.try_build::<512>().err(), Some(expected));
+        }
+        // Zero is allowed, as it was before the constructors validated their parameters.
+        assert!(config.regularization_factor(0.0).validate().is_ok());
+        assert!(matches!(
+            config.step_size(f32::NAN).validate(),
+            Err(ConfigError::InvalidStepSize(_))
+        ));
+        assert_eq!(
+            config.try_build::<511>().err(),
+            Some(ConfigError::InvalidFftSize(511))
+        );
+        assert_eq!(
+            config.try_build_partitioned::<100, 4>().err(),
+            Some(ConfigError::InvalidBlockSize(100))
+        );
+        assert_eq!(
+            config.try_build_partitioned::<128, 0>().err(),
+            Some(ConfigError::ZeroPartitions)
+        );
+        assert_eq!(
+            config.try_build_multichannel::<512, 0>().err(),
+            Some(ConfigError::ZeroChannels)
+        );
+        assert_eq!(
+            config.try_build_multi_mic::<512, 0>().err(),
+            Some(ConfigError::ZeroMicrophones)
+        );
+    }
+
+    #[test]
+    fn builds_same_canceller_as_new() {
+        let mut built = FdafAecConfig::new().try_build::<512>().unwrap();
+        let mut constructed = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+
+        let far_end: [f32; 256] = core::array::from_fn(|i| (i as f32 * 0.1).sin());
+        let mic: [f32; 256] = core::array::from_fn(|i| (i as f32 * 0.1 - 0.3).sin() * 0.5);
+        let mut a = [0.0; 256];
+        let mut b = [0.0; 256];
+        for _ in 0..4 {
+            built.process(&mut a, &far_end, &mic);
+            constructed.process(&mut b, &far_end, &mic);
+            assert_eq!(a, b);
+        }
+    }
+}
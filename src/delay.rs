@@ -0,0 +1,437 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::check_smoothing_factor;
+use crate::{float, to_f32, ConfigError, FftBackend, Float, RustFft};
+
+/// Configuration of the bulk delay estimator.
+///
+/// See [`FdafAec::enable_delay_compensation`](crate::FdafAec::enable_delay_compensation).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DelayEstimatorConfig {
+    /// The largest delay, in samples, between the far-end reference and its echo in the
+    /// microphone signal that can be detected.
+    pub max_delay: usize,
+    /// The number of microphone samples correlated per estimate. Must be at least the frame
+    /// size; longer windows give more reliable estimates but react more slowly.
+    pub window: usize,
+    /// Smoothing factor of the cross-spectrum across estimates, in `[0, 1)`.
+    pub smoothing_factor: f32,
+    /// The minimum confidence an estimate needs before the delay line follows it, in
+    /// `[0, 1]`.
+    pub min_confidence: f32,
+    /// The number of consecutive estimates that must agree on a new delay before the delay
+    /// line jumps to it, at least one.
+    pub hysteresis: usize,
+    /// The number of samples by which the applied delay is kept below the estimate, so
+    /// the start of the echo path stays inside the adaptive filter. At most `max_delay`.
+    pub headroom: usize,
+}
+
+impl Default for DelayEstimatorConfig {
+    fn default() -> Self {
+        Self {
+            max_delay: 3200,
+            window: 1024,
+            smoothing_factor: 0.7,
+            min_confidence: 0.1,
+            hysteresis: 3,
+            headroom: 32,
+        }
+    }
+}
+
+impl DelayEstimatorConfig {
+    /// Checks that every parameter is within its documented range. The window is only
+    /// checked against the frame size once the config is passed to a canceller.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.window == 0 {
+            return Err(ConfigError::InvalidDelayWindow(self.window));
+        }
+        let fft_size = self
+            .window
+            .checked_add(self.max_delay)
+            .and_then(usize::checked_next_power_of_two);
+        if fft_size.is_none() {
+            return Err(ConfigError::InvalidMaxDelay(self.max_delay));
+        }
+        check_smoothing_factor(self.smoothing_factor)?;
+        if !(0.0..=1.0).contains(&self.min_confidence) {
+            return Err(ConfigError::InvalidConfidence(self.min_confidence));
+        }
+        if self.hysteresis == 0 {
+            return Err(ConfigError::ZeroHysteresis);
+        }
+        if self.headroom > self.max_delay {
+            return Err(ConfigError::InvalidHeadroom(self.headroom));
+        }
+        Ok(())
+    }
+}
+
+/// The current state of a [`DelayEstimator`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DelayEstimate {
+    /// The delay, in samples, of the echo relative to the far-end reference.
+    pub delay: usize,
+    /// The height of the normalized GCC-PHAT peak of the latest estimate, in `[0, 1]`.
+    pub confidence: f32,
+}
+
+/// Estimates the bulk delay between the far-end reference and the microphone signal with
+/// the generalized cross-correlation with phase transform (GCC-PHAT), and delays the far-end
+/// reference accordingly.
+///
+/// Device latency otherwise wastes filter taps on pure delay before any echo arrives.
+/// Every `window` samples the cross-spectrum of the latest microphone window and the far-end
+/// history is accumulated, whitened and transformed back; its peak gives the delay. The
+/// delay line follows a new peak once it has been confirmed, so echo path jumps are tracked.
+///
+/// The transforms span the window plus the maximum delay, which is much longer than a
+/// frame. To avoid a periodic spike in processing time, the three transforms of an estimate
+/// run on three consecutive frames, so an estimate takes effect two frames after its window
+/// is complete.
+#[derive(Clone)]
+pub struct DelayEstimator<T: Float = f32> {
+    config: DelayEstimatorConfig,
+    fft: RustFft<T>,
+    far_end_history: Vec<T>,
+    mic_history: Vec<T>,
+    pending: usize,
+    stage: Stage,
+    /// The zero-padded far-end history of the estimate in progress, and then its
+    /// correlation.
+    far_end_block: Vec<T>,
+    /// The zero-padded microphone window of the estimate in progress.
+    mic_block: Vec<T>,
+    far_end_spectrum: Vec<Complex<T>>,
+    mic_spectrum: Vec<Complex<T>>,
+    cross_spectrum: Vec<Complex<T>>,
+    aligned: Vec<T>,
+    candidate: usize,
+    candidate_count: usize,
+    estimate: DelayEstimate,
+}
+
+/// The next step of the estimate in progress.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Idle,
+    TransformMic,
+    Correlate,
+}
+
+impl<T: Float> DelayEstimator<T> {
+    /// Creates a new `DelayEstimator` that starts without any delay.
+    ///
+    /// A parameter outside its documented range is rejected, see
+    /// [`DelayEstimatorConfig::validate`].
+    pub fn new(config: DelayEstimatorConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let fft_size = (config.window + config.max_delay)
+            .next_power_of_two()
+            .max(2);
+        let bins = fft_size / 2 + 1;
+
+        Ok(Self {
+            config,
+            fft: RustFft::new(fft_size),
+            far_end_history: vec![T::zero(); config.window + config.max_delay],
+            mic_history: vec![T::zero(); config.window],
+            pending: 0,
+            stage: Stage::Idle,
+            far_end_block: vec![T::zero(); fft_size],
+            mic_block: vec![T::zero(); fft_size],
+            far_end_spectrum: vec![Complex::zero(); bins],
+            mic_spectrum: vec![Complex::zero(); bins],
+            cross_spectrum: vec![Complex::zero(); bins],
+            aligned: vec![T::zero(); config.window],
+            candidate: 0,
+            candidate_count: 0,
+            estimate: DelayEstimate::default(),
+        })
+    }
+
+    /// Forgets the signal history and the delay estimate, as if the estimator had just been
+    /// created. Does not allocate.
+    pub fn reset(&mut self) {
+        self.far_end_history.fill(T::zero());
+        self.mic_history.fill(T::zero());
+        self.aligned.fill(T::zero());
+        self.pending = 0;
+        self.stage = Stage::Idle;
+        self.candidate = 0;
+        self.candidate_count = 0;
+        self.estimate = DelayEstimate::default();
+    }
+
+    /// Returns the delay currently applied by the delay line, before headroom, and the
+    /// confidence of the latest estimate.
+    pub fn estimate(&self) -> DelayEstimate {
+        self.estimate
+    }
+
+    /// Feeds one frame of far-end and microphone samples and returns the far-end frame
+    /// delayed by the current estimate (minus the configured headroom).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frames differ in length or are longer than the configured window.
+    /// [`FdafAec::enable_delay_compensation`](crate::FdafAec::enable_delay_compensation)
+    /// rejects a window shorter than the frame, so the cancellers never panic here.
+    pub fn align(&mut self, far_end_frame: &[T], mic_frame: &[T]) -> &[T] {
+        let frame_size = far_end_frame.len();
+        assert!(
+            frame_size <= self.config.window && mic_frame.len() == frame_size,
+            "frames must have equal lengths no longer than the window."
+        );
+
+        push(&mut self.far_end_history, far_end_frame);
+        push(&mut self.mic_history, mic_frame);
+        self.pending += frame_size;
+        self.advance();
+        if self.pending >= self.config.window {
+            self.pending = 0;
+            // A window of less than three frames completes before its estimate.
+            while self.stage != Stage::Idle {
+                self.advance();
+            }
+            load(&mut self.far_end_block, &self.far_end_history);
+            load(&mut self.mic_block, &self.mic_history);
+            self.fft
+                .forward(&self.far_end_block, &mut self.far_end_spectrum);
+            self.stage = Stage::TransformMic;
+        }
+
+        let delay = self.estimate.delay.saturating_sub(self.config.headroom);
+        let end = self.far_end_history.len() - delay;
+        let aligned = &mut self.aligned[..frame_size];
+        aligned.copy_from_slice(&self.far_end_history[end - frame_size..end]);
+        aligned
+    }
+
+    /// Runs the next step of the estimate in progress, if any.
+    fn advance(&mut self) {
+        match self.stage {
+            Stage::Idle => {}
+            Stage::TransformMic => {
+                self.fft.forward(&self.mic_block, &mut self.mic_spectrum);
+                self.stage = Stage::Correlate;
+            }
+            Stage::Correlate => {
+                self.update_estimate();
+                self.stage = Stage::Idle;
+            }
+        }
+    }
+
+    fn update_estimate(&mut self) {
+        let fft_size = self.far_end_block.len();
+        let max_delay = self.config.max_delay;
+
+        // Smoothed cross-spectrum, then the phase transform keeps only its phase. The
+        // far-end spectrum buffer is reused for the whitened result, and the far-end block
+        // for the correlation.
+        let a: T = float(self.config.smoothing_factor);
+        for ((s, x), d) in self
+            .cross_spectrum
+            .iter_mut()
+            .zip(self.far_end_spectrum.iter_mut())
+            .zip(&self.mic_spectrum)
+        {
+            *s = *s * a + d.conj() * *x * (T::one() - a);
+            let magnitude = s.norm();
+            *x = if magnitude > T::zero() {
+                *s / magnitude
+            } else {
+                Complex::zero()
+            };
+        }
+        self.fft
+            .inverse(&self.far_end_spectrum, &mut self.far_end_block);
+
+        // Lag `l` of the correlation lines the microphone window up with the far-end history
+        // `max_delay - l` samples before its end, i.e. an echo delay of `max_delay - l`.
+        let (peak_lag, peak) = self.far_end_block[..=max_delay].iter().enumerate().fold(
+            (0, T::min_value()),
+            |best, (lag, &c)| {
+                if c > best.1 {
+                    (lag, c)
+                } else {
+                    best
+                }
+            },
+        );
+        let delay = max_delay - peak_lag;
+        let confidence = to_f32(peak / float(fft_size as f64)).clamp(0.0, 1.0);
+
+        self.estimate.confidence = confidence;
+        if confidence < self.config.min_confidence {
+            self.candidate_count = 0;
+            return;
+        }
+        if delay.abs_diff(self.candidate) <= 1 {
+            self.candidate_count += 1;
+        } else {
+            self.candidate = delay;
+            self.candidate_count = 1;
+        }
+        if self.candidate_count >= self.config.hysteresis {
+            self.estimate.delay = self.candidate;
+        }
+    }
+}
+
+/// Appends `frame` to a history buffer, discarding the oldest samples.
+fn push<T: Float>(history: &mut [T], frame: &[T]) {
+    let len = history.len();
+    history.copy_within(frame.len().., 0);
+    history[len - frame.len()..].copy_from_slice(frame);
+}
+
+/// Loads a signal into a zero-padded FFT input.
+fn load<T: Float>(block: &mut [T], signal: &[T]) {
+    let (head, tail) = block.split_at_mut(signal.len());
+    head.copy_from_slice(signal);
+    tail.fill(T::zero());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::{ConfigError, FdafAec};
+
+    const FRAME_SIZE: usize = 256;
+
+    /// Builds a microphone signal whose echo delay switches at sample `switch_at`.
+    fn echo(far_end: &[f32], delays: (usize, usize), switch_at: usize) -> Vec<f32> {
+        let near_end = noise(far_end.len(), 99);
+        (0..far_end.len())
+            .map(|i| {
+                let delay = if i < switch_at { delays.0 } else { delays.1 };
+                let echo = if i >= delay { far_end[i - delay] } else { 0.0 };
+                0.4 * echo + 0.05 * near_end[i]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimates_delay_and_follows_jumps() {
+        let far_end = noise(FRAME_SIZE * 400, 4);
+        let mic = echo(&far_end, (1200, 2500), FRAME_SIZE * 200);
+        // The default window spreads every estimate over three of its four frames; a
+        // window of one frame completes every estimate on the next frame.
+        for window in [DelayEstimatorConfig::default().window, FRAME_SIZE] {
+            let config = DelayEstimatorConfig {
+                window,
+                ..DelayEstimatorConfig::default()
+            };
+            let mut estimator = DelayEstimator::new(config).unwrap();
+
+            let mut estimates = Vec::new();
+            for (far, mic) in far_end
+                .chunks_exact(FRAME_SIZE)
+                .zip(mic.chunks_exact(FRAME_SIZE))
+            {
+                estimator.align(far, mic);
+                estimates.push(estimator.estimate());
+            }
+
+            let before = estimates[190];
+            let after = estimates[399];
+            assert!(before.delay.abs_diff(1200) <= 1, "{window}: {before:?}");
+            assert!(after.delay.abs_diff(2500) <= 1, "{window}: {after:?}");
+            assert!(before.confidence > 0.3 && after.confidence > 0.3);
+        }
+    }
+
+    #[test]
+    fn aligned_far_end_lets_short_filter_cancel_long_delay() {
+        const FFT_SIZE: usize = 2 * FRAME_SIZE;
+        let far_end = noise(FRAME_SIZE * 300, 8);
+        let mic = echo(&far_end, (1500, 1500), 0);
+
+        let run = |compensate: bool| {
+            let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+            if compensate {
+                aec.enable_delay_compensation(DelayEstimatorConfig::default())
+                    .unwrap();
+            }
+            let mut mic_energy = 0.0;
+            let mut error_energy = 0.0;
+            for (frame, (far, mic)) in far_end
+                .chunks_exact(FRAME_SIZE)
+                .zip(mic.chunks_exact(FRAME_SIZE))
+                .enumerate()
+            {
+                let mut error = [0.0; FRAME_SIZE];
+                aec.process(
+                    &mut error,
+                    far.first_chunk().unwrap(),
+                    mic.first_chunk().unwrap(),
+                );
+                if frame >= 250 {
+                    mic_energy += mic.iter().map(|x| x * x).sum::<f32>();
+                    error_energy += error.iter().map(|x| x * x).sum::<f32>();
+                }
+            }
+            (mic_energy / error_energy, aec.delay_estimate())
+        };
+
+        let (uncompensated, none) = run(false);
+        let (compensated, estimate) = run(true);
+        assert_eq!(none, None);
+        assert!(estimate.unwrap().delay.abs_diff(1500) <= 1);
+        assert!(uncompensated < 1.5, "uncompensated ERLE {uncompensated}");
+        assert!(compensated > 20.0, "compensated ERLE {compensated}");
+    }
+
+    #[test]
+    fn rejects_window_shorter_than_frame() {
+        let mut aec = FdafAec::<{ 2 * FRAME_SIZE }>::new(0.5, 0.9, 10e-4, 10e-4);
+        let config = DelayEstimatorConfig {
+            window: FRAME_SIZE - 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            aec.enable_delay_compensation(config),
+            Err(ConfigError::InvalidDelayWindow(FRAME_SIZE - 1))
+        );
+        assert!(aec.delay_estimate().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = DelayEstimatorConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            DelayEstimatorConfig {
+                headroom: config.max_delay + 1,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidHeadroom(config.max_delay + 1))
+        );
+        assert_eq!(
+            DelayEstimatorConfig {
+                max_delay: usize::MAX,
+                headroom: 0,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidMaxDelay(usize::MAX))
+        );
+        assert_eq!(
+            DelayEstimator::<f32>::new(DelayEstimatorConfig {
+                window: 0,
+                ..config
+            })
+            .err(),
+            Some(ConfigError::InvalidDelayWindow(0))
+        );
+    }
+}
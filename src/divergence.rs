@@ -0,0 +1,261 @@
+use crate::{float, ConfigError, Float};
+
+/// What the canceller does once it has detected that its filter diverged.
+///
+/// Every recovery clears the weights, since they no longer describe the echo path, and the
+/// frame on which the divergence is detected outputs the microphone signal unchanged. If the
+/// canceller's state holds NaN or infinite values, every piece of signal-dependent state is
+/// reset as by [`FdafAec::reset`](crate::FdafAec::reset).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DivergenceRecovery {
+    /// Only clear the weights, so the filter reconverges from zero.
+    ResetWeights,
+    /// Clear the weights and multiply the step size by `factor`, in `(0, 1]`, so the filter
+    /// reconverges more carefully. The step size stays reduced until it is set again.
+    ShrinkStepSize {
+        /// The factor applied to the step size on every divergence event.
+        factor: f32,
+    },
+    /// Clear the weights and pass the microphone signal through unprocessed for `frames`
+    /// frames while the filter reconverges in the background.
+    Passthrough {
+        /// The number of frames after the divergence event that output the microphone signal.
+        frames: u32,
+    },
+}
+
+/// Configuration of the divergence monitor.
+///
+/// A filter has diverged once its error signal contains NaN or infinite values, or once
+/// the error energy has exceeded `power_ratio` times the microphone energy for `hold_frames`
+/// consecutive frames: a working canceller only ever removes energy from the microphone
+/// signal.
+///
+/// See [`FdafAec::enable_divergence_monitor`](crate::FdafAec::enable_divergence_monitor).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DivergenceConfig {
+    /// The ratio of error to microphone energy above which a frame counts as diverged,
+    /// e.g. `2.0` for 3 dB. Must be finite and positive.
+    pub power_ratio: f32,
+    /// The number of consecutive diverged frames that trigger a recovery, at least one.
+    pub hold_frames: u32,
+    /// What to do once a divergence is detected.
+    pub recovery: DivergenceRecovery,
+}
+
+impl Default for DivergenceConfig {
+    fn default() -> Self {
+        Self {
+            power_ratio: 2.0,
+            hold_frames: 4,
+            recovery: DivergenceRecovery::ResetWeights,
+        }
+    }
+}
+
+impl DivergenceConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.power_ratio.is_finite() && self.power_ratio > 0.0) {
+            return Err(ConfigError::InvalidPowerRatio(self.power_ratio));
+        }
+        if self.hold_frames == 0 {
+            return Err(ConfigError::ZeroHoldFrames);
+        }
+        if let DivergenceRecovery::ShrinkStepSize { factor } = self.recovery {
+            if !(factor > 0.0 && factor <= 1.0) {
+                return Err(ConfigError::InvalidShrinkFactor(factor));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Watches the error signal of every frame for divergence.
+#[derive(Clone)]
+pub(crate) struct DivergenceMonitor {
+    config: DivergenceConfig,
+    diverged_frames: u32,
+    passthrough_frames: u32,
+    events: u64,
+}
+
+impl DivergenceMonitor {
+    pub(crate) fn new(config: DivergenceConfig) -> Self {
+        Self {
+            config,
+            diverged_frames: 0,
+            passthrough_frames: 0,
+            events: 0,
+        }
+    }
+
+    /// Forgets the diverged frames, any pending passthrough and the event counter.
+    pub(crate) fn reset(&mut self) {
+        self.diverged_frames = 0;
+        self.passthrough_frames = 0;
+        self.events = 0;
+    }
+
+    pub(crate) fn recovery(&self) -> DivergenceRecovery {
+        self.config.recovery
+    }
+
+    /// Returns the number of divergence events detected so far.
+    pub(crate) fn events(&self) -> u64 {
+        self.events
+    }
+
+    /// Checks the error signal of this frame and returns whether it completes a divergence
+    /// event, which the caller must recover from.
+    pub(crate) fn check<T: Float>(&mut self, error_signal: &[T], mic_frame: &[T]) -> bool {
+        let error_energy = energy(error_signal);
+        let diverged = if !error_energy.is_finite() {
+            true
+        } else if error_energy > float::<T>(self.config.power_ratio) * energy(mic_frame) {
+            self.diverged_frames += 1;
+            self.diverged_frames >= self.config.hold_frames
+        } else {
+            self.diverged_frames = 0;
+            false
+        };
+        if diverged {
+            self.diverged_frames = 0;
+            self.events += 1;
+            if let DivergenceRecovery::Passthrough { frames } = self.config.recovery {
+                self.passthrough_frames = frames;
+            }
+        }
+        diverged
+    }
+
+    /// Returns whether this frame belongs to a passthrough period and counts it down.
+    pub(crate) fn passthrough(&mut self) -> bool {
+        if self.passthrough_frames > 0 {
+            self.passthrough_frames -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn energy<T: Float>(signal: &[T]) -> T {
+    signal.iter().map(|&x| x * x).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{noise, run};
+    use crate::FdafAec;
+    use alloc::vec::Vec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn recovers_from_non_finite_values() {
+        let mut far_end = noise(FRAME_SIZE * 60, 81);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 15 { 0.5 * far_end[i - 15] } else { 0.0 })
+            .collect();
+        // A single corrupt far-end sample poisons the whole filter state.
+        far_end[FRAME_SIZE * 20 + 7] = f32::NAN;
+
+        let mut unmonitored = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut monitored = unmonitored.clone();
+        monitored
+            .enable_divergence_monitor(DivergenceConfig::default())
+            .unwrap();
+        let unmonitored_out = run(&mut unmonitored, &far_end, &mic);
+        let monitored_out = run(&mut monitored, &far_end, &mic);
+
+        assert!(unmonitored_out[FRAME_SIZE * 59..]
+            .iter()
+            .all(|x| x.is_nan()));
+        assert!(monitored.divergence_events().unwrap() > 0);
+        let tail = FRAME_SIZE * 50..FRAME_SIZE * 60;
+        let residual: f32 = monitored_out[tail.clone()].iter().map(|x| x * x).sum();
+        let echo: f32 = mic[tail].iter().map(|x| x * x).sum();
+        assert!(residual < 1e-3 * echo, "{residual} >= {echo}");
+    }
+
+    #[test]
+    fn stale_weights_trigger_the_configured_recovery() {
+        let frames = 40;
+        let far_end = noise(FRAME_SIZE * frames, 83);
+        let near_end = noise(FRAME_SIZE * frames, 84);
+        // The echo path disappears after 20 frames, e.g. because the loudspeaker was muted,
+        // so the converged weights add an echo estimate that is not in the microphone signal.
+        let change = FRAME_SIZE * 20;
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                let echo = if (15..change).contains(&i) {
+                    0.5 * far_end[i - 15]
+                } else {
+                    0.0
+                };
+                echo + 0.01 * near_end[i]
+            })
+            .collect();
+
+        let config = DivergenceConfig {
+            recovery: DivergenceRecovery::Passthrough { frames: 3 },
+            ..Default::default()
+        };
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut shrinking = aec.clone();
+        aec.enable_divergence_monitor(config).unwrap();
+        let output = run(&mut aec, &far_end, &mic);
+        assert_eq!(aec.divergence_events(), Some(1));
+
+        // The event frame and the following three pass the microphone signal through. The
+        // first frame is skipped, where the zero weights leave the microphone signal as is.
+        let passthrough: Vec<usize> = (1..frames)
+            .filter(|frame| {
+                let range = frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE;
+                output[range.clone()] == mic[range]
+            })
+            .collect();
+        let event = change / FRAME_SIZE + config.hold_frames as usize - 1;
+        assert_eq!(passthrough, (event..event + 4).collect::<Vec<_>>());
+
+        shrinking
+            .enable_divergence_monitor(DivergenceConfig {
+                recovery: DivergenceRecovery::ShrinkStepSize { factor: 0.5 },
+                ..config
+            })
+            .unwrap();
+        run(&mut shrinking, &far_end, &mic);
+        assert_eq!(shrinking.divergence_events(), Some(1));
+        assert_eq!(shrinking.step_size(), 0.25);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = DivergenceConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            DivergenceConfig {
+                power_ratio: 0.0,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidPowerRatio(0.0))
+        );
+        let shrink = |factor| DivergenceConfig {
+            recovery: DivergenceRecovery::ShrinkStepSize { factor },
+            ..config
+        };
+        assert_eq!(shrink(1.0).validate(), Ok(()));
+        assert_eq!(
+            shrink(0.0).validate(),
+            Err(ConfigError::InvalidShrinkFactor(0.0))
+        );
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert!(aec.enable_divergence_monitor(shrink(2.0)).is_err());
+        assert_eq!(aec.divergence_events(), None);
+    }
+}
@@ -0,0 +1,500 @@
+#[cfg(feature = "rustfft")]
+use alloc::vec;
+#[cfg(feature = "rustfft")]
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+#[cfg(feature = "rustfft")]
+use num_traits::Zero;
+
+use crate::config::check_smoothing_factor;
+#[cfg(feature = "rustfft")]
+use crate::float;
+#[cfg(feature = "rustfft")]
+use crate::real_fft::{FftBackend, RustFft};
+use crate::{to_f32, ConfigError, Float};
+
+/// The signals a [`DoubleTalkDetector`] observes for one frame.
+#[derive(Clone, Copy, Debug)]
+pub struct DoubleTalkInput<'a, T: Float = f32> {
+    /// The far-end history used by the filter (`fft_size` samples, newest last).
+    pub far_end: &'a [T],
+    /// The half spectrum of `far_end` (`fft_size / 2 + 1` bins, DC to Nyquist).
+    pub far_end_spectrum: &'a [Complex<T>],
+    /// The microphone frame (`fft_size / 2` samples).
+    pub mic: &'a [T],
+    /// The error signal of the frame, computed with the weights before this frame's update.
+    pub error: &'a [T],
+}
+
+/// The per-frame result of a [`DoubleTalkDetector`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoubleTalkDecision {
+    /// Whether double talk was detected (including hangover frames).
+    pub double_talk: bool,
+    /// The raw detection statistic of this frame, for logging and tuning.
+    pub statistic: f32,
+    /// The factor applied to the step size for this frame's weight update.
+    pub step_scale: f32,
+}
+
+/// Decides per frame whether the near-end is talking over the far-end.
+///
+/// Detectors are passed to [`FdafAec::process_with_detector`](crate::FdafAec::process_with_detector),
+/// which scales the adaptation step size by the returned [`DoubleTalkDecision::step_scale`].
+/// `T` is the sample type of the canceller the detector is used with.
+pub trait DoubleTalkDetector<T: Float = f32> {
+    /// Inspects one frame and returns the decision for it.
+    fn detect(&mut self, input: &DoubleTalkInput<'_, T>) -> DoubleTalkDecision;
+
+    /// Returns the FFT size the detector was built for, if it only works with cancellers of
+    /// that size. Cancellers of any other size reject the detector with a
+    /// [`FrameSizeError`](crate::FrameSizeError).
+    fn fft_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Turns raw per-frame detections into decisions, holding double talk for a number of
+/// frames after the detector last fired.
+#[derive(Clone, Debug)]
+struct Hangover {
+    frames: usize,
+    remaining: usize,
+    step_scale: f32,
+}
+
+impl Hangover {
+    fn new(frames: usize) -> Self {
+        Self {
+            frames,
+            remaining: 0,
+            step_scale: 0.0,
+        }
+    }
+
+    fn set_step_scale(&mut self, step_scale: f32) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&step_scale) {
+            return Err(ConfigError::InvalidStepScale(step_scale));
+        }
+        self.step_scale = step_scale;
+        Ok(())
+    }
+
+    fn decide(&mut self, detected: bool, statistic: f32) -> DoubleTalkDecision {
+        if detected {
+            self.remaining = self.frames + 1;
+        }
+        let double_talk = self.remaining > 0;
+        self.remaining = self.remaining.saturating_sub(1);
+        DoubleTalkDecision {
+            double_talk,
+            statistic,
+            step_scale: if double_talk { self.step_scale } else { 1.0 },
+        }
+    }
+}
+
+fn check_threshold(threshold: f32) -> Result<f32, ConfigError> {
+    if threshold.is_finite() && threshold > 0.0 {
+        Ok(threshold)
+    } else {
+        Err(ConfigError::InvalidThreshold(threshold))
+    }
+}
+
+/// The Geigel double-talk detector.
+///
+/// Declares double talk when the peak microphone amplitude exceeds `threshold` times the
+/// peak far-end amplitude over the filter span. The threshold reflects the assumed echo
+/// return loss: `0.5` assumes the echo is at least 6 dB below the far-end signal.
+#[derive(Clone, Debug)]
+pub struct GeigelDetector {
+    threshold: f32,
+    hangover: Hangover,
+}
+
+impl GeigelDetector {
+    /// Creates a Geigel detector that holds its decision for `hangover_frames` frames.
+    ///
+    /// `threshold` must be finite and positive.
+    pub fn new(threshold: f32, hangover_frames: usize) -> Result<Self, ConfigError> {
+        Ok(Self {
+            threshold: check_threshold(threshold)?,
+            hangover: Hangover::new(hangover_frames),
+        })
+    }
+
+    /// Sets the step size factor used during double talk, in `[0, 1]`. Defaults to `0.0`,
+    /// which freezes adaptation.
+    pub fn with_step_scale(mut self, step_scale: f32) -> Result<Self, ConfigError> {
+        self.hangover.set_step_scale(step_scale)?;
+        Ok(self)
+    }
+}
+
+impl Default for GeigelDetector {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            hangover: Hangover::new(4),
+        }
+    }
+}
+
+impl<T: Float> DoubleTalkDetector<T> for GeigelDetector {
+    fn detect(&mut self, input: &DoubleTalkInput<'_, T>) -> DoubleTalkDecision {
+        let peak = |signal: &[T]| signal.iter().fold(T::zero(), |max, &x| max.max(x.abs()));
+        let far_end_peak = peak(input.far_end);
+        let mic_peak = peak(input.mic);
+
+        let statistic = to_f32(mic_peak / far_end_peak.max(T::min_positive_value()));
+        self.hangover.decide(statistic > self.threshold, statistic)
+    }
+}
+
+/// A normalized cross-correlation double-talk detector.
+///
+/// Compares the cross-correlation between the microphone signal and the estimated echo
+/// with the microphone power. Without double talk the estimated echo explains the
+/// microphone signal and the statistic approaches one; near-end speech lowers it.
+///
+/// The statistic is only meaningful once the filter has converged, so the detector stays
+/// disarmed (never declaring double talk) until it first exceeds `threshold`.
+#[derive(Clone, Debug)]
+pub struct NormalizedCrossCorrelationDetector {
+    threshold: f32,
+    smoothing_factor: f32,
+    mic_echo_correlation: f32,
+    mic_power: f32,
+    armed: bool,
+    hangover: Hangover,
+}
+
+impl NormalizedCrossCorrelationDetector {
+    /// Creates a detector whose correlation estimates are recursively smoothed with
+    /// `smoothing_factor` and that holds its decision for `hangover_frames` frames.
+    ///
+    /// `threshold` must be finite and positive and `smoothing_factor` in `[0, 1)`.
+    pub fn new(
+        threshold: f32,
+        smoothing_factor: f32,
+        hangover_frames: usize,
+    ) -> Result<Self, ConfigError> {
+        Ok(Self {
+            threshold: check_threshold(threshold)?,
+            smoothing_factor: check_smoothing_factor(smoothing_factor)?,
+            mic_echo_correlation: 0.0,
+            mic_power: 0.0,
+            armed: false,
+            hangover: Hangover::new(hangover_frames),
+        })
+    }
+
+    /// Sets the step size factor used during double talk, in `[0, 1]`. Defaults to `0.0`,
+    /// which freezes adaptation.
+    pub fn with_step_scale(mut self, step_scale: f32) -> Result<Self, ConfigError> {
+        self.hangover.set_step_scale(step_scale)?;
+        Ok(self)
+    }
+}
+
+impl Default for NormalizedCrossCorrelationDetector {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            smoothing_factor: 0.5,
+            mic_echo_correlation: 0.0,
+            mic_power: 0.0,
+            armed: false,
+            hangover: Hangover::new(4),
+        }
+    }
+}
+
+impl<T: Float> DoubleTalkDetector<T> for NormalizedCrossCorrelationDetector {
+    fn detect(&mut self, input: &DoubleTalkInput<'_, T>) -> DoubleTalkDecision {
+        let mut correlation = T::zero();
+        let mut power = T::zero();
+        for (&mic, &error) in input.mic.iter().zip(input.error) {
+            let echo = mic - error;
+            correlation += mic * echo;
+            power += mic * mic;
+        }
+        let (correlation, power) = (to_f32(correlation), to_f32(power));
+
+        let a = self.smoothing_factor;
+        self.mic_echo_correlation = a * self.mic_echo_correlation + (1.0 - a) * correlation;
+        self.mic_power = a * self.mic_power + (1.0 - a) * power;
+
+        let statistic = self.mic_echo_correlation / self.mic_power.max(f32::MIN_POSITIVE);
+        self.armed |= statistic > self.threshold;
+        self.hangover
+            .decide(self.armed && statistic < self.threshold, statistic)
+    }
+}
+
+/// A coherence-based double-talk detector.
+///
+/// Estimates the magnitude-squared coherence between the far-end and microphone spectra,
+/// averaged over all positive frequencies. The echo path is linear, so without double talk
+/// the two signals are highly coherent; near-end speech is uncorrelated with the far-end
+/// and lowers the coherence below `threshold`.
+#[cfg(feature = "rustfft")]
+#[derive(Clone)]
+pub struct CoherenceDetector<T: Float = f32> {
+    fft: RustFft<T>,
+    mic_buffer: Vec<T>,
+    mic_spectrum: Vec<Complex<T>>,
+    cross_psd: Vec<Complex<T>>,
+    far_end_psd: Vec<T>,
+    mic_psd: Vec<T>,
+    threshold: f32,
+    smoothing_factor: f32,
+    hangover: Hangover,
+}
+
+#[cfg(feature = "rustfft")]
+impl<T: Float> CoherenceDetector<T> {
+    /// Creates a coherence detector for a canceller with the given `fft_size`. Cancellers
+    /// of another FFT size reject it.
+    ///
+    /// The spectral estimates are recursively smoothed with `smoothing_factor`, in
+    /// `[0, 1)`, and the decision is held for `hangover_frames` frames. `threshold` must be
+    /// finite and positive, and `fft_size` a power of two of at least 2.
+    pub fn new(
+        fft_size: usize,
+        threshold: f32,
+        smoothing_factor: f32,
+        hangover_frames: usize,
+    ) -> Result<Self, ConfigError> {
+        if fft_size < 2 || !fft_size.is_power_of_two() {
+            return Err(ConfigError::InvalidFftSize(fft_size));
+        }
+        let threshold = check_threshold(threshold)?;
+        let smoothing_factor = check_smoothing_factor(smoothing_factor)?;
+        let fft = RustFft::new(fft_size);
+        let bins = fft.spectrum_len();
+        Ok(Self {
+            fft,
+            mic_buffer: vec![T::zero(); fft_size],
+            mic_spectrum: vec![Complex::zero(); bins],
+            cross_psd: vec![Complex::zero(); bins],
+            far_end_psd: vec![T::zero(); bins],
+            mic_psd: vec![T::zero(); bins],
+            threshold,
+            smoothing_factor,
+            hangover: Hangover::new(hangover_frames),
+        })
+    }
+
+    /// Sets the step size factor used during double talk, in `[0, 1]`. Defaults to `0.0`,
+    /// which freezes adaptation.
+    pub fn with_step_scale(mut self, step_scale: f32) -> Result<Self, ConfigError> {
+        self.hangover.set_step_scale(step_scale)?;
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "rustfft")]
+impl<T: Float> DoubleTalkDetector<T> for CoherenceDetector<T> {
+    fn detect(&mut self, input: &DoubleTalkInput<'_, T>) -> DoubleTalkDecision {
+        // Keep a microphone window aligned with the far-end history.
+        let frame_size = input.mic.len();
+        self.mic_buffer.copy_within(frame_size.., 0);
+        let fft_size = self.mic_buffer.len();
+        self.mic_buffer[fft_size - frame_size..].copy_from_slice(input.mic);
+
+        self.fft.forward(&self.mic_buffer, &mut self.mic_spectrum);
+
+        let a: T = float(self.smoothing_factor);
+        let mut coherence = T::zero();
+        let bins = 1..fft_size / 2;
+        let bin_count: T = float(bins.len() as f64);
+        for k in bins {
+            let x = input.far_end_spectrum[k];
+            let d = self.mic_spectrum[k];
+            self.cross_psd[k] = self.cross_psd[k] * a + x.conj() * d * (T::one() - a);
+            self.far_end_psd[k] = a * self.far_end_psd[k] + (T::one() - a) * x.norm_sqr();
+            self.mic_psd[k] = a * self.mic_psd[k] + (T::one() - a) * d.norm_sqr();
+
+            let denominator = self.far_end_psd[k] * self.mic_psd[k];
+            if denominator > T::zero() {
+                coherence += self.cross_psd[k].norm_sqr() / denominator;
+            }
+        }
+
+        let statistic = to_f32(coherence / bin_count);
+        self.hangover.decide(statistic < self.threshold, statistic)
+    }
+
+    fn fft_size(&self) -> Option<usize> {
+        Some(self.mic_buffer.len())
+    }
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::{FdafAec, FrameSizeError};
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Converges on echo only, then runs a burst of loud near-end noise and returns the
+    /// per-frame decisions together with the echo attenuation reached right after the burst.
+    fn run_double_talk_scenario(detector: Option<&mut dyn DoubleTalkDetector>) -> (Vec<bool>, f32) {
+        const CONVERGE: usize = 120;
+        const DOUBLE_TALK: usize = 30;
+        const RECOVER: usize = 4;
+        let frames = CONVERGE + DOUBLE_TALK + RECOVER;
+
+        let far_end = noise(frames * FRAME_SIZE, 3);
+        let near_end = noise(frames * FRAME_SIZE, 11);
+        let mut mic = vec![0.0; far_end.len()];
+        for i in 20..mic.len() {
+            mic[i] = 0.3 * far_end[i - 20] + 0.1 * far_end[i - 7];
+            let frame = i / FRAME_SIZE;
+            if (CONVERGE..CONVERGE + DOUBLE_TALK).contains(&frame) {
+                mic[i] += 2.0 * near_end[i];
+            }
+        }
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut detector = detector;
+        let mut decisions = Vec::new();
+        let mut mic_energy = 0.0;
+        let mut error_energy = 0.0;
+        for (frame, (far, mic)) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+            .enumerate()
+        {
+            let mut error = [0.0; FRAME_SIZE];
+            let far = far.first_chunk().unwrap();
+            let mic = mic.first_chunk().unwrap();
+            match detector.as_deref_mut() {
+                Some(detector) => {
+                    let decision = aec
+                        .process_with_detector(detector, &mut error, far, mic)
+                        .unwrap();
+                    decisions.push(decision.double_talk);
+                }
+                None => aec.process(&mut error, far, mic),
+            }
+            if frame >= CONVERGE + DOUBLE_TALK {
+                mic_energy += mic.iter().map(|x| x * x).sum::<f32>();
+                error_energy += error.iter().map(|x| x * x).sum::<f32>();
+            }
+        }
+        (decisions, mic_energy / error_energy)
+    }
+
+    fn assert_protects_filter(detector: &mut dyn DoubleTalkDetector) {
+        let (_, unprotected) = run_double_talk_scenario(None);
+        let (decisions, protected) = run_double_talk_scenario(Some(detector));
+
+        let false_alarms = decisions[60..120].iter().filter(|&&dt| dt).count();
+        let detections = decisions[120..150].iter().filter(|&&dt| dt).count();
+        assert!(false_alarms <= 3, "{false_alarms} false alarms");
+        assert!(detections >= 27, "only {detections} detections");
+        assert!(
+            protected > 10.0 * unprotected,
+            "protected {protected} vs unprotected {unprotected}"
+        );
+    }
+
+    #[test]
+    fn geigel_protects_filter_during_double_talk() {
+        assert_protects_filter(&mut GeigelDetector::default());
+    }
+
+    #[test]
+    fn ncc_protects_filter_during_double_talk() {
+        assert_protects_filter(&mut NormalizedCrossCorrelationDetector::default());
+    }
+
+    #[test]
+    fn coherence_protects_filter_during_double_talk() {
+        assert_protects_filter(&mut CoherenceDetector::new(FFT_SIZE, 0.5, 0.5, 4).unwrap());
+    }
+
+    #[test]
+    fn rejects_coherence_detector_of_another_fft_size() {
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut detector = CoherenceDetector::new(2 * FFT_SIZE, 0.5, 0.5, 4).unwrap();
+        let frame = [0.1; FRAME_SIZE];
+        let mut error = [0.0; FRAME_SIZE];
+        assert_eq!(
+            aec.process_with_detector(&mut detector, &mut error, &frame, &frame),
+            Err(FrameSizeError {
+                expected: FRAME_SIZE,
+                actual: FFT_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(matches!(
+            GeigelDetector::new(f32::NAN, 4),
+            Err(ConfigError::InvalidThreshold(_))
+        ));
+        assert_eq!(
+            GeigelDetector::new(0.0, 4).err(),
+            Some(ConfigError::InvalidThreshold(0.0))
+        );
+        assert_eq!(
+            NormalizedCrossCorrelationDetector::new(0.8, 1.0, 4).err(),
+            Some(ConfigError::InvalidSmoothingFactor(1.0))
+        );
+        assert_eq!(
+            NormalizedCrossCorrelationDetector::new(-0.8, 0.5, 4).err(),
+            Some(ConfigError::InvalidThreshold(-0.8))
+        );
+        assert_eq!(
+            CoherenceDetector::<f32>::new(FFT_SIZE, 0.5, 1.5, 4).err(),
+            Some(ConfigError::InvalidSmoothingFactor(1.5))
+        );
+        assert_eq!(
+            CoherenceDetector::<f32>::new(FFT_SIZE, f32::INFINITY, 0.5, 4).err(),
+            Some(ConfigError::InvalidThreshold(f32::INFINITY))
+        );
+        assert_eq!(
+            CoherenceDetector::<f32>::new(500, 0.5, 0.5, 4).err(),
+            Some(ConfigError::InvalidFftSize(500))
+        );
+
+        for step_scale in [-0.1, 1.5] {
+            let err = Some(ConfigError::InvalidStepScale(step_scale));
+            assert_eq!(
+                GeigelDetector::default().with_step_scale(step_scale).err(),
+                err
+            );
+            assert_eq!(
+                NormalizedCrossCorrelationDetector::default()
+                    .with_step_scale(step_scale)
+                    .err(),
+                err
+            );
+        }
+        assert!(matches!(
+            CoherenceDetector::<f32>::new(FFT_SIZE, 0.5, 0.5, 4)
+                .unwrap()
+                .with_step_scale(f32::NAN),
+            Err(ConfigError::InvalidStepScale(_))
+        ));
+        assert!(GeigelDetector::default().with_step_scale(0.1).is_ok());
+    }
+
+    #[test]
+    fn hangover_holds_decision() {
+        let mut hangover = Hangover::new(2);
+        let decisions: Vec<bool> = [true, false, false, false]
+            .into_iter()
+            .map(|detected| hangover.decide(detected, 0.0).double_talk)
+            .collect();
+        assert_eq!(decisions, [true, true, true, false]);
+    }
+}
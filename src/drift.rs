@@ -0,0 +1,317 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{float, to_f32, ConfigError, Float};
+
+/// Configuration of the clock drift compensation.
+///
+/// See [`FdafAec::enable_drift_compensation`](crate::FdafAec::enable_drift_compensation).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftCompensationConfig {
+    /// The delay, in samples, added to the far-end reference so it can be advanced as well
+    /// as delayed. The accumulated drift can be compensated up to this many samples in
+    /// either direction, and the echo path must be at least this long. Must be at least 3,
+    /// the reach of the interpolator, and less than the frame size.
+    pub headroom: usize,
+    /// The largest clock skew that is tracked, in parts per million. Must be finite and
+    /// non-negative.
+    pub max_skew_ppm: f32,
+    /// The number of frames the filter is given to converge before the position of the
+    /// echo path is locked and tracking starts, at least one.
+    pub settle_frames: u32,
+    /// The time constant of the tracking loop, in frames, at least one. Longer time
+    /// constants give smoother skew estimates but take longer to lock.
+    pub time_constant: f32,
+}
+
+impl Default for DriftCompensationConfig {
+    fn default() -> Self {
+        Self {
+            headroom: 16,
+            max_skew_ppm: 1000.0,
+            settle_frames: 50,
+            time_constant: 100.0,
+        }
+    }
+}
+
+impl DriftCompensationConfig {
+    /// Checks that every parameter is within its documented range for a canceller with
+    /// frames of `frame_size` samples.
+    pub(crate) fn validate(&self, frame_size: usize) -> Result<(), ConfigError> {
+        if !(TAPS / 2..frame_size).contains(&self.headroom) {
+            return Err(ConfigError::InvalidHeadroom(self.headroom));
+        }
+        if !(self.max_skew_ppm.is_finite() && self.max_skew_ppm >= 0.0) {
+            return Err(ConfigError::InvalidMaxSkew(self.max_skew_ppm));
+        }
+        if self.settle_frames == 0 {
+            return Err(ConfigError::ZeroSettleFrames);
+        }
+        if !(self.time_constant.is_finite() && self.time_constant >= 1.0) {
+            return Err(ConfigError::InvalidTimeConstant(self.time_constant));
+        }
+        Ok(())
+    }
+}
+
+/// The current state of the clock drift compensation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DriftEstimate {
+    /// The estimated clock skew in parts per million, positive when the playback clock runs
+    /// faster than the capture clock.
+    pub skew_ppm: f32,
+    /// The delay, in samples, currently added to the far-end reference on top of the
+    /// headroom, i.e. the drift accumulated since tracking started.
+    pub offset: f32,
+}
+
+/// The number of samples of the fractional delay line's Lagrange interpolator.
+const TAPS: usize = 6;
+
+/// Tracks the clock skew between playback and capture from the drift of the echo path
+/// within the adaptive filter, and resamples the far-end reference to cancel it.
+///
+/// The position of the echo path is measured as the energy centroid of the filter's
+/// impulse response. A second-order tracking loop turns its deviation from the position
+/// locked after convergence into the skew estimate, which drives a fractional delay line
+/// with fifth-order Lagrange interpolation on the reference path.
+#[derive(Clone)]
+pub(crate) struct DriftCompensator<T: Float> {
+    config: DriftCompensationConfig,
+    history: Vec<T>,
+    aligned: Vec<T>,
+    /// Drift of the delay line per frame, in samples, as integrated by the loop.
+    skew: T,
+    /// Change of the delay line offset during the next frame, in samples.
+    rate: T,
+    offset: T,
+    locked_position: Option<T>,
+    frames: u32,
+}
+
+impl<T: Float> DriftCompensator<T> {
+    pub(crate) fn new(config: DriftCompensationConfig, frame_size: usize) -> Self {
+        Self {
+            config,
+            // Interpolation needs two samples before and three after every read position.
+            history: vec![T::zero(); frame_size + 2 * config.headroom + TAPS / 2],
+            aligned: vec![T::zero(); frame_size],
+            skew: T::zero(),
+            rate: T::zero(),
+            offset: T::zero(),
+            locked_position: None,
+            frames: 0,
+        }
+    }
+
+    /// Forgets the signal history, the skew estimate and the locked echo path position.
+    pub(crate) fn reset(&mut self) {
+        self.history.fill(T::zero());
+        self.aligned.fill(T::zero());
+        self.skew = T::zero();
+        self.rate = T::zero();
+        self.offset = T::zero();
+        self.locked_position = None;
+        self.frames = 0;
+    }
+
+    /// Restarts tracking from the current skew estimate once the filter has reconverged,
+    /// e.g. after its weights were cleared.
+    pub(crate) fn relock(&mut self) {
+        self.locked_position = None;
+        self.frames = 0;
+        self.rate = self.skew;
+    }
+
+    pub(crate) fn estimate(&self) -> DriftEstimate {
+        let frame_size: T = float(self.aligned.len() as f64);
+        DriftEstimate {
+            skew_ppm: to_f32(-self.skew / frame_size * float(1e6)),
+            offset: to_f32(self.offset),
+        }
+    }
+
+    /// Feeds one far-end frame and returns it delayed by the headroom plus the current,
+    /// continuously moving offset.
+    pub(crate) fn align(&mut self, far_end_frame: &[T]) -> &[T] {
+        let frame_size = far_end_frame.len();
+        let len = self.history.len();
+        self.history.copy_within(frame_size.., 0);
+        self.history[len - frame_size..].copy_from_slice(far_end_frame);
+
+        // The total delay stays within [3, 2 * headroom] so the interpolation never reads
+        // past the newest sample.
+        let headroom: T = float(self.config.headroom as f64);
+        let start = self.offset;
+        let end = (start + self.rate)
+            .max(float::<T>((TAPS / 2) as f64) - headroom)
+            .min(headroom);
+        let newest: T = float((len - frame_size) as f64);
+        for (i, y) in self.aligned.iter_mut().enumerate() {
+            let offset = start + (end - start) * float((i + 1) as f64) / float(frame_size as f64);
+            let position = newest + float(i as f64) - headroom - offset;
+            let index = position.floor();
+            let first = index.to_usize().unwrap() + 1 - TAPS / 2;
+            *y = interpolate(&self.history[first..first + TAPS], position - index);
+        }
+        self.offset = end;
+        &self.aligned
+    }
+
+    /// Updates the skew estimate from the causal part of the filter's impulse response.
+    pub(crate) fn update(&mut self, impulse_response: &[T]) {
+        self.frames = self.frames.saturating_add(1);
+        if self.frames < self.config.settle_frames {
+            return;
+        }
+        let (energy, moment) = impulse_response
+            .iter()
+            .enumerate()
+            .fold((T::zero(), T::zero()), |(energy, moment), (n, &h)| {
+                (energy + h * h, moment + float::<T>(n as f64) * h * h)
+            });
+        let position = moment / energy;
+        // Silent or diverged filters carry no information about the echo path.
+        if !position.is_finite() {
+            return;
+        }
+        let error = position - *self.locked_position.get_or_insert(position);
+
+        // Critically damped loop: a later echo path calls for a longer reference delay.
+        let natural_frequency = T::one() / float(self.config.time_constant);
+        let max_skew = float::<T>(self.config.max_skew_ppm * 1e-6 * self.aligned.len() as f32);
+        self.skew = (self.skew + natural_frequency * natural_frequency * error)
+            .max(-max_skew)
+            .min(max_skew);
+        self.rate = self.skew + float::<T>(2.0) * natural_frequency * error;
+    }
+}
+
+/// Lagrange interpolation at `fraction` between the two middle `samples`.
+fn interpolate<T: Float>(samples: &[T], fraction: T) -> T {
+    // Relative to the sample before the read position.
+    let first = T::one() - float((TAPS / 2) as f64);
+    let x = fraction - first;
+    samples
+        .iter()
+        .enumerate()
+        .map(|(k, &sample)| {
+            let weight = (0..TAPS).filter(|&j| j != k).fold(T::one(), |weight, j| {
+                weight * (x - float(j as f64)) / float(k as f64 - j as f64)
+            });
+            weight * sample
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::FdafAec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Evaluates `signal` at the fractional time `t` with Blackman-windowed sinc interpolation.
+    fn evaluate(signal: &[f32], t: f64) -> f32 {
+        let center = t.floor() as isize;
+        (center - 31..=center + 32)
+            .filter(|&k| k >= 0 && (k as usize) < signal.len())
+            .map(|k| {
+                let x = t - k as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (core::f64::consts::PI * x).sin() / (core::f64::consts::PI * x)
+                };
+                let phase = core::f64::consts::PI * x / 33.0;
+                let window = 0.42 + 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                signal[k as usize] * (sinc * window) as f32
+            })
+            .sum()
+    }
+
+    /// Runs the canceller on a playback clock that is `skew` faster than the capture clock
+    /// and returns the ERLE over the last `tail` frames, in dB.
+    fn run(aec: &mut FdafAec<FFT_SIZE>, skew: f64, frames: usize, tail: usize) -> f32 {
+        // Low-pass noise, like speech mostly below a quarter of the sample rate.
+        let mut far_end = noise(frames * FRAME_SIZE, 7);
+        for _ in 0..6 {
+            let mut previous = 0.0;
+            for x in &mut far_end {
+                (*x, previous) = (0.5 * (*x + previous), *x);
+            }
+        }
+        let (mut mic_energy, mut error_energy) = (0.0, 0.0);
+        for frame in 0..frames {
+            let far = far_end[frame * FRAME_SIZE..].first_chunk().unwrap();
+            let mic: [f32; FRAME_SIZE] = core::array::from_fn(|i| {
+                let n = (frame * FRAME_SIZE + i) as f64;
+                0.5 * evaluate(&far_end, n * (1.0 + skew) - 100.0)
+            });
+            let mut error = [0.0; FRAME_SIZE];
+            aec.process(&mut error, far, &mic);
+            if frame >= frames - tail {
+                mic_energy += mic.iter().map(|x| x * x).sum::<f32>();
+                error_energy += error.iter().map(|e| e * e).sum::<f32>();
+            }
+        }
+        10.0 * (mic_energy / error_energy).log10()
+    }
+
+    #[test]
+    fn tracks_100_ppm_of_drift() {
+        // About 14 s at 16 kHz, over which the echo drifts by 23 samples.
+        let (frames, tail) = (900, 200);
+        let mut plain = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let uncompensated = run(&mut plain, 100e-6, frames, tail);
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_drift_compensation(DriftCompensationConfig {
+            headroom: 32,
+            ..Default::default()
+        })
+        .unwrap();
+        let compensated = run(&mut aec, 100e-6, frames, tail);
+        let estimate = aec.drift_estimate().unwrap();
+        assert!(compensated > uncompensated + 10.0);
+        assert!((estimate.skew_ppm - 100.0).abs() < 10.0, "{estimate:?}");
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = DriftCompensationConfig::default();
+        assert_eq!(config.validate(FRAME_SIZE), Ok(()));
+        // The headroom must cover half the interpolator and stay below the frame size.
+        for headroom in [2, FRAME_SIZE] {
+            assert_eq!(
+                DriftCompensationConfig { headroom, ..config }.validate(FRAME_SIZE),
+                Err(ConfigError::InvalidHeadroom(headroom))
+            );
+        }
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert!(aec
+            .enable_drift_compensation(DriftCompensationConfig {
+                time_constant: 0.5,
+                ..config
+            })
+            .is_err());
+        assert!(aec.drift_estimate().is_none());
+    }
+
+    #[test]
+    fn smallest_headroom_runs() {
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_drift_compensation(DriftCompensationConfig {
+            headroom: TAPS / 2,
+            settle_frames: 5,
+            ..Default::default()
+        })
+        .unwrap();
+        let erle = run(&mut aec, 100e-6, 40, 10);
+        assert!(erle.is_finite() && erle > 10.0, "{erle}");
+    }
+}
@@ -0,0 +1,522 @@
+use core::fmt;
+
+#[cfg(feature = "rustfft")]
+use alloc::vec;
+#[cfg(feature = "rustfft")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "rustfft")]
+use crate::fdaf::FdafCore;
+#[cfg(feature = "rustfft")]
+use crate::storage::Heap;
+#[cfg(feature = "rustfft")]
+use crate::{from_i16, to_i16};
+#[cfg(feature = "rustfft")]
+use crate::{
+    ComfortNoiseConfig, ConfigError, DelayEstimate, DelayEstimatorConfig, DivergenceConfig,
+    DoubleTalkDecision, DoubleTalkDetector, DriftCompensationConfig, DriftEstimate, EchoMetrics,
+    FdafAecConfig, FilterSnapshot, Float, ForegroundFilterConfig, ForegroundFilterStats,
+    KalmanConfig, MetricsConfig, ProportionateConfig, ResidualEchoSuppressorConfig, RustFft,
+    SnapshotError, StepSizeControl,
+};
+
+/// Error returned when a frame passed to [`DynFdafAec`] does not hold exactly
+/// [`DynFdafAec::frame_size`] samples, or when a double-talk detector was built for frames
+/// of another size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameSizeError {
+    /// The frame size the canceller was built for.
+    pub expected: usize,
+    /// The length of the offending frame, or the frame size of the offending detector.
+    pub actual: usize,
+}
+
+impl fmt::Display for FrameSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame has {} samples, expected {}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl core::error::Error for FrameSizeError {}
+
+/// An [`FdafAec`](crate::FdafAec) whose FFT size is chosen at runtime.
+///
+/// It runs exactly the same algorithm as the const-generic canceller, so both produce
+/// identical output for the same parameters, but one type covers every filter length and
+/// frames are plain slices. Frames of the wrong length are reported as a [`FrameSizeError`]
+/// instead of panicking. Create it with [`FdafAecConfig::try_build_dyn`].
+///
+/// As with [`FdafAec`](crate::FdafAec), call the `enable_*` methods outside the real-time
+/// thread: they allocate the state of their stage.
+#[cfg(feature = "rustfft")]
+#[derive(Clone)]
+pub struct DynFdafAec<T: Float = f32> {
+    core: FdafCore<T, RustFft<T>, Heap>,
+    /// The far-end, microphone and error frames of [`process_i16`](Self::process_i16),
+    /// converted to `T`.
+    pcm_scratch: Vec<T>,
+}
+
+#[cfg(feature = "rustfft")]
+impl<T: Float> DynFdafAec<T> {
+    /// Creates the canceller from an already validated configuration and FFT size.
+    pub(crate) fn from_config(fft_size: usize, config: FdafAecConfig) -> Self {
+        Self {
+            core: FdafCore::new(fft_size, config),
+            pcm_scratch: vec![T::zero(); 3 * (fft_size / 2)],
+        }
+    }
+
+    /// Returns the FFT size the canceller was built with.
+    pub fn fft_size(&self) -> usize {
+        self.core.fft_size()
+    }
+
+    /// Returns the number of samples every frame must hold, i.e. half the FFT size.
+    pub fn frame_size(&self) -> usize {
+        self.core.frame_size()
+    }
+
+    /// Returns the step size (mu).
+    pub fn step_size(&self) -> f32 {
+        self.core.step_size()
+    }
+
+    /// Sets the step size (mu), taking effect from the next frame.
+    ///
+    /// See [`FdafAec::set_step_size`](crate::FdafAec::set_step_size).
+    pub fn set_step_size(&mut self, step_size: f32) -> Result<(), ConfigError> {
+        self.core.set_step_size(step_size)
+    }
+
+    /// Returns the smoothing factor of the far-end PSD estimate.
+    pub fn smoothing_factor(&self) -> f32 {
+        self.core.smoothing_factor()
+    }
+
+    /// Sets the smoothing factor of the far-end PSD estimate, taking effect from the next
+    /// frame.
+    ///
+    /// See [`FdafAec::set_smoothing_factor`](crate::FdafAec::set_smoothing_factor).
+    pub fn set_smoothing_factor(&mut self, smoothing_factor: f32) -> Result<(), ConfigError> {
+        self.core.set_smoothing_factor(smoothing_factor)
+    }
+
+    /// Returns the regularization added to the PSD before normalizing the gradient.
+    pub fn regularization_factor(&self) -> f32 {
+        self.core.regularization_factor()
+    }
+
+    /// Sets the regularization added to the PSD before normalizing the gradient, taking effect
+    /// from the next frame.
+    ///
+    /// Must be finite and non-negative; otherwise returns
+    /// [`ConfigError::InvalidRegularizationFactor`]. See
+    /// [`FdafAec::set_regularization_factor`](crate::FdafAec::set_regularization_factor).
+    pub fn set_regularization_factor(
+        &mut self,
+        regularization_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.core.set_regularization_factor(regularization_factor)
+    }
+
+    /// Returns the leak applied to the weights on every update.
+    pub fn leak(&self) -> f32 {
+        self.core.leak()
+    }
+
+    /// Sets the leak applied to the weights on every update, taking effect from the next frame.
+    ///
+    /// See [`FdafAec::set_leak`](crate::FdafAec::set_leak).
+    pub fn set_leak(&mut self, leak: f32) -> Result<(), ConfigError> {
+        self.core.set_leak(leak)
+    }
+
+    /// Clears the adapted weights, keeping the far-end PSD and history.
+    ///
+    /// See [`FdafAec::reset_weights`](crate::FdafAec::reset_weights).
+    pub fn reset_weights(&mut self) {
+        self.core.reset_weights();
+    }
+
+    /// Returns the canceller to its freshly constructed state, keeping the parameters and
+    /// the enabled stages.
+    ///
+    /// See [`FdafAec::reset`](crate::FdafAec::reset).
+    pub fn reset(&mut self) {
+        self.core.reset();
+    }
+
+    /// Captures the adapted filter state, e.g. to warm-start the next session with it.
+    pub fn snapshot(&self) -> FilterSnapshot {
+        self.core.snapshot()
+    }
+
+    /// Restores the filter state from a snapshot.
+    ///
+    /// See [`FdafAec::restore`](crate::FdafAec::restore).
+    pub fn restore(&mut self, snapshot: &FilterSnapshot) -> Result<(), SnapshotError> {
+        self.core.restore(snapshot)
+    }
+
+    /// Returns the adapted weights as an nalgebra vector.
+    ///
+    /// See [`FdafAec::weights_view`](crate::FdafAec::weights_view).
+    #[cfg(feature = "nalgebra")]
+    pub fn weights_view(&self) -> nalgebra::DVectorView<'_, num_complex::Complex<T>> {
+        self.core.weights_view()
+    }
+
+    /// Returns the smoothed far-end PSD as an nalgebra vector.
+    ///
+    /// See [`FdafAec::psd_view`](crate::FdafAec::psd_view).
+    #[cfg(feature = "nalgebra")]
+    pub fn psd_view(&self) -> nalgebra::DVectorView<'_, T> {
+        self.core.psd_view()
+    }
+
+    /// Enables bulk delay compensation of the far-end reference.
+    ///
+    /// See [`FdafAec::enable_delay_compensation`](crate::FdafAec::enable_delay_compensation).
+    pub fn enable_delay_compensation(
+        &mut self,
+        config: DelayEstimatorConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_delay_compensation(config)
+    }
+
+    /// Disables bulk delay compensation, feeding the far-end reference to the filter as is.
+    pub fn disable_delay_compensation(&mut self) {
+        self.core.disable_delay_compensation();
+    }
+
+    /// Returns the current bulk delay estimate, if delay compensation is enabled.
+    pub fn delay_estimate(&self) -> Option<DelayEstimate> {
+        self.core.delay_estimate()
+    }
+
+    /// Enables clock drift compensation of the far-end reference.
+    ///
+    /// See [`FdafAec::enable_drift_compensation`](crate::FdafAec::enable_drift_compensation).
+    pub fn enable_drift_compensation(
+        &mut self,
+        config: DriftCompensationConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_drift_compensation(config)
+    }
+
+    /// Disables clock drift compensation.
+    pub fn disable_drift_compensation(&mut self) {
+        self.core.disable_drift_compensation();
+    }
+
+    /// Returns the current clock skew estimate, if drift compensation is enabled.
+    pub fn drift_estimate(&self) -> Option<DriftEstimate> {
+        self.core.drift_estimate()
+    }
+
+    /// Enables step-size control.
+    ///
+    /// See [`FdafAec::enable_step_size_control`](crate::FdafAec::enable_step_size_control).
+    pub fn enable_step_size_control(
+        &mut self,
+        control: StepSizeControl,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_step_size_control(control)
+    }
+
+    /// Disables step-size control, returning to the fixed step size.
+    pub fn disable_step_size_control(&mut self) {
+        self.core.disable_step_size_control();
+    }
+
+    /// Returns the effective step size of every half-spectrum bin used by the last weight
+    /// update, if step-size control or Kalman adaptation is enabled.
+    pub fn step_sizes(&self) -> Option<&[T]> {
+        self.core.step_sizes()
+    }
+
+    /// Switches the weight update from NLMS to the frequency-domain Kalman filter (FDKF).
+    ///
+    /// See [`FdafAec::enable_kalman_adaptation`](crate::FdafAec::enable_kalman_adaptation).
+    pub fn enable_kalman_adaptation(&mut self, config: KalmanConfig) -> Result<(), ConfigError> {
+        self.core.enable_kalman_adaptation(config)
+    }
+
+    /// Switches the weight update back to NLMS.
+    pub fn disable_kalman_adaptation(&mut self) {
+        self.core.disable_kalman_adaptation();
+    }
+
+    /// Switches the NLMS weight update to the proportionate (IPNLMS-style) update.
+    ///
+    /// See [`FdafAec::enable_proportionate_update`](crate::FdafAec::enable_proportionate_update).
+    pub fn enable_proportionate_update(
+        &mut self,
+        config: ProportionateConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_proportionate_update(config)
+    }
+
+    /// Switches the weight update back to the uniform NLMS update.
+    pub fn disable_proportionate_update(&mut self) {
+        self.core.disable_proportionate_update();
+    }
+
+    /// Enables the foreground filter, turning the canceller into a two-path canceller.
+    ///
+    /// See [`FdafAec::enable_foreground_filter`](crate::FdafAec::enable_foreground_filter).
+    pub fn enable_foreground_filter(
+        &mut self,
+        config: ForegroundFilterConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_foreground_filter(config)
+    }
+
+    /// Disables the foreground filter, so the adapting filter produces the output again.
+    pub fn disable_foreground_filter(&mut self) {
+        self.core.disable_foreground_filter();
+    }
+
+    /// Returns how often weights were exchanged between the two filters, if the foreground
+    /// filter is enabled.
+    pub fn foreground_filter_stats(&self) -> Option<ForegroundFilterStats> {
+        self.core.foreground_filter_stats()
+    }
+
+    /// Enables the divergence monitor, which detects a diverged filter and recovers from it.
+    ///
+    /// See [`FdafAec::enable_divergence_monitor`](crate::FdafAec::enable_divergence_monitor).
+    pub fn enable_divergence_monitor(
+        &mut self,
+        config: DivergenceConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_divergence_monitor(config)
+    }
+
+    /// Disables the divergence monitor.
+    pub fn disable_divergence_monitor(&mut self) {
+        self.core.disable_divergence_monitor();
+    }
+
+    /// Returns the number of divergence events detected since the monitor was enabled or the
+    /// canceller was reset, if the divergence monitor is enabled.
+    pub fn divergence_events(&self) -> Option<u64> {
+        self.core.divergence_events()
+    }
+
+    /// Enables the echo metrics.
+    ///
+    /// See [`FdafAec::enable_metrics`](crate::FdafAec::enable_metrics).
+    pub fn enable_metrics(&mut self, config: MetricsConfig) -> Result<(), ConfigError> {
+        self.core.enable_metrics(config)
+    }
+
+    /// Disables the echo metrics.
+    pub fn disable_metrics(&mut self) {
+        self.core.disable_metrics();
+    }
+
+    /// Returns the metrics of the last processed frame, if metrics are enabled.
+    pub fn metrics(&self) -> Option<EchoMetrics> {
+        self.core.metrics()
+    }
+
+    /// Enables the residual echo suppressor post-filter.
+    ///
+    /// See [`FdafAec::enable_residual_echo_suppressor`](crate::FdafAec::enable_residual_echo_suppressor).
+    pub fn enable_residual_echo_suppressor(
+        &mut self,
+        config: ResidualEchoSuppressorConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_residual_echo_suppressor(config)
+    }
+
+    /// Disables the residual echo suppressor, restoring the purely linear output.
+    pub fn disable_residual_echo_suppressor(&mut self) {
+        self.core.disable_residual_echo_suppressor();
+    }
+
+    /// Enables comfort noise generation.
+    ///
+    /// See [`FdafAec::enable_comfort_noise`](crate::FdafAec::enable_comfort_noise).
+    pub fn enable_comfort_noise(&mut self, config: ComfortNoiseConfig) -> Result<(), ConfigError> {
+        self.core.enable_comfort_noise(config)
+    }
+
+    /// Disables comfort noise generation.
+    pub fn disable_comfort_noise(&mut self) {
+        self.core.disable_comfort_noise();
+    }
+
+    /// Processes a frame of audio data to remove echo.
+    ///
+    /// Behaves like [`FdafAec::process`](crate::FdafAec::process), but all three slices must
+    /// hold [`frame_size`](Self::frame_size) samples; otherwise nothing is processed and a
+    /// [`FrameSizeError`] is returned.
+    pub fn process(
+        &mut self,
+        error_signal: &mut [T],
+        far_end_frame: &[T],
+        mic_frame: &[T],
+    ) -> Result<(), FrameSizeError> {
+        self.check_frames(error_signal, far_end_frame, mic_frame)?;
+        self.core.process(error_signal, far_end_frame, mic_frame);
+        Ok(())
+    }
+
+    /// Processes a frame of 16-bit PCM audio like [`DynFdafAec::process`].
+    ///
+    /// See [`FdafAec::process_i16`](crate::FdafAec::process_i16) for the conversion. Does
+    /// not allocate.
+    pub fn process_i16(
+        &mut self,
+        error_signal: &mut [i16],
+        far_end_frame: &[i16],
+        mic_frame: &[i16],
+    ) -> Result<(), FrameSizeError> {
+        self.check_frames(error_signal, far_end_frame, mic_frame)?;
+        let (far, rest) = self.pcm_scratch.split_at_mut(far_end_frame.len());
+        let (mic, error) = rest.split_at_mut(mic_frame.len());
+        for (far, &sample) in far.iter_mut().zip(far_end_frame) {
+            *far = from_i16(sample);
+        }
+        for (mic, &sample) in mic.iter_mut().zip(mic_frame) {
+            *mic = from_i16(sample);
+        }
+        self.core.process(error, far, mic);
+        for (output, &error) in error_signal.iter_mut().zip(error.iter()) {
+            *output = to_i16(error);
+        }
+        Ok(())
+    }
+
+    /// Processes a frame of audio data like [`DynFdafAec::process`], consulting a
+    /// double-talk detector before the weight update.
+    ///
+    /// See [`FdafAec::process_with_detector`](crate::FdafAec::process_with_detector).
+    pub fn process_with_detector<D: DoubleTalkDetector<T> + ?Sized>(
+        &mut self,
+        detector: &mut D,
+        error_signal: &mut [T],
+        far_end_frame: &[T],
+        mic_frame: &[T],
+    ) -> Result<DoubleTalkDecision, FrameSizeError> {
+        self.check_frames(error_signal, far_end_frame, mic_frame)?;
+        self.core.check_detector(detector)?;
+        Ok(self
+            .core
+            .process_with_detector(detector, error_signal, far_end_frame, mic_frame))
+    }
+
+    fn check_frames<S>(
+        &self,
+        error_signal: &[S],
+        far_end_frame: &[S],
+        mic_frame: &[S],
+    ) -> Result<(), FrameSizeError> {
+        let expected = self.frame_size();
+        for actual in [error_signal.len(), far_end_frame.len(), mic_frame.len()] {
+            if actual != expected {
+                return Err(FrameSizeError { expected, actual });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::FdafAec;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn matches_const_generic_canceller() {
+        let far_end = noise(FRAME_SIZE * 30, 3);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 20 { 0.4 * far_end[i - 20] } else { 0.0 })
+            .collect();
+
+        let mut dynamic = FdafAecConfig::new().try_build_dyn(FFT_SIZE).unwrap();
+        let mut fixed = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut a = [0.0; FRAME_SIZE];
+        let mut b = [0.0; FRAME_SIZE];
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            dynamic.process(&mut a, far, mic).unwrap();
+            fixed.process(
+                &mut b,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_sizes() {
+        assert_eq!(
+            FdafAecConfig::new().try_build_dyn(1000).err(),
+            Some(ConfigError::InvalidFftSize(1000))
+        );
+
+        let mut aec = FdafAecConfig::new().try_build_dyn(FFT_SIZE).unwrap();
+        let mut error = vec![0.0; FRAME_SIZE];
+        let result = aec.process(&mut error, &[0.0; 128], &[0.0; FRAME_SIZE]);
+        assert_eq!(
+            result,
+            Err(FrameSizeError {
+                expected: FRAME_SIZE,
+                actual: 128
+            })
+        );
+    }
+
+    #[test]
+    fn i16_frames_match_const_generic_canceller() {
+        let far_end: Vec<i16> = noise(FRAME_SIZE * 30, 5)
+            .iter()
+            .map(|x| (x * 8000.0) as i16)
+            .collect();
+        let mic: Vec<i16> = (0..far_end.len())
+            .map(|i| if i >= 20 { far_end[i - 20] / 2 } else { 0 })
+            .collect();
+
+        let mut dynamic = FdafAecConfig::new().try_build_dyn(FFT_SIZE).unwrap();
+        let mut fixed = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut a = [0; FRAME_SIZE];
+        let mut b = [0; FRAME_SIZE];
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            dynamic.process_i16(&mut a, far, mic).unwrap();
+            fixed.process_i16(
+                &mut b,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            assert_eq!(a, b);
+        }
+
+        assert_eq!(
+            dynamic.process_i16(&mut a, &[0; 128], &[0; FRAME_SIZE]),
+            Err(FrameSizeError {
+                expected: FRAME_SIZE,
+                actual: 128
+            })
+        );
+    }
+}
@@ -0,0 +1,945 @@
+use num_complex::Complex;
+use num_traits::Zero;
+
+#[cfg(feature = "alloc")]
+use crate::comfort_noise::ComfortNoiseGenerator;
+use crate::config::{
+    check_leak, check_regularization_factor, check_smoothing_factor, check_step_size,
+};
+use crate::divergence::DivergenceMonitor;
+#[cfg(feature = "alloc")]
+use crate::drift::DriftCompensator;
+#[cfg(feature = "alloc")]
+use crate::kalman::KalmanFilter;
+#[cfg(feature = "alloc")]
+use crate::metrics::MetricsTracker;
+#[cfg(feature = "alloc")]
+use crate::post_filter::ResidualEchoSuppressor;
+#[cfg(feature = "alloc")]
+use crate::proportionate::ProportionateUpdate;
+use crate::real_fft::FftBackend;
+#[cfg(feature = "alloc")]
+use crate::step_size::StepSizeController;
+use crate::storage::Storage;
+#[cfg(feature = "alloc")]
+use crate::two_path::ForegroundFilter;
+use crate::{
+    constrain_gradient, float, kernels, leaky_update, normalize_gradient, update_psd, ConfigError,
+    DivergenceConfig, DivergenceRecovery, DoubleTalkDecision, DoubleTalkDetector, DoubleTalkInput,
+    FdafAecConfig, Float, FrameSizeError,
+};
+#[cfg(feature = "alloc")]
+use crate::{
+    ComfortNoiseConfig, DriftCompensationConfig, DriftEstimate, EchoMetrics, FilterSnapshot,
+    ForegroundFilterConfig, ForegroundFilterStats, KalmanConfig, MetricsConfig,
+    ProportionateConfig, ResidualEchoSuppressorConfig, SnapshotError, StepSizeControl,
+};
+#[cfg(feature = "rustfft")]
+use crate::{DelayEstimate, DelayEstimator, DelayEstimatorConfig};
+
+/// The Overlap-Save FDAF algorithm with a runtime FFT size, shared by
+/// [`FdafAec`](crate::FdafAec) and [`DynFdafAec`](crate::DynFdafAec).
+///
+/// All spectra, the weights and the PSD hold only the `fft_size / 2 + 1` independent bins of
+/// the real signals' Hermitian spectra. Frame lengths are not checked here; the public
+/// wrappers validate them before calling in.
+#[derive(Clone)]
+pub(crate) struct FdafCore<T: Float, F: FftBackend<T>, S: Storage> {
+    fft: F,
+    weights: S::Spectrum<T>,
+    far_end_buffer: S::Buffer<T>,
+    x_f: S::Spectrum<T>,
+    e_t: S::Buffer<T>,
+    e_f: S::Spectrum<T>,
+    y_f: S::Spectrum<T>,
+    y_t: S::Buffer<T>,
+    gradient: S::Spectrum<T>,
+    time_scratch: S::Buffer<T>,
+    psd: S::Buffer<T>,
+    mu: f32,
+    smoothing_factor: f32,
+    regularization_factor: f32,
+    leak: f32,
+    #[cfg(feature = "alloc")]
+    output_f: S::Spectrum<T>,
+    #[cfg(feature = "rustfft")]
+    delay_estimator: Option<DelayEstimator<T>>,
+    #[cfg(feature = "alloc")]
+    drift_compensator: Option<DriftCompensator<T>>,
+    #[cfg(feature = "alloc")]
+    step_size_controller: Option<StepSizeController<T>>,
+    #[cfg(feature = "alloc")]
+    kalman: Option<KalmanFilter<T>>,
+    #[cfg(feature = "alloc")]
+    proportionate: Option<ProportionateUpdate<T>>,
+    #[cfg(feature = "alloc")]
+    foreground: Option<ForegroundFilter<T>>,
+    divergence: Option<DivergenceMonitor>,
+    #[cfg(feature = "alloc")]
+    metrics: Option<MetricsTracker<T>>,
+    #[cfg(feature = "alloc")]
+    residual_echo_suppressor: Option<ResidualEchoSuppressor<T>>,
+    #[cfg(feature = "alloc")]
+    comfort_noise: Option<ComfortNoiseGenerator<T>>,
+}
+
+impl<T: Float, F: FftBackend<T>, S: Storage> FdafCore<T, F, S> {
+    /// Creates the canceller from an already validated configuration and FFT size.
+    pub(crate) fn new(fft_size: usize, config: FdafAecConfig) -> Self {
+        let bins = fft_size / 2 + 1;
+
+        Self {
+            fft: F::new(fft_size),
+            weights: S::spectrum(bins, Complex::zero()),
+            far_end_buffer: S::buffer(fft_size, T::zero()),
+            x_f: S::spectrum(bins, Complex::zero()),
+            e_t: S::buffer(fft_size, T::zero()),
+            e_f: S::spectrum(bins, Complex::zero()),
+            psd: S::buffer(bins, T::one()), // Initialize with 1 to avoid division by zero
+            y_f: S::spectrum(bins, Complex::zero()),
+            y_t: S::buffer(fft_size, T::zero()),
+            gradient: S::spectrum(bins, Complex::zero()),
+            time_scratch: S::buffer(fft_size, T::zero()),
+            mu: config.step_size,
+            smoothing_factor: config.smoothing_factor,
+            regularization_factor: config.regularization_factor,
+            leak: config.leak,
+            #[cfg(feature = "alloc")]
+            output_f: S::spectrum(bins, Complex::zero()),
+            #[cfg(feature = "rustfft")]
+            delay_estimator: None,
+            #[cfg(feature = "alloc")]
+            drift_compensator: None,
+            #[cfg(feature = "alloc")]
+            step_size_controller: None,
+            #[cfg(feature = "alloc")]
+            kalman: None,
+            #[cfg(feature = "alloc")]
+            proportionate: None,
+            #[cfg(feature = "alloc")]
+            foreground: None,
+            divergence: None,
+            #[cfg(feature = "alloc")]
+            metrics: None,
+            #[cfg(feature = "alloc")]
+            residual_echo_suppressor: None,
+            #[cfg(feature = "alloc")]
+            comfort_noise: None,
+        }
+    }
+
+    pub(crate) fn fft_size(&self) -> usize {
+        self.far_end_buffer.len()
+    }
+
+    pub(crate) fn frame_size(&self) -> usize {
+        self.far_end_buffer.len() / 2
+    }
+
+    pub(crate) fn step_size(&self) -> f32 {
+        self.mu
+    }
+
+    pub(crate) fn set_step_size(&mut self, step_size: f32) -> Result<(), ConfigError> {
+        self.mu = check_step_size(step_size)?;
+        Ok(())
+    }
+
+    pub(crate) fn smoothing_factor(&self) -> f32 {
+        self.smoothing_factor
+    }
+
+    pub(crate) fn set_smoothing_factor(
+        &mut self,
+        smoothing_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.smoothing_factor = check_smoothing_factor(smoothing_factor)?;
+        Ok(())
+    }
+
+    pub(crate) fn regularization_factor(&self) -> f32 {
+        self.regularization_factor
+    }
+
+    pub(crate) fn set_regularization_factor(
+        &mut self,
+        regularization_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.regularization_factor = check_regularization_factor(regularization_factor)?;
+        Ok(())
+    }
+
+    pub(crate) fn leak(&self) -> f32 {
+        self.leak
+    }
+
+    pub(crate) fn set_leak(&mut self, leak: f32) -> Result<(), ConfigError> {
+        self.leak = check_leak(leak)?;
+        Ok(())
+    }
+
+    /// Clears the adapted weights only.
+    pub(crate) fn reset_weights(&mut self) {
+        self.weights.fill(Complex::zero());
+        #[cfg(feature = "alloc")]
+        if let Some(foreground) = &mut self.foreground {
+            foreground.set_weights(&self.weights);
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(compensator) = &mut self.drift_compensator {
+            compensator.relock();
+        }
+    }
+
+    /// Returns every piece of signal-dependent state to its initial value, keeping the
+    /// parameters and the enabled stages.
+    pub(crate) fn reset(&mut self) {
+        self.reset_weights();
+        self.psd.fill(T::one());
+        self.far_end_buffer.fill(T::zero());
+        #[cfg(feature = "rustfft")]
+        if let Some(estimator) = &mut self.delay_estimator {
+            estimator.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(compensator) = &mut self.drift_compensator {
+            compensator.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(controller) = &mut self.step_size_controller {
+            controller.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(kalman) = &mut self.kalman {
+            kalman.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(foreground) = &mut self.foreground {
+            foreground.reset(&self.weights);
+        }
+        if let Some(monitor) = &mut self.divergence {
+            monitor.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(suppressor) = &mut self.residual_echo_suppressor {
+            suppressor.reset();
+        }
+        #[cfg(feature = "alloc")]
+        if let Some(comfort_noise) = &mut self.comfort_noise {
+            comfort_noise.reset();
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn snapshot(&self) -> FilterSnapshot {
+        FilterSnapshot::new(&self.weights, &self.psd, &self.far_end_buffer)
+    }
+
+    #[cfg(feature = "nalgebra")]
+    pub(crate) fn weights_view(&self) -> nalgebra::DVectorView<'_, Complex<T>> {
+        nalgebra::DVectorView::from_slice(&self.weights, self.weights.len())
+    }
+
+    #[cfg(feature = "nalgebra")]
+    pub(crate) fn psd_view(&self) -> nalgebra::DVectorView<'_, T> {
+        nalgebra::DVectorView::from_slice(&self.psd, self.psd.len())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn restore(&mut self, snapshot: &FilterSnapshot) -> Result<(), SnapshotError> {
+        snapshot.validate(self.fft_size())?;
+        snapshot.copy_to(&mut self.weights, &mut self.psd, &mut self.far_end_buffer);
+        if let Some(foreground) = &mut self.foreground {
+            foreground.set_weights(&self.weights);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "rustfft")]
+    pub(crate) fn enable_delay_compensation(
+        &mut self,
+        config: DelayEstimatorConfig,
+    ) -> Result<(), ConfigError> {
+        if config.window < self.frame_size() {
+            return Err(ConfigError::InvalidDelayWindow(config.window));
+        }
+        self.delay_estimator = Some(DelayEstimator::new(config)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "rustfft")]
+    pub(crate) fn disable_delay_compensation(&mut self) {
+        self.delay_estimator = None;
+    }
+
+    #[cfg(feature = "rustfft")]
+    pub(crate) fn delay_estimate(&self) -> Option<DelayEstimate> {
+        self.delay_estimator.as_ref().map(DelayEstimator::estimate)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_drift_compensation(
+        &mut self,
+        config: DriftCompensationConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate(self.frame_size())?;
+        self.drift_compensator = Some(DriftCompensator::new(config, self.frame_size()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_drift_compensation(&mut self) {
+        self.drift_compensator = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn drift_estimate(&self) -> Option<DriftEstimate> {
+        self.drift_compensator
+            .as_ref()
+            .map(DriftCompensator::estimate)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_step_size_control(
+        &mut self,
+        control: StepSizeControl,
+    ) -> Result<(), ConfigError> {
+        control.validate()?;
+        self.step_size_controller = Some(StepSizeController::new(control, self.fft_size()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_step_size_control(&mut self) {
+        self.step_size_controller = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn step_sizes(&self) -> Option<&[T]> {
+        match (&self.kalman, &self.step_size_controller) {
+            (Some(kalman), _) => Some(kalman.steps()),
+            (None, Some(controller)) => Some(controller.steps()),
+            (None, None) => None,
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_kalman_adaptation(
+        &mut self,
+        config: KalmanConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.kalman = Some(KalmanFilter::new(config, self.weights.len()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_kalman_adaptation(&mut self) {
+        self.kalman = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_proportionate_update(
+        &mut self,
+        config: ProportionateConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.proportionate = Some(ProportionateUpdate::new(config, self.fft_size()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_proportionate_update(&mut self) {
+        self.proportionate = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_foreground_filter(
+        &mut self,
+        config: ForegroundFilterConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.foreground = Some(ForegroundFilter::new(config, &self.weights));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_foreground_filter(&mut self) {
+        self.foreground = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn foreground_filter_stats(&self) -> Option<ForegroundFilterStats> {
+        self.foreground.as_ref().map(ForegroundFilter::stats)
+    }
+
+    pub(crate) fn enable_divergence_monitor(
+        &mut self,
+        config: DivergenceConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.divergence = Some(DivergenceMonitor::new(config));
+        Ok(())
+    }
+
+    pub(crate) fn disable_divergence_monitor(&mut self) {
+        self.divergence = None;
+    }
+
+    pub(crate) fn divergence_events(&self) -> Option<u64> {
+        self.divergence.as_ref().map(DivergenceMonitor::events)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_metrics(&mut self, config: MetricsConfig) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.metrics = Some(MetricsTracker::new(config, self.weights.len()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_metrics(&mut self) {
+        self.metrics = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn metrics(&self) -> Option<EchoMetrics> {
+        self.metrics.as_ref().map(MetricsTracker::metrics)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_residual_echo_suppressor(
+        &mut self,
+        config: ResidualEchoSuppressorConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.residual_echo_suppressor = Some(ResidualEchoSuppressor::new(config, self.fft_size()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_residual_echo_suppressor(&mut self) {
+        self.residual_echo_suppressor = None;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn enable_comfort_noise(
+        &mut self,
+        config: ComfortNoiseConfig,
+    ) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.comfort_noise = Some(ComfortNoiseGenerator::new(config, self.fft_size()));
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn disable_comfort_noise(&mut self) {
+        self.comfort_noise = None;
+    }
+
+    pub(crate) fn process(&mut self, error_signal: &mut [T], far_end_frame: &[T], mic_frame: &[T]) {
+        self.filter(error_signal, far_end_frame, mic_frame);
+        self.update(error_signal, mic_frame);
+    }
+
+    /// Processes a microphone frame against the far-end analysis of `leader`, a core of the
+    /// same FFT size that has just processed the same far-end frame.
+    ///
+    /// The far-end FFT and PSD update of `leader` are reused instead of being computed
+    /// again, so several microphones sharing one far-end signal analyse it only once.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn process_shared(
+        &mut self,
+        leader: &Self,
+        error_signal: &mut [T],
+        mic_frame: &[T],
+    ) {
+        self.far_end_buffer.copy_from_slice(&leader.far_end_buffer);
+        self.x_f.copy_from_slice(&leader.x_f);
+        self.psd.copy_from_slice(&leader.psd);
+        self.cancel(error_signal, mic_frame);
+        self.update(error_signal, mic_frame);
+    }
+
+    /// Runs every step after filtering for a frame processed without a double-talk detector.
+    fn update(&mut self, error_signal: &mut [T], mic_frame: &[T]) {
+        self.adapt(error_signal, 1.0);
+        self.run_stages(error_signal, mic_frame, 1.0, false);
+    }
+
+    /// Runs the optional stages that follow the weight update.
+    #[cfg(feature = "alloc")]
+    fn run_stages(
+        &mut self,
+        error_signal: &mut [T],
+        mic_frame: &[T],
+        step_scale: f32,
+        double_talk: bool,
+    ) {
+        self.track_drift();
+        self.run_foreground(error_signal, mic_frame);
+        let passthrough = self.check_divergence(error_signal, mic_frame);
+        self.update_metrics(error_signal, mic_frame, step_scale);
+        if !passthrough {
+            self.post_process(error_signal, double_talk);
+        }
+    }
+
+    /// Runs the divergence monitor, the only optional stage that needs no heap.
+    #[cfg(not(feature = "alloc"))]
+    fn run_stages(
+        &mut self,
+        error_signal: &mut [T],
+        mic_frame: &[T],
+        _step_scale: f32,
+        _double_talk: bool,
+    ) {
+        self.check_divergence(error_signal, mic_frame);
+    }
+
+    /// Checks that `detector` was built for this canceller's FFT size, if it depends on one.
+    pub(crate) fn check_detector<D: DoubleTalkDetector<T> + ?Sized>(
+        &self,
+        detector: &D,
+    ) -> Result<(), FrameSizeError> {
+        match detector.fft_size() {
+            Some(fft_size) if fft_size != self.fft_size() => Err(FrameSizeError {
+                expected: self.frame_size(),
+                actual: fft_size / 2,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn process_with_detector<D: DoubleTalkDetector<T> + ?Sized>(
+        &mut self,
+        detector: &mut D,
+        error_signal: &mut [T],
+        far_end_frame: &[T],
+        mic_frame: &[T],
+    ) -> DoubleTalkDecision {
+        self.filter(error_signal, far_end_frame, mic_frame);
+        let decision = detector.detect(&DoubleTalkInput {
+            far_end: &self.far_end_buffer,
+            far_end_spectrum: &self.x_f,
+            mic: mic_frame,
+            error: error_signal,
+        });
+        self.adapt(error_signal, decision.step_scale);
+        self.run_stages(
+            error_signal,
+            mic_frame,
+            decision.step_scale,
+            decision.double_talk,
+        );
+        decision
+    }
+
+    /// Runs steps 1-7 of the algorithm: filters the far-end signal with the current weights
+    /// and writes the error signal.
+    fn filter(&mut self, error_signal: &mut [T], far_end_frame: &[T], mic_frame: &[T]) {
+        let frame_size = self.frame_size();
+        // 0. Align the far-end reference with its echo
+        #[cfg(feature = "rustfft")]
+        let far_end_frame = match &mut self.delay_estimator {
+            Some(estimator) => estimator.align(far_end_frame, mic_frame),
+            None => far_end_frame,
+        };
+        #[cfg(feature = "alloc")]
+        let far_end_frame = match &mut self.drift_compensator {
+            Some(compensator) => compensator.align(far_end_frame),
+            None => far_end_frame,
+        };
+
+        // 1. Update far-end buffer (shift old data, add new data)
+        // This creates a rolling window of the last `fft_size` samples.
+        self.far_end_buffer.copy_within(frame_size.., 0);
+        self.far_end_buffer[frame_size..].copy_from_slice(far_end_frame);
+
+        // 2. FFT of the far-end signal block
+        self.fft.forward(&self.far_end_buffer, &mut self.x_f);
+
+        // 3. Update Power Spectral Density (PSD) of the far-end signal
+        update_psd(&mut self.psd, &self.x_f, self.smoothing_factor);
+
+        self.cancel(error_signal, mic_frame);
+    }
+
+    /// Runs steps 4-7 of the algorithm: estimates the echo from the far-end spectrum of this
+    /// frame and writes the error signal.
+    fn cancel(&mut self, error_signal: &mut [T], mic_frame: &[T]) {
+        let fft_size = self.fft_size();
+        let frame_size = self.frame_size();
+
+        // 4. Estimate echo in frequency domain
+        kernels::complex_mul(&mut self.y_f, &self.weights, &self.x_f);
+
+        // 5. Inverse FFT of the estimated echo
+        self.fft.inverse(&self.y_f, &mut self.y_t);
+
+        // IFFT normalization
+        let scale = T::one() / float(fft_size as f64);
+        for y in self.y_t.iter_mut() {
+            *y *= scale;
+        }
+
+        // 6. Extract the valid part of the convolution (Overlap-Save method)
+        let estimated_echo = &self.y_t[frame_size..];
+
+        // 7. Calculate the error signal (mic signal - estimated echo)
+        for (idx, (&mic, &echo)) in mic_frame.iter().zip(estimated_echo.iter()).enumerate() {
+            error_signal[idx] = mic - echo;
+        }
+    }
+
+    /// Runs steps 8-9 of the algorithm: updates the weights from the error signal of the
+    /// last filtered frame, with the step size scaled by `step_scale`.
+    fn adapt(&mut self, error_signal: &[T], step_scale: f32) {
+        let frame_size = self.frame_size();
+        // 8. FFT of the error signal for weight update
+        // The error signal is placed in the second half of the buffer (the first half
+        // is zero-padded) to ensure correct time alignment for the gradient calculation.
+        let (padding, frame) = self.e_t.split_at_mut(frame_size);
+        padding.fill(T::zero());
+        frame.copy_from_slice(error_signal);
+        self.fft.forward(&self.e_t, &mut self.e_f);
+
+        #[cfg(feature = "alloc")]
+        if let Some(kalman) = &mut self.kalman {
+            // 9. Update filter weights using the frequency-domain Kalman filter
+            kalman.correction(&self.x_f, &self.e_f, &mut self.gradient, step_scale);
+            constrain_gradient(&mut self.fft, &mut self.gradient, &mut self.time_scratch);
+            kalman.update(&mut self.weights, &self.gradient);
+            return;
+        }
+
+        // 9. Update filter weights using Normalized LMS algorithm
+        for ((g, x), e) in self
+            .gradient
+            .iter_mut()
+            .zip(self.x_f.iter())
+            .zip(self.e_f.iter())
+        {
+            *g = x.conj() * e;
+        }
+
+        // Normalize by the PSD of the far-end signal
+        normalize_gradient(&mut self.gradient, &self.psd, self.regularization_factor);
+
+        // Per-bin step sizes are applied before the constraint, so the update stays causal.
+        // `peak_step` is the largest step any bin takes in this update.
+        let mu = float(self.mu * step_scale);
+        #[cfg(feature = "alloc")]
+        let (mu, peak_step) = match &mut self.step_size_controller {
+            Some(controller) => {
+                controller.update(&self.x_f, &self.e_f, self.mu, step_scale);
+                for (g, &step) in self.gradient.iter_mut().zip(controller.steps()) {
+                    *g *= step;
+                }
+                let peak_step = controller.steps().iter().copied().fold(T::zero(), T::max);
+                (T::one(), peak_step)
+            }
+            None => (mu, mu),
+        };
+
+        // Tap gains of the proportionate update are applied to the time-domain gradient.
+        #[cfg(feature = "alloc")]
+        if let Some(proportionate) = &mut self.proportionate {
+            proportionate.constrain(
+                &mut self.fft,
+                &self.weights,
+                &mut self.gradient,
+                &mut self.time_scratch,
+                T::one() / peak_step,
+            );
+            leaky_update(&mut self.weights, &self.gradient, mu, self.leak);
+            return;
+        }
+
+        constrain_gradient(&mut self.fft, &mut self.gradient, &mut self.time_scratch);
+        leaky_update(&mut self.weights, &self.gradient, mu, self.leak);
+    }
+
+    /// Updates the optional drift compensation from the impulse response of the weights.
+    #[cfg(feature = "alloc")]
+    fn track_drift(&mut self) {
+        let frame_size = self.frame_size();
+        if let Some(compensator) = &mut self.drift_compensator {
+            // The missing 1 / N scaling does not move the energy centroid.
+            self.fft.inverse(&self.weights, &mut self.time_scratch);
+            compensator.update(&self.time_scratch[..frame_size]);
+        }
+    }
+
+    /// Runs the optional foreground filter, whose error signal replaces the one of the
+    /// adapting (background) filter from here on.
+    #[cfg(feature = "alloc")]
+    fn run_foreground(&mut self, error_signal: &mut [T], mic_frame: &[T]) {
+        let frame_size = self.frame_size();
+        if let Some(foreground) = &mut self.foreground {
+            let reverted = foreground.process(
+                &mut self.fft,
+                &self.x_f,
+                mic_frame,
+                &mut self.weights,
+                error_signal,
+                &mut self.y_t[frame_size..],
+            );
+            // The uncertainty of the diverged weights does not describe the reverted ones.
+            if let (true, Some(kalman)) = (reverted, &mut self.kalman) {
+                kalman.reset();
+            }
+
+            // The post-processing stages work on the spectrum of the foreground error.
+            let (padding, frame) = self.e_t.split_at_mut(frame_size);
+            padding.fill(T::zero());
+            frame.copy_from_slice(error_signal);
+            self.fft.forward(&self.e_t, &mut self.e_f);
+        }
+    }
+
+    /// Runs the optional divergence monitor and recovers from a detected divergence.
+    ///
+    /// Returns whether this frame passes the microphone signal through, in which case
+    /// `error_signal` already holds it and the post-processing stages are skipped.
+    fn check_divergence(&mut self, error_signal: &mut [T], mic_frame: &[T]) -> bool {
+        let Some(monitor) = &mut self.divergence else {
+            return false;
+        };
+        let passthrough = if monitor.check(error_signal, mic_frame) {
+            let recovery = monitor.recovery();
+            self.recover_from_divergence(recovery);
+            true
+        } else {
+            monitor.passthrough()
+        };
+        if passthrough {
+            error_signal.copy_from_slice(mic_frame);
+        }
+        passthrough
+    }
+
+    fn recover_from_divergence(&mut self, recovery: DivergenceRecovery) {
+        let finite = self
+            .weights
+            .iter()
+            .all(|w| w.re.is_finite() && w.im.is_finite())
+            && self.psd.iter().all(|p| p.is_finite())
+            && self.far_end_buffer.iter().all(|x| x.is_finite());
+        if finite {
+            // Adaptation restarts from zero, with the state uncertainty of a fresh filter.
+            self.reset_weights();
+            #[cfg(feature = "alloc")]
+            if let Some(kalman) = &mut self.kalman {
+                kalman.reset();
+            }
+        } else {
+            // Non-finite values may have spread into any stage; keep only the event count.
+            let monitor = self.divergence.take();
+            self.reset();
+            self.divergence = monitor;
+        }
+        if let DivergenceRecovery::ShrinkStepSize { factor } = recovery {
+            // Keep the shrunk step within the range `set_step_size` accepts.
+            if let Ok(mu) = check_step_size(self.mu * factor) {
+                self.mu = mu;
+            }
+        }
+    }
+
+    /// Updates the optional metrics with the linear error signal of this frame.
+    #[cfg(feature = "alloc")]
+    fn update_metrics(&mut self, error_signal: &[T], mic_frame: &[T], step_scale: f32) {
+        if let Some(metrics) = &mut self.metrics {
+            let frame_size = self.far_end_buffer.len() / 2;
+            let step_size = match (&self.kalman, &self.step_size_controller) {
+                (Some(kalman), _) => kalman.mean_step(),
+                (None, Some(controller)) => controller.mean_step(),
+                (None, None) => float(self.mu * step_scale),
+            };
+            metrics.update(
+                &self.far_end_buffer[frame_size..],
+                mic_frame,
+                error_signal,
+                &self.y_t[frame_size..],
+                &self.weights,
+                step_size,
+            );
+        }
+    }
+
+    /// Runs the optional frequency-domain stages that follow the linear canceller and
+    /// rewrites the error signal with their output.
+    #[cfg(feature = "alloc")]
+    fn post_process(&mut self, error_signal: &mut [T], double_talk: bool) {
+        if self.residual_echo_suppressor.is_none() && self.comfort_noise.is_none() {
+            return;
+        }
+        let fft_size = self.fft_size();
+        let frame_size = self.frame_size();
+        self.output_f.copy_from_slice(&self.e_f);
+
+        // 10. Residual echo suppression
+        if let Some(suppressor) = &mut self.residual_echo_suppressor {
+            suppressor.process(
+                &mut self.fft,
+                &self.y_t[frame_size..],
+                &self.e_f,
+                &mut self.output_f,
+            );
+        }
+
+        // 11. Comfort noise injection
+        if let Some(comfort_noise) = &mut self.comfort_noise {
+            comfort_noise.process(
+                &self.far_end_buffer[frame_size..],
+                double_talk,
+                &self.e_f,
+                &mut self.output_f,
+            );
+        }
+
+        // 12. Back to the time domain, keeping the frame the error signal occupied
+        self.fft.inverse(&self.output_f, &mut self.time_scratch);
+        let scale = T::one() / float(fft_size as f64);
+        for (e, &y) in error_signal
+            .iter_mut()
+            .zip(&self.time_scratch[frame_size..])
+        {
+            *e = y * scale;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::*;
+    use crate::storage::{Heap, Inline};
+    use crate::test_util::noise;
+    use crate::{FixedFft, RustFft};
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use rustfft::FftPlanner;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// The canceller as it was written before the half-spectrum path: full complex FFTs over
+    /// all `FFT_SIZE` bins.
+    fn complex_reference(far_end: &[f32], mic: &[f32]) -> Vec<f32> {
+        let (mu, smoothing, regularization, leak) = (0.5, 0.9, 10e-4, 10e-4);
+        let mut planner = FftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let ifft = planner.plan_fft_inverse(FFT_SIZE);
+        let scale = 1.0 / FFT_SIZE as f32;
+        let mut weights = vec![Complex::zero(); FFT_SIZE];
+        let mut psd = vec![1.0; FFT_SIZE];
+        let mut far_end_buffer = vec![0.0; FFT_SIZE];
+
+        let mut output = Vec::new();
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            far_end_buffer.copy_within(FRAME_SIZE.., 0);
+            far_end_buffer[FRAME_SIZE..].copy_from_slice(far);
+            let mut x_f: Vec<_> = far_end_buffer
+                .iter()
+                .map(|&x| Complex::new(x, 0.0))
+                .collect();
+            fft.process(&mut x_f);
+            for (p, x) in psd.iter_mut().zip(&x_f) {
+                *p = smoothing * *p + (1.0 - smoothing) * x.norm_sqr();
+            }
+
+            let mut y: Vec<_> = weights.iter().zip(&x_f).map(|(w, x)| w * x).collect();
+            ifft.process(&mut y);
+            let error: Vec<f32> = mic
+                .iter()
+                .zip(&y[FRAME_SIZE..])
+                .map(|(d, y)| d - y.re * scale)
+                .collect();
+
+            let mut e_f = vec![Complex::zero(); FFT_SIZE];
+            for (e_f, &e) in e_f[FRAME_SIZE..].iter_mut().zip(&error) {
+                *e_f = Complex::new(e, 0.0);
+            }
+            fft.process(&mut e_f);
+            let mut gradient: Vec<_> = x_f
+                .iter()
+                .zip(&e_f)
+                .zip(&psd)
+                .map(|((x, e), p)| x.conj() * e / (p + regularization))
+                .collect();
+            ifft.process(&mut gradient);
+            for (i, g) in gradient.iter_mut().enumerate() {
+                *g = if i < FRAME_SIZE {
+                    *g * scale
+                } else {
+                    Complex::zero()
+                };
+            }
+            fft.process(&mut gradient);
+            for (w, g) in weights.iter_mut().zip(&gradient) {
+                *w = *w * (1.0 - leak) + g * mu;
+            }
+            output.extend_from_slice(&error);
+        }
+        output
+    }
+
+    #[test]
+    fn half_spectrum_matches_complex_reference() {
+        let frames = 60;
+        let far_end = noise(FRAME_SIZE * frames, 11);
+        let near_end = noise(FRAME_SIZE * frames, 12);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                let echo = if i >= 40 { far_end[i - 40] } else { 0.0 };
+                0.5 * echo + 0.05 * near_end[i]
+            })
+            .collect();
+
+        let expected = complex_reference(&far_end, &mic);
+        check(
+            FdafCore::<_, RustFft<_>, Heap>::new(FFT_SIZE, FdafAecConfig::new()),
+            &far_end,
+            &mic,
+            &expected,
+        );
+        check(
+            FdafCore::<_, FixedFft<_, FFT_SIZE>, Inline<FFT_SIZE>>::new(
+                FFT_SIZE,
+                FdafAecConfig::new(),
+            ),
+            &far_end,
+            &mic,
+            &expected,
+        );
+    }
+
+    /// Runs the canceller on the signals and compares its output with the expected one.
+    fn check<F: FftBackend<f32>, S: Storage>(
+        mut core: FdafCore<f32, F, S>,
+        far_end: &[f32],
+        mic: &[f32],
+        expected: &[f32],
+    ) {
+        let mut error = [0.0; FRAME_SIZE];
+        for (frame, (far, mic)) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+            .enumerate()
+        {
+            core.process(&mut error, far, mic);
+            let expected = &expected[frame * FRAME_SIZE..][..FRAME_SIZE];
+            for (a, b) in error.iter().zip(expected) {
+                assert!((a - b).abs() < 1e-4, "frame {frame}: {a} != {b}");
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+
+use crate::config::check_smoothing_factor;
+use crate::step_size::spectrum_mean;
+use crate::{float, ConfigError, Float};
+
+/// Fraction of the far-end block covered by the error frame. Only the echo in this part of
+/// the block is observed, which halves the cross-correlation of far-end and error.
+const OBSERVED_FRACTION: f64 = 0.5;
+
+/// Configuration of the frequency-domain Kalman filter (FDKF) adaptation.
+///
+/// The echo path is modelled as a per-bin first-order Markov process, `W(n + 1) = A W(n) +
+/// noise`, observed through the error spectrum. Every bin tracks how uncertain its weight
+/// is, and the Kalman gain weighs that uncertainty against the estimated near-end power:
+/// bins that are well known or masked by near-end speech adapt slowly, uncertain bins adapt
+/// fast. This makes the update inherently robust to double talk and nonstationary signals,
+/// without a step size to tune.
+///
+/// See [`FdafAec::enable_kalman_adaptation`](crate::FdafAec::enable_kalman_adaptation).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KalmanConfig {
+    /// Transition factor `A` of the echo path model, in `(0, 1]`. Values below one let the
+    /// state uncertainty grow again over time, so the filter keeps tracking echo path
+    /// changes; `1.0` models a fixed echo path.
+    pub transition_factor: f32,
+    /// State uncertainty of every bin when adaptation starts, i.e. the expected power of the
+    /// unknown echo path response per bin. Must be finite and positive.
+    pub initial_uncertainty: f32,
+    /// Process noise added to the state uncertainty of every bin on every frame, on top of
+    /// the `(1 - A^2) |W|^2` implied by the transition factor. Must be finite and
+    /// non-negative.
+    pub process_noise: f32,
+    /// Smoothing factor of the observation noise (near-end) PSD estimate, in `[0, 1)`.
+    pub smoothing_factor: f32,
+}
+
+impl Default for KalmanConfig {
+    fn default() -> Self {
+        Self {
+            transition_factor: 0.999,
+            initial_uncertainty: 1.0,
+            process_noise: 0.0,
+            smoothing_factor: 0.5,
+        }
+    }
+}
+
+impl KalmanConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.transition_factor > 0.0 && self.transition_factor <= 1.0) {
+            return Err(ConfigError::InvalidTransitionFactor(self.transition_factor));
+        }
+        if !(self.initial_uncertainty.is_finite() && self.initial_uncertainty > 0.0) {
+            return Err(ConfigError::InvalidUncertainty(self.initial_uncertainty));
+        }
+        if !(self.process_noise.is_finite() && self.process_noise >= 0.0) {
+            return Err(ConfigError::InvalidProcessNoise(self.process_noise));
+        }
+        check_smoothing_factor(self.smoothing_factor)?;
+        Ok(())
+    }
+}
+
+/// The per-bin state of the frequency-domain Kalman filter.
+#[derive(Clone)]
+pub(crate) struct KalmanFilter<T: Float> {
+    config: KalmanConfig,
+    uncertainty: Vec<T>,
+    observation_psd: Vec<T>,
+    steps: Vec<T>,
+}
+
+impl<T: Float> KalmanFilter<T> {
+    pub(crate) fn new(config: KalmanConfig, bins: usize) -> Self {
+        Self {
+            config,
+            uncertainty: vec![float(config.initial_uncertainty); bins],
+            observation_psd: vec![T::zero(); bins],
+            steps: vec![T::zero(); bins],
+        }
+    }
+
+    /// Restores the initial state uncertainty and forgets the observation noise estimate.
+    pub(crate) fn reset(&mut self) {
+        self.uncertainty
+            .fill(float(self.config.initial_uncertainty));
+        self.observation_psd.fill(T::zero());
+        self.steps.fill(T::zero());
+    }
+
+    /// Returns the NLMS-equivalent step size of every bin in the last update, i.e. the
+    /// Kalman gain relative to the normalized gradient.
+    pub(crate) fn steps(&self) -> &[T] {
+        &self.steps
+    }
+
+    /// Returns the mean of the NLMS-equivalent step sizes over the full spectrum.
+    pub(crate) fn mean_step(&self) -> T {
+        spectrum_mean(&self.steps)
+    }
+
+    /// Writes the Kalman-weighted correction `K E` into `gradient`, before the gradient
+    /// constraint. `step_scale` scales the gain, e.g. to freeze adaptation during double
+    /// talk.
+    pub(crate) fn correction(
+        &mut self,
+        far_end_spectrum: &[Complex<T>],
+        error_spectrum: &[Complex<T>],
+        gradient: &mut [Complex<T>],
+        step_scale: f32,
+    ) {
+        let a: T = float(self.config.smoothing_factor);
+        let c: T = float(OBSERVED_FRACTION);
+        let step_scale: T = float(step_scale);
+        for ((((g, x), e), (&p, psd)), step) in gradient
+            .iter_mut()
+            .zip(far_end_spectrum)
+            .zip(error_spectrum)
+            .zip(self.uncertainty.iter().zip(self.observation_psd.iter_mut()))
+            .zip(self.steps.iter_mut())
+        {
+            *psd = a * *psd + (T::one() - a) * e.norm_sqr();
+
+            // Innovation power: the echo misalignment the state uncertainty predicts plus
+            // the observation noise.
+            let predicted = c * c * x.norm_sqr() * p;
+            let innovation = predicted + *psd;
+            let gain = if innovation > T::zero() {
+                step_scale * c * p / innovation
+            } else {
+                T::zero()
+            };
+            *g = x.conj() * e * gain;
+            *step = gain * x.norm_sqr();
+        }
+    }
+
+    /// Applies the constrained correction to the weights and propagates the state
+    /// uncertainty to the next frame.
+    pub(crate) fn update(&mut self, weights: &mut [Complex<T>], correction: &[Complex<T>]) {
+        let transition: T = float(self.config.transition_factor);
+        let transition_sqr = transition * transition;
+        let c: T = float(OBSERVED_FRACTION);
+        let process_noise: T = float(self.config.process_noise);
+        for ((w, g), (p, step)) in weights
+            .iter_mut()
+            .zip(correction)
+            .zip(self.uncertainty.iter_mut().zip(&self.steps))
+        {
+            *w = (*w + g) * transition;
+            // The fraction of the uncertainty resolved by this observation.
+            let resolved = c * *step;
+            *p = transition_sqr * (T::one() - resolved).max(T::zero()) * *p
+                + (T::one() - transition_sqr) * w.norm_sqr()
+                + process_noise;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{energy, noise, run};
+    use crate::FdafAec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn kalman_is_robust_to_double_talk() {
+        let frames = 160;
+        let far_end = noise(FRAME_SIZE * frames, 61);
+        let near_end = noise(FRAME_SIZE * frames, 62);
+        // Loud near-end speech without a double-talk detector from frame 60 to 100.
+        let talk = FRAME_SIZE * 60..FRAME_SIZE * 100;
+        let echo: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                if i >= 30 {
+                    0.5 * far_end[i - 30] + 0.2 * far_end[i - 12]
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                echo[i]
+                    + if talk.contains(&i) {
+                        0.5 * near_end[i]
+                    } else {
+                        0.001 * near_end[i]
+                    }
+            })
+            .collect();
+
+        let mut nlms = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut kalman = nlms.clone();
+        kalman
+            .enable_kalman_adaptation(KalmanConfig::default())
+            .unwrap();
+        let nlms_out = run(&mut nlms, &far_end, &mic);
+        let kalman_out = run(&mut kalman, &far_end, &mic);
+        assert!(kalman_out.iter().all(|x| x.is_finite()));
+
+        let frames_energy = |signal: &[f32], frames: core::ops::Range<usize>| {
+            energy(&signal[frames.start * FRAME_SIZE..frames.end * FRAME_SIZE])
+        };
+        // Faster initial convergence.
+        assert!(frames_energy(&kalman_out, 3..8) < 0.1 * frames_energy(&nlms_out, 3..8));
+        // The near-end burst disturbs the Kalman filter far less.
+        assert!(frames_energy(&kalman_out, 100..105) < 0.2 * frames_energy(&nlms_out, 100..105));
+        // And it still cancels the echo by more than 30 dB in the steady state.
+        assert!(frames_energy(&kalman_out, 140..160) < 1e-3 * frames_energy(&mic, 140..160));
+        assert_eq!(kalman.step_sizes().unwrap().len(), FFT_SIZE / 2 + 1);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = KalmanConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            KalmanConfig {
+                transition_factor: 0.0,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidTransitionFactor(0.0))
+        );
+        assert_eq!(
+            KalmanConfig {
+                initial_uncertainty: -1.0,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidUncertainty(-1.0))
+        );
+        assert_eq!(
+            KalmanConfig {
+                process_noise: f32::INFINITY,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidProcessNoise(f32::INFINITY))
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! The per-bin loops of the weight update, with vectorized implementations.
+//!
+//! With the `std` feature, `f32` kernels use AVX when the CPU supports it, detected at
+//! runtime. Everything else, including `no_std` builds, runs the scalar kernels in
+//! [`scalar`]. Both perform the same operations in the same order without fused
+//! multiply-adds, so their results are bit-identical.
+//!
+//! Public for the benchmarks only; not part of the API.
+
+use core::any::TypeId;
+
+use num_complex::Complex;
+
+use crate::Float;
+
+/// Recursively smooths `psd` with the power of `spectrum`.
+pub fn smooth_psd<T: Float>(psd: &mut [T], spectrum: &[Complex<T>], smoothing_factor: T) {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if let Some(smoothing_factor) = as_f32(smoothing_factor) {
+        if avx::detected() {
+            // SAFETY: `T` is `f32` and the CPU supports AVX.
+            unsafe { avx::smooth_psd(cast_mut(psd), cast(spectrum), smoothing_factor) };
+            return;
+        }
+    }
+    scalar::smooth_psd(psd, spectrum, smoothing_factor);
+}
+
+/// Multiplies `a` and `b` bin by bin into `output`.
+pub fn complex_mul<T: Float>(output: &mut [Complex<T>], a: &[Complex<T>], b: &[Complex<T>]) {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if TypeId::of::<T>() == TypeId::of::<f32>() && avx::detected() {
+        // SAFETY: `T` is `f32` and the CPU supports AVX.
+        unsafe { avx::complex_mul(cast_mut(output), cast(a), cast(b)) };
+        return;
+    }
+    scalar::complex_mul(output, a, b);
+}
+
+/// Divides each gradient bin by the regularized PSD.
+pub fn normalize_gradient<T: Float>(gradient: &mut [Complex<T>], psd: &[T], regularization: T) {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if let Some(regularization) = as_f32(regularization) {
+        if avx::detected() {
+            // SAFETY: `T` is `f32` and the CPU supports AVX.
+            unsafe { avx::normalize_gradient(cast_mut(gradient), cast(psd), regularization) };
+            return;
+        }
+    }
+    scalar::normalize_gradient(gradient, psd, regularization);
+}
+
+/// Scales the weights by `factor` and adds the gradient scaled by `mu`.
+pub fn leaky_update<T: Float>(
+    weights: &mut [Complex<T>],
+    gradient: &[Complex<T>],
+    mu: T,
+    factor: T,
+) {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if let (Some(mu), Some(factor)) = (as_f32(mu), as_f32(factor)) {
+        if avx::detected() {
+            // SAFETY: `T` is `f32` and the CPU supports AVX.
+            unsafe { avx::leaky_update(cast_mut(weights), cast(gradient), mu, factor) };
+            return;
+        }
+    }
+    scalar::leaky_update(weights, gradient, mu, factor);
+}
+
+/// Returns `value` as an `f32` if `T` is `f32`.
+#[cfg_attr(not(all(feature = "std", target_arch = "x86_64")), allow(dead_code))]
+fn as_f32<T: Float>(value: T) -> Option<f32> {
+    (TypeId::of::<T>() == TypeId::of::<f32>()).then(|| value.to_f32().unwrap())
+}
+
+/// Reinterprets a slice of `T`-based values as the same values based on `f32`.
+///
+/// # Safety
+///
+/// `T` must be `f32`, so `U` and `V` are the same type.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+unsafe fn cast<U, V>(slice: &[U]) -> &[V] {
+    debug_assert_eq!(core::mem::size_of::<U>(), core::mem::size_of::<V>());
+    unsafe { core::slice::from_raw_parts(slice.as_ptr().cast(), slice.len()) }
+}
+
+/// The mutable version of [`cast`].
+///
+/// # Safety
+///
+/// `T` must be `f32`, so `U` and `V` are the same type.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+unsafe fn cast_mut<U, V>(slice: &mut [U]) -> &mut [V] {
+    debug_assert_eq!(core::mem::size_of::<U>(), core::mem::size_of::<V>());
+    unsafe { core::slice::from_raw_parts_mut(slice.as_mut_ptr().cast(), slice.len()) }
+}
+
+/// The reference implementations, used for every type without a vectorized kernel and for
+/// the tail of the vectorized loops.
+pub mod scalar {
+    use num_complex::Complex;
+
+    use crate::Float;
+
+    /// See [`smooth_psd`](super::smooth_psd).
+    pub fn smooth_psd<T: Float>(psd: &mut [T], spectrum: &[Complex<T>], smoothing_factor: T) {
+        let complement = T::one() - smoothing_factor;
+        for (psd, x) in psd.iter_mut().zip(spectrum) {
+            *psd = smoothing_factor * *psd + complement * x.norm_sqr();
+        }
+    }
+
+    /// See [`complex_mul`](super::complex_mul).
+    pub fn complex_mul<T: Float>(output: &mut [Complex<T>], a: &[Complex<T>], b: &[Complex<T>]) {
+        for ((y, a), b) in output.iter_mut().zip(a).zip(b) {
+            *y = a * b;
+        }
+    }
+
+    /// See [`normalize_gradient`](super::normalize_gradient).
+    pub fn normalize_gradient<T: Float>(gradient: &mut [Complex<T>], psd: &[T], regularization: T) {
+        for (g, &psd) in gradient.iter_mut().zip(psd) {
+            *g /= psd + regularization;
+        }
+    }
+
+    /// See [`leaky_update`](super::leaky_update).
+    pub fn leaky_update<T: Float>(
+        weights: &mut [Complex<T>],
+        gradient: &[Complex<T>],
+        mu: T,
+        factor: T,
+    ) {
+        for (w, g) in weights.iter_mut().zip(gradient) {
+            *w *= factor;
+            *w += g * mu;
+        }
+    }
+}
+
+/// AVX kernels for `f32`, processing four complex bins per instruction.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+mod avx {
+    use core::arch::x86_64::*;
+
+    use num_complex::Complex;
+
+    use super::scalar;
+
+    /// The number of complex bins in one AVX register.
+    const LANES: usize = 4;
+
+    pub(super) fn detected() -> bool {
+        std::is_x86_feature_detected!("avx")
+    }
+
+    /// Loads four complex bins.
+    ///
+    /// # Safety
+    ///
+    /// `bins` must hold at least four values.
+    #[target_feature(enable = "avx")]
+    unsafe fn load(bins: &[Complex<f32>]) -> __m256 {
+        unsafe { _mm256_loadu_ps(bins.as_ptr().cast()) }
+    }
+
+    /// Stores four complex bins.
+    ///
+    /// # Safety
+    ///
+    /// `bins` must hold at least four values.
+    #[target_feature(enable = "avx")]
+    unsafe fn store(bins: &mut [Complex<f32>], value: __m256) {
+        unsafe { _mm256_storeu_ps(bins.as_mut_ptr().cast(), value) }
+    }
+
+    /// Repeats each of four real values for the real and imaginary part of a bin.
+    ///
+    /// # Safety
+    ///
+    /// `values` must hold at least four values.
+    #[target_feature(enable = "avx")]
+    unsafe fn load_duplicated(values: &[f32]) -> __m256 {
+        let values = unsafe { _mm_loadu_ps(values.as_ptr()) };
+        _mm256_set_m128(
+            _mm_unpackhi_ps(values, values),
+            _mm_unpacklo_ps(values, values),
+        )
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn smooth_psd(psd: &mut [f32], spectrum: &[Complex<f32>], a: f32) {
+        let len = psd.len().min(spectrum.len());
+        let vectorized = len - len % (2 * LANES);
+        let (smoothing, complement) = (_mm256_set1_ps(a), _mm256_set1_ps(1.0 - a));
+        for (psd, x) in psd[..vectorized]
+            .chunks_exact_mut(2 * LANES)
+            .zip(spectrum.chunks_exact(2 * LANES))
+        {
+            let (low, high) = x.split_at(LANES);
+            let (low, high) = unsafe { (load(low), load(high)) };
+            // Bins 0, 1, 4, 5 in the lower and 2, 3, 6, 7 in the upper half.
+            let power = _mm256_hadd_ps(_mm256_mul_ps(low, low), _mm256_mul_ps(high, high));
+            let swapped = _mm256_permute2f128_ps(power, power, 0x01);
+            let power = _mm256_blend_ps(
+                _mm256_shuffle_ps(power, swapped, 0b01_00_01_00),
+                _mm256_shuffle_ps(swapped, power, 0b11_10_11_10),
+                0b1111_0000,
+            );
+            let previous = unsafe { _mm256_loadu_ps(psd.as_ptr()) };
+            let smoothed = _mm256_add_ps(
+                _mm256_mul_ps(smoothing, previous),
+                _mm256_mul_ps(complement, power),
+            );
+            unsafe { _mm256_storeu_ps(psd.as_mut_ptr(), smoothed) };
+        }
+        scalar::smooth_psd(&mut psd[vectorized..len], &spectrum[vectorized..len], a);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn complex_mul(
+        output: &mut [Complex<f32>],
+        a: &[Complex<f32>],
+        b: &[Complex<f32>],
+    ) {
+        let len = output.len().min(a.len()).min(b.len());
+        let vectorized = len - len % LANES;
+        for ((y, a), b) in output[..vectorized]
+            .chunks_exact_mut(LANES)
+            .zip(a.chunks_exact(LANES))
+            .zip(b.chunks_exact(LANES))
+        {
+            let (a, b) = unsafe { (load(a), load(b)) };
+            // (a.re * b.re - a.im * b.im, a.re * b.im + a.im * b.re)
+            let real_products = _mm256_mul_ps(_mm256_moveldup_ps(a), b);
+            let imag_products =
+                _mm256_mul_ps(_mm256_movehdup_ps(a), _mm256_permute_ps(b, 0b10_11_00_01));
+            unsafe { store(y, _mm256_addsub_ps(real_products, imag_products)) };
+        }
+        scalar::complex_mul(
+            &mut output[vectorized..len],
+            &a[vectorized..len],
+            &b[vectorized..len],
+        );
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn normalize_gradient(
+        gradient: &mut [Complex<f32>],
+        psd: &[f32],
+        regularization: f32,
+    ) {
+        let len = gradient.len().min(psd.len());
+        let vectorized = len - len % LANES;
+        let regularization_vector = _mm256_set1_ps(regularization);
+        for (g, psd) in gradient[..vectorized]
+            .chunks_exact_mut(LANES)
+            .zip(psd.chunks_exact(LANES))
+        {
+            let psd = unsafe { load_duplicated(psd) };
+            let normalized = _mm256_div_ps(
+                unsafe { load(g) },
+                _mm256_add_ps(psd, regularization_vector),
+            );
+            unsafe { store(g, normalized) };
+        }
+        scalar::normalize_gradient(
+            &mut gradient[vectorized..len],
+            &psd[vectorized..len],
+            regularization,
+        );
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn leaky_update(
+        weights: &mut [Complex<f32>],
+        gradient: &[Complex<f32>],
+        mu: f32,
+        factor: f32,
+    ) {
+        let len = weights.len().min(gradient.len());
+        let vectorized = len - len % LANES;
+        let (mu_vector, factor_vector) = (_mm256_set1_ps(mu), _mm256_set1_ps(factor));
+        for (w, g) in weights[..vectorized]
+            .chunks_exact_mut(LANES)
+            .zip(gradient.chunks_exact(LANES))
+        {
+            let (leaked, step) = unsafe {
+                (
+                    _mm256_mul_ps(load(w), factor_vector),
+                    _mm256_mul_ps(load(g), mu_vector),
+                )
+            };
+            unsafe { store(w, _mm256_add_ps(leaked, step)) };
+        }
+        scalar::leaky_update(
+            &mut weights[vectorized..len],
+            &gradient[vectorized..len],
+            mu,
+            factor,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Deterministic test values spanning several orders of magnitude and both signs.
+    fn values(len: usize, mut state: u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let x = (state as f32 / u32::MAX as f32) - 0.5;
+                x * 10f32.powi((state % 7) as i32 - 3)
+            })
+            .collect()
+    }
+
+    fn spectrum(len: usize, state: u32) -> Vec<Complex<f32>> {
+        values(2 * len, state)
+            .chunks_exact(2)
+            .map(|pair| Complex::new(pair[0], pair[1]))
+            .collect()
+    }
+
+    fn bits(spectrum: &[Complex<f32>]) -> Vec<(u32, u32)> {
+        spectrum
+            .iter()
+            .map(|x| (x.re.to_bits(), x.im.to_bits()))
+            .collect()
+    }
+
+    #[test]
+    fn vectorized_kernels_match_scalar_bit_for_bit() {
+        // Lengths around the vector widths, and the bins of common FFT sizes.
+        for len in (0..20).chain([129, 257, 513, 1025]) {
+            let seed = len as u32 + 1;
+            let (a, b) = (spectrum(len, seed), spectrum(len, seed + 100));
+            let psd: Vec<f32> = values(len, seed + 200).iter().map(|x| x.abs()).collect();
+
+            let mut expected = psd.clone();
+            let mut actual = psd.clone();
+            scalar::smooth_psd(&mut expected, &a, 0.9);
+            smooth_psd(&mut actual, &a, 0.9);
+            let to_bits = |psd: &[f32]| psd.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+            assert_eq!(to_bits(&actual), to_bits(&expected), "len {len}");
+
+            let mut expected = a.clone();
+            let mut actual = a.clone();
+            scalar::complex_mul(&mut expected, &a, &b);
+            complex_mul(&mut actual, &a, &b);
+            assert_eq!(bits(&actual), bits(&expected), "len {len}");
+
+            let mut expected = a.clone();
+            let mut actual = a.clone();
+            scalar::normalize_gradient(&mut expected, &psd, 1e-3);
+            normalize_gradient(&mut actual, &psd, 1e-3);
+            assert_eq!(bits(&actual), bits(&expected), "len {len}");
+
+            let mut expected = a.clone();
+            let mut actual = a.clone();
+            scalar::leaky_update(&mut expected, &b, 0.5, 0.999);
+            leaky_update(&mut actual, &b, 0.5, 0.999);
+            assert_eq!(bits(&actual), bits(&expected), "len {len}");
+        }
+    }
+}
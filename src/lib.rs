@@ -1,37 +1,193 @@
 #![no_std]
+#[cfg(any(feature = "alloc", test))]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
-use alloc::sync::Arc;
-#[allow(unused)]
-use alloc::vec;
+#[cfg(not(feature = "rustfft"))]
+use core::fmt::Debug;
+use core::iter::Sum;
 
-use nalgebra::{ComplexField, DVector, DVectorView};
 use num_complex::Complex;
-use rustfft::{num_traits::Zero, Fft, FftPlanner};
+#[cfg(not(feature = "rustfft"))]
+use num_traits::FromPrimitive;
+use num_traits::{FloatConst, NumAssign};
+#[cfg(feature = "rustfft")]
+use rustfft::FftNum;
+
+#[cfg(feature = "alloc")]
+mod comfort_noise;
+mod config;
+#[cfg(feature = "rustfft")]
+mod delay;
+mod divergence;
+mod double_talk;
+#[cfg(feature = "alloc")]
+mod drift;
+mod dynamic;
+mod fdaf;
+#[cfg(feature = "alloc")]
+mod kalman;
+#[doc(hidden)]
+pub mod kernels;
+#[cfg(feature = "alloc")]
+mod metrics;
+#[cfg(feature = "alloc")]
+mod multi_mic;
+#[cfg(feature = "alloc")]
+mod multichannel;
+#[cfg(feature = "alloc")]
+mod partitioned;
+#[cfg(feature = "alloc")]
+mod post_filter;
+#[cfg(feature = "alloc")]
+mod proportionate;
+mod real_fft;
+#[cfg(feature = "alloc")]
+mod snapshot;
+#[cfg(feature = "alloc")]
+mod step_size;
+mod storage;
+#[cfg(feature = "alloc")]
+mod streaming;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "alloc")]
+mod two_path;
+
+#[cfg(feature = "alloc")]
+pub use comfort_noise::ComfortNoiseConfig;
+pub use config::{ConfigError, FdafAecConfig};
+#[cfg(feature = "rustfft")]
+pub use delay::{DelayEstimate, DelayEstimator, DelayEstimatorConfig};
+pub use divergence::{DivergenceConfig, DivergenceRecovery};
+#[cfg(feature = "rustfft")]
+pub use double_talk::CoherenceDetector;
+pub use double_talk::{
+    DoubleTalkDecision, DoubleTalkDetector, DoubleTalkInput, GeigelDetector,
+    NormalizedCrossCorrelationDetector,
+};
+#[cfg(feature = "alloc")]
+pub use drift::{DriftCompensationConfig, DriftEstimate};
+#[cfg(feature = "rustfft")]
+pub use dynamic::DynFdafAec;
+pub use dynamic::FrameSizeError;
+#[cfg(feature = "alloc")]
+pub use kalman::KalmanConfig;
+#[cfg(feature = "alloc")]
+pub use metrics::{EchoMetrics, MetricsConfig};
+#[cfg(feature = "alloc")]
+pub use multi_mic::MultiMicFdafAec;
+#[cfg(feature = "alloc")]
+pub use multichannel::{DecorrelationConfig, MultichannelFdafAec};
+#[cfg(feature = "alloc")]
+pub use partitioned::PartitionedFdafAec;
+#[cfg(feature = "alloc")]
+pub use post_filter::ResidualEchoSuppressorConfig;
+#[cfg(feature = "alloc")]
+pub use proportionate::ProportionateConfig;
+#[cfg(feature = "rustfft")]
+pub use real_fft::RustFft;
+pub use real_fft::{DefaultFft, FftBackend, FixedFft};
+#[cfg(feature = "alloc")]
+pub use snapshot::{FilterSnapshot, SnapshotError};
+#[cfg(feature = "alloc")]
+pub use step_size::{AnnealingConfig, OptimalStepSizeConfig, StepSizeControl};
+#[cfg(feature = "alloc")]
+pub use streaming::StreamingFdafAec;
+#[cfg(feature = "alloc")]
+pub use two_path::{ForegroundFilterConfig, ForegroundFilterStats};
+
+use fdaf::FdafCore;
+use storage::Inline;
+
+/// A floating-point type the canceller can compute in, implemented for `f32` and `f64`.
+///
+/// The sample type only affects the signal path and the adaptive state. Parameters,
+/// configurations and reported statistics are `f32` whatever the sample type.
+#[cfg(feature = "rustfft")]
+pub trait Float: FftNum + num_traits::Float + FloatConst + NumAssign + Sum + Default {}
+
+/// A floating-point type the canceller can compute in, implemented for `f32` and `f64`.
+///
+/// The sample type only affects the signal path and the adaptive state. Parameters,
+/// configurations and reported statistics are `f32` whatever the sample type.
+#[cfg(not(feature = "rustfft"))]
+pub trait Float:
+    num_traits::Float
+    + FromPrimitive
+    + FloatConst
+    + NumAssign
+    + Sum
+    + Default
+    + Debug
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl Float for f32 {}
+impl Float for f64 {}
+
+/// Converts a parameter or constant to the sample type.
+fn float<T: Float>(value: impl Into<f64>) -> T {
+    T::from_f64(value.into()).unwrap()
+}
+
+/// Converts a value of the sample type to a reported `f32` statistic.
+fn to_f32<T: Float>(value: T) -> f32 {
+    value.to_f32().unwrap()
+}
+
+/// Scales a 16-bit PCM sample to `[-1, 1)`.
+fn from_i16<T: Float>(sample: i16) -> T {
+    float::<T>(sample) / float(32768.0)
+}
+
+/// Scales a sample back to 16-bit PCM, rounding and saturating at the range limits.
+fn to_i16<T: Float>(sample: T) -> i16 {
+    let scaled = (sample * float(32768.0)).round();
+    // NaN saturates to zero.
+    scaled.to_i16().unwrap_or(if scaled > T::zero() {
+        i16::MAX
+    } else if scaled < T::zero() {
+        i16::MIN
+    } else {
+        0
+    })
+}
 
 /// Implements an Acoustic Echo Canceller using the Frequency Domain Adaptive Filter (FDAF)
 /// algorithm with the Overlap-Save method.
 ///
-/// This struct holds the state for the AEC and processes audio in frames.
+/// This struct holds the state for the AEC and processes audio in frames. The FFT size is
+/// fixed at compile time; see [`DynFdafAec`] for a canceller sized at runtime.
+///
+/// The canceller computes in `f32` by default; `FdafAec<FFT_SIZE, f64>` runs the same
+/// algorithm in double precision. [`process_i16`](Self::process_i16) accepts 16-bit PCM
+/// frames directly. The transforms run on the [`FftBackend`] `F`, by default
+/// [`DefaultFft`]: [`RustFft`] with the `rustfft` feature, [`FixedFft`] without it.
+///
+/// The signal buffers are arrays of `FFT_SIZE` samples and the weights and spectra arrays
+/// of `FFT_SIZE / 2 + 1` bins inside the struct, so a canceller of a large FFT size should
+/// be boxed or kept in a `static` rather than on a small stack.
+///
+/// The optional stages allocate their state when enabled, so call the `enable_*` methods
+/// outside the real-time thread; processing itself never allocates. Apart from the
+/// divergence monitor, they need the `alloc` feature, and delay compensation also needs
+/// `rustfft`.
 #[derive(Clone)]
-pub struct FdafAec<const FFT_SIZE: usize> {
-    fft: Arc<dyn Fft<f32>>,
-    ifft: Arc<dyn Fft<f32>>,
-    weights: DVector<Complex<f32>>,
-    far_end_buffer: DVector<f32>,
-    x_t_buffer: [Complex<f32>; FFT_SIZE],
-    e_t_buffer: [Complex<f32>; FFT_SIZE],
-    y_t: DVector<f32>,
-    psd: DVector<f32>,
-    mu: f32,
-    smoothing_factor: f32,
-    regularization_factor: f32,
-    leak: f32,
+pub struct FdafAec<
+    const FFT_SIZE: usize,
+    T: Float = f32,
+    F: FftBackend<T> = DefaultFft<T, FFT_SIZE>,
+> {
+    core: FdafCore<T, F, Inline<FFT_SIZE>>,
 }
 
-impl<const FFT_SIZE: usize> FdafAec<FFT_SIZE> {
+impl<const FFT_SIZE: usize, T: Float, F: FftBackend<T>> FdafAec<FFT_SIZE, T, F> {
     pub const FRAME_SIZE: usize = FFT_SIZE / 2;
-
     /// Creates a new `FdafAec` instance.
     ///
     /// # Arguments
@@ -43,141 +199,554 @@ impl<const FFT_SIZE: usize> FdafAec<FFT_SIZE> {
     /// * `step_size`: The learning rate (mu) for the adaptive filter. It controls how fast the
     ///   filter adapts. A larger value leads to faster convergence but can be less stable.
     ///   A typical value is between 0.1 and 1.0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any parameter is invalid; use [`FdafAecConfig::try_build`] to handle
+    /// invalid parameters without panicking.
     pub fn new(
         step_size: f32,
         smoothing_factor: f32,
         regularization_factor: f32,
         leak: f32,
     ) -> Self {
-        assert!(
-            Self::FRAME_SIZE > 0 && Self::FRAME_SIZE.is_power_of_two(),
-            "FRAME_SIZE must be a power of two."
-        );
-        let mut fft_planner = FftPlanner::new();
-        let fft = fft_planner.plan_fft_forward(FFT_SIZE);
-        let ifft = fft_planner.plan_fft_inverse(FFT_SIZE);
+        FdafAecConfig::new()
+            .step_size(step_size)
+            .smoothing_factor(smoothing_factor)
+            .regularization_factor(regularization_factor)
+            .leak(leak)
+            .try_build_as()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Fails the build of a canceller whose FFT backend is fixed to another size.
+    const BACKEND_FITS: () = assert!(
+        match F::FIXED_SIZE {
+            Some(size) => size == FFT_SIZE,
+            None => true,
+        },
+        "the FFT backend is built for another FFT size"
+    );
 
+    /// Creates the canceller from an already validated configuration.
+    pub(crate) fn from_config(config: FdafAecConfig) -> Self {
+        let () = Self::BACKEND_FITS;
         Self {
-            fft,
-            ifft,
-            weights: DVector::from_element(FFT_SIZE, Complex::zero()),
-            far_end_buffer: DVector::from_element(FFT_SIZE, 0.0),
-            x_t_buffer: [Complex::zero(); FFT_SIZE],
-            e_t_buffer: [Complex::zero(); FFT_SIZE],
-            psd: DVector::from_element(FFT_SIZE, 1.0), // Initialize with 1 to avoid division by zero
-            y_t: DVector::zeros(FFT_SIZE),
-            mu: step_size,
-            smoothing_factor,
-            regularization_factor,
-            leak,
+            core: FdafCore::new(FFT_SIZE, config),
         }
     }
 
+    /// Returns the step size (mu).
+    pub fn step_size(&self) -> f32 {
+        self.core.step_size()
+    }
+
+    /// Sets the step size (mu), taking effect from the next frame.
+    ///
+    /// The adapted weights and PSD are kept, so parameters can be tuned while the canceller
+    /// runs without restarting convergence. Values outside `[0, 2)` are rejected with
+    /// [`ConfigError::InvalidStepSize`] and leave the canceller unchanged.
+    pub fn set_step_size(&mut self, step_size: f32) -> Result<(), ConfigError> {
+        self.core.set_step_size(step_size)
+    }
+
+    /// Returns the smoothing factor of the far-end PSD estimate.
+    pub fn smoothing_factor(&self) -> f32 {
+        self.core.smoothing_factor()
+    }
+
+    /// Sets the smoothing factor of the far-end PSD estimate, taking effect from the next
+    /// frame.
+    ///
+    /// Values outside `[0, 1)` are rejected with [`ConfigError::InvalidSmoothingFactor`]; see
+    /// [`FdafAec::set_step_size`].
+    pub fn set_smoothing_factor(&mut self, smoothing_factor: f32) -> Result<(), ConfigError> {
+        self.core.set_smoothing_factor(smoothing_factor)
+    }
+
+    /// Returns the regularization added to the PSD before normalizing the gradient.
+    pub fn regularization_factor(&self) -> f32 {
+        self.core.regularization_factor()
+    }
+
+    /// Sets the regularization added to the PSD before normalizing the gradient, taking effect
+    /// from the next frame.
+    ///
+    /// Must be finite and non-negative; otherwise returns
+    /// [`ConfigError::InvalidRegularizationFactor`]. See [`FdafAec::set_step_size`].
+    pub fn set_regularization_factor(
+        &mut self,
+        regularization_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.core.set_regularization_factor(regularization_factor)
+    }
+
+    /// Returns the leak applied to the weights on every update.
+    pub fn leak(&self) -> f32 {
+        self.core.leak()
+    }
+
+    /// Sets the leak applied to the weights on every update, taking effect from the next frame.
+    ///
+    /// Values outside `[0, 1)` are rejected with [`ConfigError::InvalidLeak`]; see
+    /// [`FdafAec::set_step_size`].
+    pub fn set_leak(&mut self, leak: f32) -> Result<(), ConfigError> {
+        self.core.set_leak(leak)
+    }
+
+    /// Clears the adapted weights, keeping the far-end PSD and history.
+    ///
+    /// Use it when the echo path has changed completely, e.g. after switching the output
+    /// device: the filter restarts from zero but normalizes its first updates with the PSD it
+    /// already knows.
+    pub fn reset_weights(&mut self) {
+        self.core.reset_weights();
+    }
+
+    /// Returns the canceller to its freshly constructed state: clears the weights, the PSD,
+    /// the far-end history and the state of every enabled stage.
+    ///
+    /// The parameters and the enabled stages are kept. Does not allocate.
+    pub fn reset(&mut self) {
+        self.core.reset();
+    }
+
+    /// Captures the adapted filter state, e.g. to warm-start the next session with it.
+    #[cfg(feature = "alloc")]
+    pub fn snapshot(&self) -> FilterSnapshot {
+        self.core.snapshot()
+    }
+
+    /// Restores the filter state from a snapshot, replacing the current weights, PSD and
+    /// far-end history.
+    ///
+    /// Fails without modifying the canceller if the snapshot was taken from a canceller
+    /// with a different FFT size.
+    #[cfg(feature = "alloc")]
+    pub fn restore(&mut self, snapshot: &FilterSnapshot) -> Result<(), SnapshotError> {
+        self.core.restore(snapshot)
+    }
+
+    /// Returns the adapted weights, the `FFT_SIZE / 2 + 1` bins of the estimated echo
+    /// path's half spectrum, as an nalgebra vector.
+    #[cfg(feature = "nalgebra")]
+    pub fn weights_view(&self) -> nalgebra::DVectorView<'_, Complex<T>> {
+        self.core.weights_view()
+    }
+
+    /// Returns the smoothed far-end PSD, one value per half-spectrum bin, as an nalgebra
+    /// vector.
+    #[cfg(feature = "nalgebra")]
+    pub fn psd_view(&self) -> nalgebra::DVectorView<'_, T> {
+        self.core.psd_view()
+    }
+
+    /// Enables bulk delay compensation of the far-end reference.
+    ///
+    /// A GCC-PHAT [`DelayEstimator`] tracks the delay between the far-end and microphone
+    /// signals and delays the far-end reference before it enters the filter, so the filter
+    /// taps are spent on the echo path rather than on device latency.
+    ///
+    /// Requires a [`window`](DelayEstimatorConfig::window) of at least
+    /// [`FRAME_SIZE`](Self::FRAME_SIZE) samples, a smoothing factor in `[0, 1)`, a minimum
+    /// confidence in `[0, 1]`, a hysteresis of at least one and a headroom of at most the
+    /// maximum delay.
+    #[cfg(feature = "rustfft")]
+    pub fn enable_delay_compensation(
+        &mut self,
+        config: DelayEstimatorConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_delay_compensation(config)
+    }
+
+    /// Disables bulk delay compensation, feeding the far-end reference to the filter as is.
+    #[cfg(feature = "rustfft")]
+    pub fn disable_delay_compensation(&mut self) {
+        self.core.disable_delay_compensation();
+    }
+
+    /// Returns the current bulk delay estimate, if delay compensation is enabled.
+    #[cfg(feature = "rustfft")]
+    pub fn delay_estimate(&self) -> Option<DelayEstimate> {
+        self.core.delay_estimate()
+    }
+
+    /// Enables clock drift compensation of the far-end reference.
+    ///
+    /// When playback and capture run on separate clocks, the echo slowly slides against
+    /// the far-end reference and the filter has to keep re-learning the echo path. The
+    /// compensator measures the skew from the movement of the echo path within the filter
+    /// and resamples the reference with a fractional delay line, so the echo path stays in
+    /// place. The reference is delayed by [`DriftCompensationConfig::headroom`] samples,
+    /// after any bulk delay compensation, and the echo path must still fit the filter
+    /// with that extra delay.
+    ///
+    /// Requires a [`headroom`](DriftCompensationConfig::headroom) in `[3, FRAME_SIZE)`, a
+    /// finite, non-negative maximum skew, at least one settle frame and a finite time
+    /// constant of at least 1.
+    #[cfg(feature = "alloc")]
+    pub fn enable_drift_compensation(
+        &mut self,
+        config: DriftCompensationConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_drift_compensation(config)
+    }
+
+    /// Disables clock drift compensation, feeding the far-end reference to the filter as is.
+    #[cfg(feature = "alloc")]
+    pub fn disable_drift_compensation(&mut self) {
+        self.core.disable_drift_compensation();
+    }
+
+    /// Returns the current clock skew estimate, if drift compensation is enabled.
+    #[cfg(feature = "alloc")]
+    pub fn drift_estimate(&self) -> Option<DriftEstimate> {
+        self.core.drift_estimate()
+    }
+
+    /// Enables step-size control.
+    ///
+    /// Instead of a fixed step size, every weight update uses per-bin step sizes derived
+    /// from the configured [`step_size`](Self::step_size) by the given strategy, trading
+    /// fast initial convergence for low steady-state misadjustment. A double-talk detector
+    /// still scales the result. The effective steps are reported by
+    /// [`step_sizes`](Self::step_sizes) and, averaged, in [`EchoMetrics::mean_step_size`].
+    ///
+    /// Requires a smoothing factor in `[0, 1)` for [`StepSizeControl::Optimal`], and a final
+    /// step size in `[0, 2)` and a finite, positive time constant for
+    /// [`StepSizeControl::Annealing`].
+    #[cfg(feature = "alloc")]
+    pub fn enable_step_size_control(
+        &mut self,
+        control: StepSizeControl,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_step_size_control(control)
+    }
+
+    /// Disables step-size control, returning to the fixed step size.
+    #[cfg(feature = "alloc")]
+    pub fn disable_step_size_control(&mut self) {
+        self.core.disable_step_size_control();
+    }
+
+    /// Returns the effective step size of every half-spectrum bin (`FFT_SIZE / 2 + 1`
+    /// values) used by the last weight update, if step-size control or Kalman adaptation
+    /// is enabled. For the Kalman filter these are the NLMS-equivalent steps of its gain.
+    #[cfg(feature = "alloc")]
+    pub fn step_sizes(&self) -> Option<&[T]> {
+        self.core.step_sizes()
+    }
+
+    /// Switches the weight update from NLMS to the frequency-domain Kalman filter (FDKF).
+    ///
+    /// `process` and `process_with_detector` keep their contract; only the adaptation rule
+    /// changes, so both can be A/B tested on the same recordings. While enabled, the step
+    /// size, the regularization factor, the leak and any step-size control are not used:
+    /// the per-bin Kalman gain takes their place. A double-talk detector still scales the
+    /// gain. The adapted weights are kept when switching either way.
+    ///
+    /// Requires a transition factor in `(0, 1]`, a finite, positive initial uncertainty, a
+    /// finite, non-negative process noise and a smoothing factor in `[0, 1)`.
+    #[cfg(feature = "alloc")]
+    pub fn enable_kalman_adaptation(&mut self, config: KalmanConfig) -> Result<(), ConfigError> {
+        self.core.enable_kalman_adaptation(config)
+    }
+
+    /// Switches the weight update back to NLMS.
+    #[cfg(feature = "alloc")]
+    pub fn disable_kalman_adaptation(&mut self) {
+        self.core.disable_kalman_adaptation();
+    }
+
+    /// Switches the NLMS weight update to the proportionate (IPNLMS-style) update for
+    /// sparse echo paths.
+    ///
+    /// Before the gradient constraint, every tap of the time-domain gradient is weighted by
+    /// a gain derived from the magnitude of the corresponding filter tap, so the few active
+    /// taps of a sparse echo path adapt faster than the rest. The step size, leak,
+    /// regularization and any step-size control or double-talk detector apply as before.
+    /// Has no effect while [Kalman adaptation](Self::enable_kalman_adaptation) is enabled.
+    /// Costs one more inverse FFT per frame.
+    ///
+    /// Requires a proportionality in `[-1, 1]` and a finite, positive regularization.
+    #[cfg(feature = "alloc")]
+    pub fn enable_proportionate_update(
+        &mut self,
+        config: ProportionateConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_proportionate_update(config)
+    }
+
+    /// Switches the weight update back to the uniform NLMS update.
+    #[cfg(feature = "alloc")]
+    pub fn disable_proportionate_update(&mut self) {
+        self.core.disable_proportionate_update();
+    }
+
+    /// Enables the foreground filter, turning the canceller into a two-path canceller.
+    ///
+    /// The canceller's weights become the background filter, which keeps adapting as
+    /// configured (typically with an aggressive step size) but no longer produces the
+    /// output. A foreground filter with its own copy of the weights filters the same far-end
+    /// spectrum, and its error signal is what `process` returns and what the metrics and
+    /// post-processing stages see. The background weights are copied to the foreground when
+    /// the background error energy is consistently lower, and the foreground weights are
+    /// copied back when the background diverges. The foreground starts from the current
+    /// weights.
+    ///
+    /// Requires a smoothing factor in `[0, 1)`, a copy ratio in `(0, 1]`, at least one hold
+    /// frame and a finite revert ratio of at least 1.
+    #[cfg(feature = "alloc")]
+    pub fn enable_foreground_filter(
+        &mut self,
+        config: ForegroundFilterConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_foreground_filter(config)
+    }
+
+    /// Disables the foreground filter, so the adapting filter produces the output again.
+    #[cfg(feature = "alloc")]
+    pub fn disable_foreground_filter(&mut self) {
+        self.core.disable_foreground_filter();
+    }
+
+    /// Returns how often weights were exchanged between the two filters, if the foreground
+    /// filter is enabled.
+    #[cfg(feature = "alloc")]
+    pub fn foreground_filter_stats(&self) -> Option<ForegroundFilterStats> {
+        self.core.foreground_filter_stats()
+    }
+
+    /// Enables the divergence monitor, which detects a diverged filter and recovers from it.
+    ///
+    /// Without it, weights that blew up, e.g. after a clipping burst, keep producing garbage
+    /// or NaN forever. The monitor checks the error signal of every frame and recovers as
+    /// configured in [`DivergenceConfig`]; [`divergence_events`](Self::divergence_events)
+    /// counts how often that happened.
+    ///
+    /// Requires a finite, positive power ratio, at least one hold frame and, for
+    /// [`DivergenceRecovery::ShrinkStepSize`], a factor in `(0, 1]`.
+    pub fn enable_divergence_monitor(
+        &mut self,
+        config: DivergenceConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_divergence_monitor(config)
+    }
+
+    /// Disables the divergence monitor.
+    pub fn disable_divergence_monitor(&mut self) {
+        self.core.disable_divergence_monitor();
+    }
+
+    /// Returns the number of divergence events detected since the monitor was enabled or the
+    /// canceller was reset, if the divergence monitor is enabled.
+    pub fn divergence_events(&self) -> Option<u64> {
+        self.core.divergence_events()
+    }
+
+    /// Enables the echo metrics.
+    ///
+    /// Once enabled, every processed frame updates an [`EchoMetrics`] snapshot with ERLE,
+    /// levels and convergence indicators, which [`FdafAec::metrics`] returns by value so it
+    /// can be polled from the audio thread.
+    ///
+    /// Requires a smoothing factor in `[0, 1)`.
+    #[cfg(feature = "alloc")]
+    pub fn enable_metrics(&mut self, config: MetricsConfig) -> Result<(), ConfigError> {
+        self.core.enable_metrics(config)
+    }
+
+    /// Disables the echo metrics.
+    #[cfg(feature = "alloc")]
+    pub fn disable_metrics(&mut self) {
+        self.core.disable_metrics();
+    }
+
+    /// Returns the metrics of the last processed frame, if metrics are enabled.
+    #[cfg(feature = "alloc")]
+    pub fn metrics(&self) -> Option<EchoMetrics> {
+        self.core.metrics()
+    }
+
+    /// Enables the residual echo suppressor post-filter.
+    ///
+    /// When enabled, `process` still adapts on the linear error signal but outputs it after
+    /// a per-bin Wiener-style suppression gain, removing echo the linear filter cannot model
+    /// (e.g. loudspeaker nonlinearities).
+    ///
+    /// Requires a finite, non-negative over-suppression, a gain floor in `[0, 1]` and a
+    /// smoothing factor in `[0, 1)`.
+    #[cfg(feature = "alloc")]
+    pub fn enable_residual_echo_suppressor(
+        &mut self,
+        config: ResidualEchoSuppressorConfig,
+    ) -> Result<(), ConfigError> {
+        self.core.enable_residual_echo_suppressor(config)
+    }
+
+    /// Disables the residual echo suppressor, restoring the purely linear output.
+    #[cfg(feature = "alloc")]
+    pub fn disable_residual_echo_suppressor(&mut self) {
+        self.core.disable_residual_echo_suppressor();
+    }
+
+    /// Enables comfort noise generation.
+    ///
+    /// The near-end background noise spectrum is tracked from the error signal while only the
+    /// far-end is active, and bins attenuated below it are filled with spectrally shaped
+    /// noise, so suppression is not perceived as the call dropping.
+    ///
+    /// Requires a finite, non-negative noise level and far-end threshold.
+    #[cfg(feature = "alloc")]
+    pub fn enable_comfort_noise(&mut self, config: ComfortNoiseConfig) -> Result<(), ConfigError> {
+        self.core.enable_comfort_noise(config)
+    }
+
+    /// Disables comfort noise generation.
+    #[cfg(feature = "alloc")]
+    pub fn disable_comfort_noise(&mut self) {
+        self.core.disable_comfort_noise();
+    }
+
     /// Processes a frame of audio data to remove echo.
     ///
     /// # Arguments
     ///
+    /// * `error_signal`: Receives the echo-cancelled audio frame. Its length must be `fft_size / 2`.
     /// * `far_end_frame`: A slice representing the audio frame from the far-end (the reference signal, e.g., loudspeaker).
     ///   Its length must be `fft_size / 2`.
     /// * `mic_frame`: A slice representing the audio frame from the near-end microphone, containing both the
     ///   near-end speaker's voice and the echo from the far-end. Its length must be `fft_size / 2`.
     ///
-    /// # Returns
-    ///
-    /// A `Vec<f32>` containing the echo-cancelled audio frame. The length of the vector is `fft_size / 2`.
+    /// The processing path works entirely on buffers preallocated by [`FdafAec::new`] and never
+    /// allocates, so it is safe to call from a real-time audio thread.
     pub fn process<const FRAME_SIZE: usize>(
         &mut self,
-        error_signal: &mut [f32; FRAME_SIZE],
-        far_end_frame: &[f32; FRAME_SIZE],
-        mic_frame: &[f32; FRAME_SIZE],
+        error_signal: &mut [T; FRAME_SIZE],
+        far_end_frame: &[T; FRAME_SIZE],
+        mic_frame: &[T; FRAME_SIZE],
     ) {
         assert_eq!(FRAME_SIZE, FFT_SIZE / 2);
-        // 1. Update far-end buffer (shift old data, add new data)
-        // This creates a rolling window of the last `fft_size` samples.
-        self.far_end_buffer
-            .as_mut_slice()
-            .copy_within(FRAME_SIZE.., 0);
-        self.far_end_buffer
-            .rows_mut(FRAME_SIZE, FRAME_SIZE)
-            .copy_from_slice(far_end_frame);
-
-        // 2. FFT of the far-end signal block
-        for (idx, x) in self.far_end_buffer.iter().enumerate() {
-            self.x_t_buffer[idx] = Complex::new(*x, 0.0);
-        }
-        self.fft.process(&mut self.x_t_buffer);
-        let x_f = DVectorView::from_slice(&self.x_t_buffer, FFT_SIZE);
-
-        // 3. Update Power Spectral Density (PSD) of the far-end signal
-        for i in 0..FFT_SIZE {
-            let power = x_f[i].norm_sqr();
-            self.psd[i] =
-                self.smoothing_factor * self.psd[i] + (1.0 - self.smoothing_factor) * power;
-        }
-
-        // 4. Estimate echo in frequency domain
-        let mut y_f = self.weights.component_mul(&x_f);
-
-        // 5. Inverse FFT of the estimated echo
-        let y_t_complex = y_f.as_mut_slice();
-        self.ifft.process(y_t_complex);
-
-        // IFFT normalization and extract real part
-        let scale = 1.0 / (FFT_SIZE as f32);
-        for (idx, c) in y_t_complex.iter().enumerate() {
-            self.y_t[idx] = c.re.scale(scale);
-        }
-
-        // 6. Extract the valid part of the convolution (Overlap-Save method)
-        let estimated_echo = self.y_t.rows(FRAME_SIZE, FRAME_SIZE);
+        self.core.process(error_signal, far_end_frame, mic_frame);
+    }
 
-        // 7. Calculate the error signal (mic signal - estimated echo)
-        for (idx, (mic, echo)) in mic_frame.iter().zip(estimated_echo.iter()).enumerate() {
-            error_signal[idx] = mic - echo;
-        }
+    /// Processes a frame like [`FdafAec::process`], taking the frames as slices whose
+    /// length the caller guarantees to be [`FRAME_SIZE`](Self::FRAME_SIZE).
+    #[cfg(any(feature = "alloc", test))]
+    pub(crate) fn process_frame(
+        &mut self,
+        error_signal: &mut [T],
+        far_end_frame: &[T],
+        mic_frame: &[T],
+    ) {
+        debug_assert_eq!(error_signal.len(), Self::FRAME_SIZE);
+        self.core.process(error_signal, far_end_frame, mic_frame);
+    }
 
-        // 8. FFT of the error signal for weight update
-        // The error signal is placed in the second half of the buffer (the first half
-        // is zero-padded) to ensure correct time alignment for the gradient calculation.
-        self.e_t_buffer = [Complex::zero(); FFT_SIZE];
-        for (i, &sample) in error_signal.iter().enumerate() {
-            self.e_t_buffer[i + FRAME_SIZE] = Complex::new(sample, 0.0);
+    /// Processes a frame of 16-bit PCM audio like [`FdafAec::process`].
+    ///
+    /// The samples are scaled to `[-1, 1)` on the way in and back to the 16-bit range on
+    /// the way out. Output samples that exceed the range, e.g. when the filter has not
+    /// converged yet and adds echo instead of removing it, saturate at `i16::MIN` and
+    /// `i16::MAX` rather than wrapping around. Does not allocate.
+    pub fn process_i16<const FRAME_SIZE: usize>(
+        &mut self,
+        error_signal: &mut [i16; FRAME_SIZE],
+        far_end_frame: &[i16; FRAME_SIZE],
+        mic_frame: &[i16; FRAME_SIZE],
+    ) {
+        assert_eq!(FRAME_SIZE, FFT_SIZE / 2);
+        let far_end_frame = far_end_frame.map(from_i16);
+        let mic_frame = mic_frame.map(from_i16);
+        let mut error = [T::zero(); FRAME_SIZE];
+        self.core.process(&mut error, &far_end_frame, &mic_frame);
+        for (output, error) in error_signal.iter_mut().zip(error) {
+            *output = to_i16(error);
         }
+    }
 
-        self.fft.process(&mut self.e_t_buffer);
-        let e_f = DVectorView::from_slice(&self.e_t_buffer, FFT_SIZE);
+    /// Processes a frame of audio data like [`FdafAec::process`], consulting a double-talk
+    /// detector before the weight update.
+    ///
+    /// The detector sees the far-end history, the microphone frame and the error signal of
+    /// the current frame. The step size used for this frame's update is scaled by
+    /// [`DoubleTalkDecision::step_scale`], so adaptation can be frozen while the near-end
+    /// speaks. The decision is returned so it can be logged.
+    ///
+    /// A detector built for another FFT size, see [`DoubleTalkDetector::fft_size`], is
+    /// rejected with a [`FrameSizeError`] before anything is processed.
+    pub fn process_with_detector<const FRAME_SIZE: usize, D: DoubleTalkDetector<T> + ?Sized>(
+        &mut self,
+        detector: &mut D,
+        error_signal: &mut [T; FRAME_SIZE],
+        far_end_frame: &[T; FRAME_SIZE],
+        mic_frame: &[T; FRAME_SIZE],
+    ) -> Result<DoubleTalkDecision, FrameSizeError> {
+        assert_eq!(FRAME_SIZE, FFT_SIZE / 2);
+        self.core.check_detector(detector)?;
+        Ok(self
+            .core
+            .process_with_detector(detector, error_signal, far_end_frame, mic_frame))
+    }
+}
 
-        // 9. Update filter weights using Normalized LMS algorithm
-        let mut gradient = x_f.map(|c| c.conj()).component_mul(&e_f);
+/// Recursively smooths the far-end power spectrum used to normalize the gradient.
+fn update_psd<T: Float>(psd: &mut [T], spectrum: &[Complex<T>], smoothing_factor: f32) {
+    kernels::smooth_psd(psd, spectrum, float(smoothing_factor));
+}
 
-        // modified
+/// Divides each gradient bin by the regularized far-end PSD (the "normalized" in NLMS).
+fn normalize_gradient<T: Float>(
+    gradient: &mut [Complex<T>],
+    psd: &[T],
+    regularization_factor: f32,
+) {
+    kernels::normalize_gradient(gradient, psd, float(regularization_factor));
+}
 
-        for i in 0..FFT_SIZE {
-            // Normalize by the PSD of the far-end signal
-            gradient[i] /= self.psd[i] + self.regularization_factor;
-        }
+/// Applies the gradient constraint of the Overlap-Save FDAF.
+///
+/// The gradient is taken to the time domain (in `time`, which holds `fft_size` samples),
+/// its second half (the part that would produce a circular rather than linear convolution)
+/// is zeroed, and it is transformed back. Also applies the IFFT normalization.
+fn constrain_gradient<T: Float>(
+    fft: &mut impl FftBackend<T>,
+    gradient: &mut [Complex<T>],
+    time: &mut [T],
+) {
+    let fft_size = time.len();
+    let scale = T::one() / float(fft_size as f64);
 
-        self.ifft.process(gradient.as_mut_slice());
+    fft.inverse(gradient, time);
 
-        for i in 0..FFT_SIZE {
-            if i < FRAME_SIZE {
-                gradient[i] *= scale;
-            } else {
-                gradient[i] = Complex::zero();
-            }
-        }
+    let (causal, wrapped) = time.split_at_mut(fft_size / 2);
+    causal.iter_mut().for_each(|g| *g *= scale);
+    wrapped.fill(T::zero());
 
-        self.fft.process(gradient.as_mut_slice());
+    fft.forward(time, gradient);
+}
 
-        let factor = 1.0 - self.leak;
-        self.weights.iter_mut().for_each(|i| *i *= factor);
-        self.weights += &gradient * Complex::new(self.mu, 0.0);
+/// Returns how often half-spectrum bin `k` occurs in the full spectrum of `fft_size` bins.
+#[cfg(feature = "alloc")]
+fn bin_multiplicity<T: Float>(k: usize, fft_size: usize) -> T {
+    if k == 0 || k == fft_size / 2 {
+        T::one()
+    } else {
+        float(2.0)
     }
 }
 
-#[cfg(test)]
+/// Applies the leak to the weights and adds the step-scaled gradient.
+fn leaky_update<T: Float>(weights: &mut [Complex<T>], gradient: &[Complex<T>], mu: T, leak: f32) {
+    kernels::leaky_update(weights, gradient, mu, T::one() - float(leak));
+}
+
+#[cfg(all(test, feature = "rustfft"))]
 mod tests {
     use super::*;
+    use crate::test_util::noise;
+    use alloc::vec;
+    use alloc::vec::Vec;
 
     #[test]
     fn new_instance_and_process_frame() {
@@ -202,6 +771,233 @@ mod tests {
         );
     }
 
+    fn mean_square(signal: &[f32]) -> f32 {
+        signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32
+    }
+
+    #[test]
+    fn parameters_change_mid_stream_without_discontinuity() {
+        const FFT_SIZE: usize = 512;
+        const FRAME_SIZE: usize = FFT_SIZE / 2;
+        let far_end = noise(FRAME_SIZE * 80, 41);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 30 { 0.5 * far_end[i - 30] } else { 0.0 })
+            .collect();
+        let frames: Vec<_> = far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+            .map(|(far, mic)| (far.first_chunk().unwrap(), mic.first_chunk().unwrap()))
+            .collect();
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut error = [0.0; FRAME_SIZE];
+        for (far, mic) in &frames[..40] {
+            aec.process(&mut error, far, mic);
+        }
+        let converged = mean_square(&error);
+        let mut untouched = aec.clone();
+
+        aec.set_step_size(1.5).unwrap();
+        aec.set_smoothing_factor(0.5).unwrap();
+        aec.set_regularization_factor(10e-2).unwrap();
+        aec.set_leak(0.0).unwrap();
+        assert_eq!((aec.step_size(), aec.smoothing_factor()), (1.5, 0.5));
+        assert_eq!((aec.regularization_factor(), aec.leak()), (10e-2, 0.0));
+
+        // The new parameters only affect the updates, so the first frame is unchanged and the
+        // filter stays converged from then on.
+        let mut expected = [0.0; FRAME_SIZE];
+        let (far, mic) = frames[40];
+        aec.process(&mut error, far, mic);
+        untouched.process(&mut expected, far, mic);
+        assert_eq!(error, expected);
+        for (far, mic) in &frames[41..] {
+            aec.process(&mut error, far, mic);
+            assert!(error.iter().all(|x| x.is_finite()));
+            assert!(
+                mean_square(&error) < 10.0 * converged.max(1e-10),
+                "{} > {converged}",
+                mean_square(&error)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected_and_kept() {
+        let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert_eq!(
+            aec.set_step_size(2.0),
+            Err(ConfigError::InvalidStepSize(2.0))
+        );
+        assert!(aec.set_smoothing_factor(f32::NAN).is_err());
+        assert_eq!(
+            aec.set_regularization_factor(-1.0),
+            Err(ConfigError::InvalidRegularizationFactor(-1.0))
+        );
+        assert_eq!(aec.set_leak(1.0), Err(ConfigError::InvalidLeak(1.0)));
+        assert_eq!(aec.step_size(), 0.5);
+        assert_eq!(aec.smoothing_factor(), 0.9);
+        assert_eq!(aec.regularization_factor(), 10e-4);
+        assert_eq!(aec.leak(), 10e-4);
+    }
+
+    #[test]
+    fn reset_matches_a_fresh_canceller() {
+        const FFT_SIZE: usize = 512;
+        const FRAME_SIZE: usize = FFT_SIZE / 2;
+        let far_end = noise(FRAME_SIZE * 20, 43);
+        let mic: Vec<f32> = far_end.iter().map(|x| 0.3 * x).collect();
+        let run = |aec: &mut FdafAec<FFT_SIZE>| {
+            let mut output = Vec::new();
+            for (far, mic) in far_end
+                .chunks_exact(FRAME_SIZE)
+                .zip(mic.chunks_exact(FRAME_SIZE))
+            {
+                let mut error = [0.0; FRAME_SIZE];
+                aec.process(
+                    &mut error,
+                    far.first_chunk().unwrap(),
+                    mic.first_chunk().unwrap(),
+                );
+                output.extend_from_slice(&error);
+            }
+            output
+        };
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_comfort_noise(ComfortNoiseConfig::default())
+            .unwrap();
+        let first = run(&mut aec);
+
+        // Without weights, the first frame passes the microphone signal through unchanged.
+        aec.reset_weights();
+        let mut error = [0.0; FRAME_SIZE];
+        let mut no_comfort_noise = aec.clone();
+        no_comfort_noise.disable_comfort_noise();
+        no_comfort_noise.process(
+            &mut error,
+            far_end.first_chunk().unwrap(),
+            mic.first_chunk().unwrap(),
+        );
+        assert_eq!(&error[..], &mic[..FRAME_SIZE]);
+
+        aec.reset();
+        assert_eq!(run(&mut aec), first);
+    }
+
+    #[test]
+    fn f64_output_matches_f32_within_tolerance() {
+        const FFT_SIZE: usize = 512;
+        const FRAME_SIZE: usize = FFT_SIZE / 2;
+        let far_end = noise(FRAME_SIZE * 60, 45);
+        let near_end = noise(FRAME_SIZE * 60, 46);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                let echo = if i >= 70 { 0.4 * far_end[i - 70] } else { 0.0 };
+                echo + 0.01 * near_end[i]
+            })
+            .collect();
+
+        let mut single = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut double = FdafAec::<FFT_SIZE, f64>::new(0.5, 0.9, 10e-4, 10e-4);
+        single
+            .enable_step_size_control(StepSizeControl::Optimal(Default::default()))
+            .unwrap();
+        double
+            .enable_step_size_control(StepSizeControl::Optimal(Default::default()))
+            .unwrap();
+        single.enable_metrics(MetricsConfig::default()).unwrap();
+        double.enable_metrics(MetricsConfig::default()).unwrap();
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let mut expected = [0.0; FRAME_SIZE];
+            single.process(
+                &mut expected,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            let mut error = [0.0; FRAME_SIZE];
+            double.process(
+                &mut error,
+                &far.first_chunk().unwrap().map(f64::from),
+                &mic.first_chunk().unwrap().map(f64::from),
+            );
+            for (&e, &x) in error.iter().zip(&expected) {
+                assert!((e - f64::from(x)).abs() < 1e-4, "{e} != {x}");
+            }
+        }
+        let (single, double) = (single.metrics().unwrap(), double.metrics().unwrap());
+        assert!(
+            (single.erle_db - double.erle_db).abs() < 0.5,
+            "{single:?} {double:?}"
+        );
+    }
+
+    #[test]
+    fn i16_frames_are_converted_and_saturated() {
+        const FFT_SIZE: usize = 512;
+        const FRAME_SIZE: usize = FFT_SIZE / 2;
+        let far_end: Vec<i16> = noise(FRAME_SIZE * 40, 47)
+            .iter()
+            .map(|x| (x * 60000.0) as i16)
+            .collect();
+        // The echo path flips its sign halfway, so the converged filter doubles the echo
+        // and the output exceeds the 16-bit range.
+        let mic: Vec<i16> = (0..far_end.len())
+            .map(|i| match i {
+                ..20 => 0,
+                i if i < far_end.len() / 2 => far_end[i - 20] / 10 * 9,
+                i => -(far_end[i - 20] / 10 * 9),
+            })
+            .collect();
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut reference = aec.clone();
+        let mut saturated = 0;
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let (far, mic) = (far.first_chunk().unwrap(), mic.first_chunk().unwrap());
+            let mut error = [0; FRAME_SIZE];
+            aec.process_i16(&mut error, far, mic);
+
+            let mut expected = [0.0; FRAME_SIZE];
+            reference.process(
+                &mut expected,
+                &far.map(|x| f32::from(x) / 32768.0),
+                &mic.map(|x| f32::from(x) / 32768.0),
+            );
+            for (&e, &x) in error.iter().zip(&expected) {
+                let x = (x * 32768.0).round();
+                assert_eq!(e, x.clamp(-32768.0, 32767.0) as i16);
+                saturated += usize::from(!(-32768.0..=32767.0).contains(&x));
+            }
+        }
+        assert!(saturated > 0);
+        assert_eq!(to_i16(f32::NAN), 0);
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn nalgebra_views_show_the_filter_state() {
+        const FFT_SIZE: usize = 512;
+        const FRAME_SIZE: usize = FFT_SIZE / 2;
+        let far_end = noise(FRAME_SIZE, 48);
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut error = [0.0; FRAME_SIZE];
+        aec.process(
+            &mut error,
+            far_end.first_chunk().unwrap(),
+            far_end.first_chunk().unwrap(),
+        );
+        assert_eq!(aec.weights_view().len(), FFT_SIZE / 2 + 1);
+        assert!(aec.weights_view().iter().any(|w| w.norm() > 0.0));
+        assert!(aec.psd_view().iter().all(|&p| p > 0.0));
+    }
+
     #[test]
     #[should_panic]
     fn test_new_with_non_power_of_two_fft_size() {
@@ -0,0 +1,267 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::check_smoothing_factor;
+use crate::{bin_multiplicity, float, to_f32, ConfigError, Float};
+
+/// The lowest level reported in decibels, used for silent signals.
+const MIN_DB: f32 = -120.0;
+
+/// Configuration of the echo metrics.
+///
+/// See [`FdafAec::enable_metrics`](crate::FdafAec::enable_metrics).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetricsConfig {
+    /// Smoothing factor of the recursive power estimates behind the smoothed values, in
+    /// `[0, 1)`.
+    pub smoothing_factor: f32,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            smoothing_factor: 0.95,
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_smoothing_factor(self.smoothing_factor)?;
+        Ok(())
+    }
+}
+
+/// A snapshot of the canceller's performance, updated on every processed frame.
+///
+/// Levels are mean-square powers in dB relative to a full-scale square wave (amplitude
+/// `1.0`), so a full-scale sine reads about -3 dBFS. Silence is reported as -120 dB. The
+/// error signal is the linear canceller's output, before any post-filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EchoMetrics {
+    /// The number of frames processed since the metrics were enabled.
+    pub frames: u64,
+    /// Echo return loss enhancement of the last frame: microphone power over error power,
+    /// in dB.
+    pub erle_db: f32,
+    /// Echo return loss enhancement computed from smoothed powers, in dB.
+    pub smoothed_erle_db: f32,
+    /// Echo return loss estimate: smoothed far-end power over the smoothed power of the
+    /// estimated echo, in dB. Only meaningful once the filter has converged.
+    pub erl_db: f32,
+    /// Level of the far-end frame fed to the filter, in dBFS.
+    pub far_end_dbfs: f32,
+    /// Level of the microphone frame, in dBFS.
+    pub mic_dbfs: f32,
+    /// Level of the error frame, in dBFS.
+    pub error_dbfs: f32,
+    /// Energy of the filter impulse response. It grows while the filter converges and
+    /// settles at the echo path energy.
+    pub filter_energy: f32,
+    /// Energy of the last weight update relative to the filter energy, in dB. It falls
+    /// while the filter converges and rises again when the echo path changes or the filter
+    /// is disturbed by double talk, which makes it a proxy for the filter misalignment.
+    pub weight_change_db: f32,
+    /// Step size of the last weight update, averaged over all bins when step-size control
+    /// varies it per bin. Includes the scaling by a double-talk detector.
+    pub mean_step_size: f32,
+}
+
+/// Tracks [`EchoMetrics`] from the signals of every processed frame.
+#[derive(Clone)]
+pub(crate) struct MetricsTracker<T: Float> {
+    config: MetricsConfig,
+    previous_weights: Vec<Complex<T>>,
+    far_end_power: T,
+    mic_power: T,
+    error_power: T,
+    echo_power: T,
+    metrics: EchoMetrics,
+}
+
+impl<T: Float> MetricsTracker<T> {
+    pub(crate) fn new(config: MetricsConfig, bins: usize) -> Self {
+        Self {
+            config,
+            previous_weights: vec![Complex::zero(); bins],
+            far_end_power: T::zero(),
+            mic_power: T::zero(),
+            error_power: T::zero(),
+            echo_power: T::zero(),
+            metrics: EchoMetrics::default(),
+        }
+    }
+
+    /// Restarts the metrics from the first frame.
+    pub(crate) fn reset(&mut self) {
+        self.previous_weights.fill(Complex::zero());
+        self.far_end_power = T::zero();
+        self.mic_power = T::zero();
+        self.error_power = T::zero();
+        self.echo_power = T::zero();
+        self.metrics = EchoMetrics::default();
+    }
+
+    pub(crate) fn metrics(&self) -> EchoMetrics {
+        self.metrics
+    }
+
+    /// Updates the metrics with one frame. `weights` holds the half-spectrum weights after
+    /// this frame's update, which used the (mean) step size `step_size`.
+    pub(crate) fn update(
+        &mut self,
+        far_end_frame: &[T],
+        mic_frame: &[T],
+        error_signal: &[T],
+        estimated_echo: &[T],
+        weights: &[Complex<T>],
+        step_size: T,
+    ) {
+        let far_end = mean_square(far_end_frame);
+        let mic = mean_square(mic_frame);
+        let error = mean_square(error_signal);
+        let echo = mean_square(estimated_echo);
+
+        let a = if self.metrics.frames == 0 {
+            T::zero()
+        } else {
+            float(self.config.smoothing_factor)
+        };
+        self.far_end_power = a * self.far_end_power + (T::one() - a) * far_end;
+        self.mic_power = a * self.mic_power + (T::one() - a) * mic;
+        self.error_power = a * self.error_power + (T::one() - a) * error;
+        self.echo_power = a * self.echo_power + (T::one() - a) * echo;
+
+        // Parseval over the half spectrum, in which every bin but DC and Nyquist stands for
+        // two bins of the full spectrum.
+        let fft_size = (weights.len() - 1) * 2;
+        let mut filter_energy = T::zero();
+        let mut change_energy = T::zero();
+        for (k, (w, previous)) in weights
+            .iter()
+            .zip(self.previous_weights.iter_mut())
+            .enumerate()
+        {
+            let multiplicity: T = bin_multiplicity(k, fft_size);
+            filter_energy += multiplicity * w.norm_sqr();
+            change_energy += multiplicity * (w - *previous).norm_sqr();
+            *previous = *w;
+        }
+        let filter_energy = filter_energy / float(fft_size as f64);
+        let change_energy = change_energy / float(fft_size as f64);
+
+        let metrics = &mut self.metrics;
+        metrics.frames += 1;
+        metrics.erle_db = ratio_db(mic, error);
+        metrics.smoothed_erle_db = ratio_db(self.mic_power, self.error_power);
+        metrics.erl_db = ratio_db(self.far_end_power, self.echo_power);
+        metrics.far_end_dbfs = power_db(far_end);
+        metrics.mic_dbfs = power_db(mic);
+        metrics.error_dbfs = power_db(error);
+        metrics.filter_energy = to_f32(filter_energy);
+        metrics.weight_change_db = ratio_db(change_energy, filter_energy);
+        metrics.mean_step_size = to_f32(step_size);
+    }
+}
+
+fn mean_square<T: Float>(signal: &[T]) -> T {
+    signal.iter().map(|&x| x * x).sum::<T>() / float(signal.len() as f64)
+}
+
+fn power_db<T: Float>(power: T) -> f32 {
+    if power > T::zero() {
+        to_f32(float::<T>(10.0) * power.log10()).max(MIN_DB)
+    } else {
+        MIN_DB
+    }
+}
+
+/// Returns `numerator / denominator` in dB, or 0 dB when either power is zero.
+fn ratio_db<T: Float>(numerator: T, denominator: T) -> f32 {
+    if numerator > T::zero() && denominator > T::zero() {
+        to_f32(float::<T>(10.0) * (numerator / denominator).log10())
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::FdafAec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn reports_convergence() {
+        let far_end = noise(FRAME_SIZE * 80, 23);
+        // The echo is attenuated by 10 dB on its way from the loudspeaker to the microphone.
+        let gain = 10f32.powf(-10.0 / 20.0);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 25 { gain * far_end[i - 25] } else { 0.0 })
+            .collect();
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert_eq!(aec.metrics(), None);
+        aec.enable_metrics(MetricsConfig::default()).unwrap();
+
+        let mut history = Vec::new();
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let mut error = [0.0; FRAME_SIZE];
+            aec.process(
+                &mut error,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            history.push(aec.metrics().unwrap());
+        }
+
+        let (early, last) = (history[2], history[79]);
+        assert_eq!(last.frames, 80);
+        assert!(early.erle_db < 10.0, "{early:?}");
+        assert!(
+            last.erle_db > 30.0 && last.smoothed_erle_db > 10.0,
+            "{last:?}"
+        );
+        assert!((last.erl_db - 10.0).abs() < 1.0, "{last:?}");
+        assert!(
+            (last.far_end_dbfs - last.mic_dbfs - 10.0).abs() < 0.5,
+            "{last:?}"
+        );
+        assert!(last.error_dbfs < last.mic_dbfs - 30.0, "{last:?}");
+        assert!((last.filter_energy - gain * gain).abs() < 0.01, "{last:?}");
+        assert!(
+            last.weight_change_db < early.weight_change_db - 20.0,
+            "{last:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert_eq!(MetricsConfig::default().validate(), Ok(()));
+        assert!(matches!(
+            MetricsConfig {
+                smoothing_factor: f32::NAN
+            }
+            .validate(),
+            Err(ConfigError::InvalidSmoothingFactor(_))
+        ));
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert!(aec
+            .enable_metrics(MetricsConfig {
+                smoothing_factor: 1.0
+            })
+            .is_err());
+        assert_eq!(aec.metrics(), None);
+    }
+}
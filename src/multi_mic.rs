@@ -0,0 +1,232 @@
+use alloc::vec::Vec;
+
+use crate::fdaf::FdafCore;
+use crate::storage::Heap;
+use crate::{ConfigError, DefaultFft, FdafAecConfig, FftBackend, Float};
+
+/// Implements an Acoustic Echo Canceller for a microphone array that shares one far-end
+/// reference.
+///
+/// Running one [`FdafAec`](crate::FdafAec) per microphone repeats the far-end FFT and PSD
+/// update for every channel, although they only depend on the far-end signal. This
+/// canceller analyses the far end once per frame and adapts one set of weights per
+/// microphone against it. Every microphone's output is identical to that of a separate
+/// `FdafAec` with the same parameters.
+///
+/// Like [`FdafAec`](crate::FdafAec), it computes in the sample type `T` on the
+/// [`FftBackend`] `F`.
+#[derive(Clone)]
+pub struct MultiMicFdafAec<
+    const FFT_SIZE: usize,
+    const MICS: usize,
+    T: Float = f32,
+    F: FftBackend<T> = DefaultFft<T, FFT_SIZE>,
+> {
+    /// One canceller per microphone. The first one analyses the far end for all of them.
+    cores: Vec<FdafCore<T, F, Heap>>,
+}
+
+impl<const FFT_SIZE: usize, const MICS: usize, T: Float, F: FftBackend<T>>
+    MultiMicFdafAec<FFT_SIZE, MICS, T, F>
+{
+    pub const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Creates a new `MultiMicFdafAec` instance.
+    ///
+    /// The parameters have the same meaning as in [`FdafAec::new`](crate::FdafAec::new) and
+    /// apply to every microphone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any parameter is invalid; use [`FdafAecConfig::try_build_multi_mic`] to
+    /// handle invalid parameters without panicking.
+    pub fn new(
+        step_size: f32,
+        smoothing_factor: f32,
+        regularization_factor: f32,
+        leak: f32,
+    ) -> Self {
+        FdafAecConfig::new()
+            .step_size(step_size)
+            .smoothing_factor(smoothing_factor)
+            .regularization_factor(regularization_factor)
+            .leak(leak)
+            .try_build_multi_mic_as()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Fails the build of a canceller whose FFT backend is fixed to another size.
+    const BACKEND_FITS: () = assert!(
+        match F::FIXED_SIZE {
+            Some(size) => size == FFT_SIZE,
+            None => true,
+        },
+        "the FFT backend is built for another FFT size"
+    );
+
+    /// Creates the canceller from an already validated configuration.
+    pub(crate) fn from_config(config: FdafAecConfig) -> Self {
+        let () = Self::BACKEND_FITS;
+        Self {
+            cores: (0..MICS).map(|_| FdafCore::new(FFT_SIZE, config)).collect(),
+        }
+    }
+
+    /// Returns the step size (mu).
+    pub fn step_size(&self) -> f32 {
+        self.cores[0].step_size()
+    }
+
+    /// Sets the step size (mu) of every microphone, taking effect from the next frame.
+    ///
+    /// See [`FdafAec::set_step_size`](crate::FdafAec::set_step_size).
+    pub fn set_step_size(&mut self, step_size: f32) -> Result<(), ConfigError> {
+        self.cores
+            .iter_mut()
+            .try_for_each(|core| core.set_step_size(step_size))
+    }
+
+    /// Returns the smoothing factor of the far-end PSD estimate.
+    pub fn smoothing_factor(&self) -> f32 {
+        self.cores[0].smoothing_factor()
+    }
+
+    /// Sets the smoothing factor of the far-end PSD estimate, taking effect from the next
+    /// frame.
+    ///
+    /// See [`FdafAec::set_smoothing_factor`](crate::FdafAec::set_smoothing_factor).
+    pub fn set_smoothing_factor(&mut self, smoothing_factor: f32) -> Result<(), ConfigError> {
+        self.cores
+            .iter_mut()
+            .try_for_each(|core| core.set_smoothing_factor(smoothing_factor))
+    }
+
+    /// Returns the regularization added to the PSD before normalizing the gradient.
+    pub fn regularization_factor(&self) -> f32 {
+        self.cores[0].regularization_factor()
+    }
+
+    /// Sets the regularization added to the PSD before normalizing the gradient, taking effect
+    /// from the next frame.
+    ///
+    /// Must be finite and non-negative; otherwise returns
+    /// [`ConfigError::InvalidRegularizationFactor`]. See
+    /// [`FdafAec::set_regularization_factor`](crate::FdafAec::set_regularization_factor).
+    pub fn set_regularization_factor(
+        &mut self,
+        regularization_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.cores
+            .iter_mut()
+            .try_for_each(|core| core.set_regularization_factor(regularization_factor))
+    }
+
+    /// Returns the leak applied to the weights on every update.
+    pub fn leak(&self) -> f32 {
+        self.cores[0].leak()
+    }
+
+    /// Sets the leak applied to the weights on every update, taking effect from the next frame.
+    ///
+    /// See [`FdafAec::set_leak`](crate::FdafAec::set_leak).
+    pub fn set_leak(&mut self, leak: f32) -> Result<(), ConfigError> {
+        self.cores
+            .iter_mut()
+            .try_for_each(|core| core.set_leak(leak))
+    }
+
+    /// Clears the adapted weights of every microphone, keeping the far-end PSD and history.
+    pub fn reset_weights(&mut self) {
+        for core in &mut self.cores {
+            core.reset_weights();
+        }
+    }
+
+    /// Returns the canceller to its freshly constructed state, keeping the parameters. Does
+    /// not allocate.
+    pub fn reset(&mut self) {
+        for core in &mut self.cores {
+            core.reset();
+        }
+    }
+
+    /// Processes a frame of audio data to remove the echo from every microphone.
+    ///
+    /// This follows the same Overlap-Save contract as [`FdafAec::process`](crate::FdafAec::process),
+    /// with one microphone and one error frame per microphone, and does not allocate.
+    pub fn process<const FRAME_SIZE: usize>(
+        &mut self,
+        error_signals: &mut [[T; FRAME_SIZE]; MICS],
+        far_end_frame: &[T; FRAME_SIZE],
+        mic_frames: &[[T; FRAME_SIZE]; MICS],
+    ) {
+        assert_eq!(FRAME_SIZE, FFT_SIZE / 2);
+        // Construction rejects an array without microphones.
+        let (leader, followers) = self.cores.split_first_mut().unwrap();
+        leader.process(&mut error_signals[0], far_end_frame, &mic_frames[0]);
+        for ((core, error_signal), mic_frame) in followers
+            .iter_mut()
+            .zip(&mut error_signals[1..])
+            .zip(&mic_frames[1..])
+        {
+            core.process_shared(leader, error_signal, mic_frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::{FdafAec, FixedFft};
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    fn check_matches_separate_cancellers<F: FftBackend<f32>>() {
+        let far_end = noise(FRAME_SIZE * 30, 101);
+        let near_end = noise(FRAME_SIZE * 30, 102);
+        // Three microphones with different echo paths and near-end levels.
+        let paths = [(0.5, 20), (-0.3, 47), (0.8, 3)];
+        let mics: Vec<Vec<f32>> = paths
+            .iter()
+            .enumerate()
+            .map(|(m, &(gain, delay))| {
+                (0..far_end.len())
+                    .map(|i| {
+                        let echo = if i >= delay {
+                            gain * far_end[i - delay]
+                        } else {
+                            0.0
+                        };
+                        echo + 0.01 * m as f32 * near_end[i]
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut multi = MultiMicFdafAec::<FFT_SIZE, 3, f32, F>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut separate: [FdafAec<FFT_SIZE, f32, F>; 3] =
+            core::array::from_fn(|_| FdafAec::new(0.5, 0.9, 10e-4, 10e-4));
+        for frame in 0..far_end.len() / FRAME_SIZE {
+            let range = frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE;
+            let far = far_end[range.clone()].first_chunk().unwrap();
+            let mic_frames: [[f32; FRAME_SIZE]; 3] =
+                core::array::from_fn(|m| *mics[m][range.clone()].first_chunk().unwrap());
+
+            let mut actual = [[0.0; FRAME_SIZE]; 3];
+            multi.process(&mut actual, far, &mic_frames);
+            for ((aec, mic), actual) in separate.iter_mut().zip(&mic_frames).zip(&actual) {
+                let mut expected = [0.0; FRAME_SIZE];
+                aec.process(&mut expected, far, mic);
+                assert_eq!(actual, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_separate_cancellers() {
+        check_matches_separate_cancellers::<DefaultFft<f32, FFT_SIZE>>();
+        check_matches_separate_cancellers::<FixedFft<f32, FFT_SIZE>>();
+    }
+}
@@ -0,0 +1,597 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::{
+    check_leak, check_regularization_factor, check_smoothing_factor, check_step_size,
+};
+use crate::{
+    constrain_gradient, float, leaky_update, ConfigError, DefaultFft, FdafAecConfig, FftBackend,
+    Float,
+};
+
+/// Fraction by which the off-diagonal (cross-channel) entries of the cross-spectral matrix
+/// are shrunk before it is inverted. Strongly correlated channels make the matrix nearly
+/// singular; shrinking bounds the gain of its weakest direction to about the inverse of
+/// this fraction, which keeps the constrained update stable.
+const CROSS_SPECTRUM_SHRINKAGE: f32 = 0.05;
+
+/// Configuration of the decorrelation pre-processor of a [`MultichannelFdafAec`].
+///
+/// The pre-processor adds a half-wave rectified copy of every channel to itself, using the
+/// positive half-wave on even and the negative half-wave on odd channels. The nonlinearity
+/// is barely audible but makes the channels less linearly related, which is what lets the
+/// canceller identify the individual echo paths.
+///
+/// See [`MultichannelFdafAec::enable_decorrelation`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecorrelationConfig {
+    /// The amount of the rectified signal added to every channel, in `[0, 1]`. Larger values
+    /// decorrelate more at the cost of audible distortion; `0.5` is a common upper bound for
+    /// speech.
+    pub strength: f32,
+}
+
+impl Default for DecorrelationConfig {
+    fn default() -> Self {
+        Self { strength: 0.3 }
+    }
+}
+
+impl DecorrelationConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.strength) {
+            return Err(ConfigError::InvalidDecorrelationStrength(self.strength));
+        }
+        Ok(())
+    }
+}
+
+/// Implements an Acoustic Echo Canceller for a multichannel far-end reference, e.g. a
+/// stereo loudspeaker setup, using the multichannel Overlap-Save FDAF.
+///
+/// Every loudspeaker channel has its own echo path, so the canceller adapts one set of
+/// weights per channel and estimates the echo as the sum of all channels' contributions.
+/// The channels of a far-end signal are usually strongly correlated (the same talker is
+/// picked up by every microphone of the far-end room), which makes normalizing every
+/// channel by its own PSD converge very slowly. Instead, the gradient of every bin is
+/// normalized jointly with the inverse of the regularized inter-channel cross-spectral
+/// matrix.
+///
+/// If the channels are linearly related, the individual echo paths cannot be identified
+/// at all, only a combination that is valid as long as the relation holds (the
+/// non-uniqueness problem). The optional [decorrelation](Self::enable_decorrelation)
+/// pre-processor addresses this.
+///
+/// Like [`FdafAec`](crate::FdafAec), it computes in the sample type `T` on the
+/// [`FftBackend`] `F`. It does not share the single-channel core, and so offers none of the
+/// `enable_*` stages of `FdafAec`: the joint normalization couples the updates of all
+/// channels in every bin, while those stages each assume one weight and one PSD per bin.
+#[derive(Clone)]
+pub struct MultichannelFdafAec<
+    const FFT_SIZE: usize,
+    const CHANNELS: usize,
+    T: Float = f32,
+    F: FftBackend<T> = DefaultFft<T, FFT_SIZE>,
+> {
+    fft: F,
+    weights: Vec<Vec<Complex<T>>>,
+    far_end_buffers: Vec<Vec<T>>,
+    far_end_spectra: Vec<Vec<Complex<T>>>,
+    /// The smoothed cross-spectral matrix `conj(X_i) X_j` of the channels, one per bin.
+    cross_psd: Vec<[[Complex<T>; CHANNELS]; CHANNELS]>,
+    y_f: Vec<Complex<T>>,
+    e_t: Vec<T>,
+    e_f: Vec<Complex<T>>,
+    gradients: Vec<Vec<Complex<T>>>,
+    time_scratch: Vec<T>,
+    mu: f32,
+    smoothing_factor: f32,
+    regularization_factor: f32,
+    leak: f32,
+    decorrelation: Option<DecorrelationConfig>,
+}
+
+impl<const FFT_SIZE: usize, const CHANNELS: usize, T: Float, F: FftBackend<T>>
+    MultichannelFdafAec<FFT_SIZE, CHANNELS, T, F>
+{
+    pub const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Creates a new `MultichannelFdafAec` instance.
+    ///
+    /// The parameters have the same meaning as in [`FdafAec::new`](crate::FdafAec::new).
+    ///
+    /// # Panics
+    ///
+    /// Panics if any parameter is invalid; use [`FdafAecConfig::try_build_multichannel`] to
+    /// handle invalid parameters without panicking.
+    pub fn new(
+        step_size: f32,
+        smoothing_factor: f32,
+        regularization_factor: f32,
+        leak: f32,
+    ) -> Self {
+        FdafAecConfig::new()
+            .step_size(step_size)
+            .smoothing_factor(smoothing_factor)
+            .regularization_factor(regularization_factor)
+            .leak(leak)
+            .try_build_multichannel_as()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Fails the build of a canceller whose FFT backend is fixed to another size.
+    const BACKEND_FITS: () = assert!(
+        match F::FIXED_SIZE {
+            Some(size) => size == FFT_SIZE,
+            None => true,
+        },
+        "the FFT backend is built for another FFT size"
+    );
+
+    /// Creates the canceller from an already validated configuration.
+    pub(crate) fn from_config(config: FdafAecConfig) -> Self {
+        let () = Self::BACKEND_FITS;
+        let bins = FFT_SIZE / 2 + 1;
+
+        let spectrum = vec![Complex::zero(); bins];
+        Self {
+            fft: F::new(FFT_SIZE),
+            weights: vec![spectrum.clone(); CHANNELS],
+            far_end_buffers: vec![vec![T::zero(); FFT_SIZE]; CHANNELS],
+            far_end_spectra: vec![spectrum.clone(); CHANNELS],
+            // Initialize with the identity to avoid division by zero
+            cross_psd: vec![identity(); bins],
+            y_f: spectrum.clone(),
+            e_t: vec![T::zero(); FFT_SIZE],
+            e_f: spectrum.clone(),
+            gradients: vec![spectrum; CHANNELS],
+            time_scratch: vec![T::zero(); FFT_SIZE],
+            mu: config.step_size,
+            smoothing_factor: config.smoothing_factor,
+            regularization_factor: config.regularization_factor,
+            leak: config.leak,
+            decorrelation: None,
+        }
+    }
+
+    /// Returns the step size (mu).
+    pub fn step_size(&self) -> f32 {
+        self.mu
+    }
+
+    /// Sets the step size (mu), taking effect from the next frame.
+    ///
+    /// See [`FdafAec::set_step_size`](crate::FdafAec::set_step_size).
+    pub fn set_step_size(&mut self, step_size: f32) -> Result<(), ConfigError> {
+        self.mu = check_step_size(step_size)?;
+        Ok(())
+    }
+
+    /// Returns the smoothing factor of the far-end cross-spectrum estimate.
+    pub fn smoothing_factor(&self) -> f32 {
+        self.smoothing_factor
+    }
+
+    /// Sets the smoothing factor of the far-end cross-spectrum estimate, taking effect from the
+    /// next frame.
+    ///
+    /// See [`FdafAec::set_smoothing_factor`](crate::FdafAec::set_smoothing_factor).
+    pub fn set_smoothing_factor(&mut self, smoothing_factor: f32) -> Result<(), ConfigError> {
+        self.smoothing_factor = check_smoothing_factor(smoothing_factor)?;
+        Ok(())
+    }
+
+    /// Returns the regularization added to the diagonal of the cross-spectral matrix before
+    /// normalizing the gradient.
+    pub fn regularization_factor(&self) -> f32 {
+        self.regularization_factor
+    }
+
+    /// Sets the regularization added to the diagonal of the cross-spectral matrix, taking
+    /// effect from the next frame.
+    ///
+    /// Must be finite and non-negative; otherwise returns
+    /// [`ConfigError::InvalidRegularizationFactor`]. See
+    /// [`FdafAec::set_regularization_factor`](crate::FdafAec::set_regularization_factor).
+    pub fn set_regularization_factor(
+        &mut self,
+        regularization_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.regularization_factor = check_regularization_factor(regularization_factor)?;
+        Ok(())
+    }
+
+    /// Returns the leak applied to the weights on every update.
+    pub fn leak(&self) -> f32 {
+        self.leak
+    }
+
+    /// Sets the leak applied to the weights on every update, taking effect from the next frame.
+    ///
+    /// See [`FdafAec::set_leak`](crate::FdafAec::set_leak).
+    pub fn set_leak(&mut self, leak: f32) -> Result<(), ConfigError> {
+        self.leak = check_leak(leak)?;
+        Ok(())
+    }
+
+    /// Enables the decorrelation pre-processor.
+    ///
+    /// The pre-processor changes the far-end signal, so it has to run before playback:
+    /// pass every far-end frame through [`decorrelate`](Self::decorrelate), then play it
+    /// and hand the same frame to [`process`](Self::process).
+    ///
+    /// A strength outside `[0, 1]` is rejected with
+    /// [`ConfigError::InvalidDecorrelationStrength`].
+    pub fn enable_decorrelation(&mut self, config: DecorrelationConfig) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.decorrelation = Some(config);
+        Ok(())
+    }
+
+    /// Disables the decorrelation pre-processor, so [`decorrelate`](Self::decorrelate)
+    /// leaves the far-end signal unchanged.
+    pub fn disable_decorrelation(&mut self) {
+        self.decorrelation = None;
+    }
+
+    /// Applies the decorrelation pre-processor to the far-end frames of all channels in
+    /// place, if it is enabled.
+    pub fn decorrelate<const FRAME_SIZE: usize>(
+        &self,
+        far_end_frames: &mut [[T; FRAME_SIZE]; CHANNELS],
+    ) {
+        let Some(config) = self.decorrelation else {
+            return;
+        };
+        let half: T = float(0.5 * config.strength);
+        for (channel, frame) in far_end_frames.iter_mut().enumerate() {
+            let sign = if channel % 2 == 0 {
+                T::one()
+            } else {
+                -T::one()
+            };
+            for x in frame.iter_mut() {
+                *x += half * (*x + sign * x.abs());
+            }
+        }
+    }
+
+    /// Clears the adapted weights of every channel, keeping the cross-spectrum and history.
+    pub fn reset_weights(&mut self) {
+        for weights in &mut self.weights {
+            weights.fill(Complex::zero());
+        }
+    }
+
+    /// Returns the canceller to its freshly constructed state, keeping the parameters and
+    /// the decorrelation setting. Does not allocate.
+    pub fn reset(&mut self) {
+        self.reset_weights();
+        for buffer in &mut self.far_end_buffers {
+            buffer.fill(T::zero());
+        }
+        self.cross_psd.fill(identity());
+    }
+
+    /// Processes a frame of audio data to remove the echo of every far-end channel.
+    ///
+    /// This follows the same Overlap-Save contract as [`FdafAec::process`](crate::FdafAec::process),
+    /// with one far-end frame per channel, and does not allocate.
+    pub fn process<const FRAME_SIZE: usize>(
+        &mut self,
+        error_signal: &mut [T; FRAME_SIZE],
+        far_end_frames: &[[T; FRAME_SIZE]; CHANNELS],
+        mic_frame: &[T; FRAME_SIZE],
+    ) {
+        assert_eq!(FRAME_SIZE, FFT_SIZE / 2);
+
+        // 1 & 2. Update the far-end buffer of every channel and take its FFT
+        for ((buffer, spectrum), frame) in self
+            .far_end_buffers
+            .iter_mut()
+            .zip(self.far_end_spectra.iter_mut())
+            .zip(far_end_frames)
+        {
+            buffer.as_mut_slice().copy_within(FRAME_SIZE.., 0);
+            buffer[FRAME_SIZE..].copy_from_slice(frame);
+            self.fft.forward(buffer.as_slice(), spectrum.as_mut_slice());
+        }
+
+        // 3. Update the cross-spectral matrix of every bin
+        let a: T = float(self.smoothing_factor);
+        for (k, cross_psd) in self.cross_psd.iter_mut().enumerate() {
+            for (i, row) in cross_psd.iter_mut().enumerate() {
+                let x_i = self.far_end_spectra[i][k];
+                for (j, r) in row.iter_mut().enumerate() {
+                    let x_j = self.far_end_spectra[j][k];
+                    *r = *r * a + x_i.conj() * x_j * (T::one() - a);
+                }
+            }
+        }
+
+        // 4. Estimate echo in frequency domain by summing the contribution of every channel
+        self.y_f.fill(Complex::zero());
+        for (weights, x_f) in self.weights.iter().zip(&self.far_end_spectra) {
+            for ((y, w), x) in self.y_f.iter_mut().zip(weights.iter()).zip(x_f.iter()) {
+                *y += w * x;
+            }
+        }
+
+        // 5. Inverse FFT of the estimated echo
+        self.fft
+            .inverse(self.y_f.as_slice(), &mut self.time_scratch);
+
+        // 6 & 7. Keep the valid part of the convolution and calculate the error signal
+        let scale = T::one() / float(FFT_SIZE as f64);
+        for ((e, mic), y) in error_signal
+            .iter_mut()
+            .zip(mic_frame.iter())
+            .zip(&self.time_scratch[FRAME_SIZE..])
+        {
+            *e = *mic - *y * scale;
+        }
+
+        // 8. FFT of the zero-padded error signal for weight update
+        let (padding, frame) = self.e_t.split_at_mut(FRAME_SIZE);
+        padding.fill(T::zero());
+        frame.copy_from_slice(error_signal);
+        self.fft.forward(&self.e_t, self.e_f.as_mut_slice());
+
+        // 9. Gradient of every channel, jointly normalized by the inverse of the regularized
+        // cross-spectral matrix
+        let regularization: T = float(self.regularization_factor);
+        let shrinkage = T::one() - float(CROSS_SPECTRUM_SHRINKAGE);
+        for (k, (cross_psd, e)) in self.cross_psd.iter().zip(self.e_f.iter()).enumerate() {
+            let mut matrix = *cross_psd;
+            let mut gradient = [Complex::zero(); CHANNELS];
+            for (c, g) in gradient.iter_mut().enumerate() {
+                *g = self.far_end_spectra[c][k].conj() * e;
+                for (j, r) in matrix[c].iter_mut().enumerate() {
+                    if j == c {
+                        *r += regularization;
+                    } else {
+                        *r *= shrinkage;
+                    }
+                }
+            }
+            solve_hermitian(&mut matrix, &mut gradient);
+            for (c, g) in gradient.into_iter().enumerate() {
+                self.gradients[c][k] = g;
+            }
+        }
+
+        // 10. Constrain and apply the update of every channel
+        let mu = float(self.mu);
+        for (weights, gradient) in self.weights.iter_mut().zip(self.gradients.iter_mut()) {
+            constrain_gradient(
+                &mut self.fft,
+                gradient.as_mut_slice(),
+                &mut self.time_scratch,
+            );
+            leaky_update(weights.as_mut_slice(), gradient.as_slice(), mu, self.leak);
+        }
+    }
+}
+
+fn identity<T: Float, const N: usize>() -> [[Complex<T>; N]; N] {
+    let mut matrix = [[Complex::zero(); N]; N];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        row[i] = Complex::new(T::one(), T::zero());
+    }
+    matrix
+}
+
+/// Solves `matrix * x = rhs` in place for a Hermitian positive definite `matrix`, leaving
+/// the solution in `rhs`.
+///
+/// Gaussian elimination needs no pivoting for such matrices, which the regularized
+/// cross-spectral matrices always are.
+fn solve_hermitian<T: Float, const N: usize>(
+    matrix: &mut [[Complex<T>; N]; N],
+    rhs: &mut [Complex<T>; N],
+) {
+    for pivot in 0..N {
+        let inverse = matrix[pivot][pivot].inv();
+        let (upper, lower) = matrix.split_at_mut(pivot + 1);
+        let pivot_row = &upper[pivot];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[pivot] * inverse;
+            for (r, p) in row[pivot..].iter_mut().zip(&pivot_row[pivot..]) {
+                *r -= factor * p;
+            }
+            let value = rhs[pivot];
+            rhs[pivot + 1 + offset] -= factor * value;
+        }
+    }
+    for pivot in (0..N).rev() {
+        let solved: Complex<T> = matrix[pivot][pivot + 1..]
+            .iter()
+            .zip(&rhs[pivot + 1..])
+            .map(|(m, x)| m * x)
+            .sum();
+        rhs[pivot] = (rhs[pivot] - solved) / matrix[pivot][pivot];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{energy, noise};
+    use crate::FdafAec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Runs a stereo canceller, passing the far end through the decorrelation pre-processor,
+    /// and returns the output together with the microphone signal the echo paths produce
+    /// from the pre-processed far end.
+    fn run_stereo(
+        aec: &mut MultichannelFdafAec<FFT_SIZE, 2>,
+        far_end: [&[f32]; 2],
+        echo_paths: impl Fn(usize, [&[f32]; 2]) -> f32,
+    ) -> (Vec<f32>, Vec<f32>) {
+        let mut played = [Vec::new(), Vec::new()];
+        let mut mic = Vec::new();
+        let mut output = Vec::new();
+        for frame in 0..far_end[0].len() / FRAME_SIZE {
+            let range = frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE;
+            let mut frames = [
+                *far_end[0][range.clone()].first_chunk().unwrap(),
+                *far_end[1][range.clone()].first_chunk().unwrap(),
+            ];
+            aec.decorrelate(&mut frames);
+            played[0].extend_from_slice(&frames[0]);
+            played[1].extend_from_slice(&frames[1]);
+            let mic_frame: [f32; FRAME_SIZE] =
+                core::array::from_fn(|i| echo_paths(range.start + i, [&played[0], &played[1]]));
+            let mut error = [0.0; FRAME_SIZE];
+            aec.process(&mut error, &frames, &mic_frame);
+            mic.extend_from_slice(&mic_frame);
+            output.extend_from_slice(&error);
+        }
+        (output, mic)
+    }
+
+    #[test]
+    fn single_channel_matches_fdaf() {
+        let mut fdaf = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut multichannel = MultichannelFdafAec::<FFT_SIZE, 1>::new(0.5, 0.9, 10e-4, 10e-4);
+
+        let far_end = noise(FRAME_SIZE * 20, 91);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 40 { 0.5 * far_end[i - 40] } else { 0.0 })
+            .collect();
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let mut expected = [0.0; FRAME_SIZE];
+            let mut actual = [0.0; FRAME_SIZE];
+            let far = far.first_chunk().unwrap();
+            let mic = mic.first_chunk().unwrap();
+            fdaf.process(&mut expected, far, mic);
+            multichannel.process(&mut actual, &[*far], mic);
+            for (a, b) in actual.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-5, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn cancels_correlated_stereo_echo() {
+        let frames = 100;
+        // A single far-end talker picked up by both far-end microphones, plus a little
+        // independent noise per channel.
+        let talker = noise(FRAME_SIZE * frames, 93);
+        let left_noise = noise(FRAME_SIZE * frames, 94);
+        let right_noise = noise(FRAME_SIZE * frames, 95);
+        let left: Vec<f32> = (0..talker.len())
+            .map(|i| talker[i] + 0.05 * left_noise[i])
+            .collect();
+        let right: Vec<f32> = (0..talker.len())
+            .map(|i| 0.7 * talker[i.saturating_sub(6)] + 0.05 * right_noise[i])
+            .collect();
+        let echo_paths = |i: usize, played: [&[f32]; 2]| {
+            let tap = |x: &[f32], delay: usize| if i >= delay { x[i - delay] } else { 0.0 };
+            0.5 * tap(played[0], 20) - 0.2 * tap(played[0], 33) + 0.4 * tap(played[1], 45)
+        };
+
+        let mut aec = MultichannelFdafAec::<FFT_SIZE, 2>::new(0.5, 0.9, 10e-4, 10e-4);
+        let (output, mic) = run_stereo(&mut aec, [&left, &right], echo_paths);
+        assert!(output.iter().all(|x| x.is_finite()));
+        let tail = FRAME_SIZE * (frames - 20)..;
+        let erle = energy(&mic[tail.clone()]) / energy(&output[tail]);
+        assert!(erle > 300.0, "ERLE too low: {erle}");
+    }
+
+    #[test]
+    fn decorrelation_identifies_the_individual_echo_paths() {
+        let frames = 160;
+        let change = FRAME_SIZE * 120;
+        // The far-end talker moves after 120 frames, which changes the relation of the
+        // channels while the echo paths stay the same.
+        let talker = noise(FRAME_SIZE * frames, 96);
+        let left = talker.clone();
+        let right: Vec<f32> = (0..talker.len())
+            .map(|i| {
+                let (gain, delay) = if i < change { (0.8, 4) } else { (-0.5, 11) };
+                gain * talker[i.saturating_sub(delay)]
+            })
+            .collect();
+        let echo_paths = |i: usize, played: [&[f32]; 2]| {
+            let tap = |x: &[f32], delay: usize| if i >= delay { x[i - delay] } else { 0.0 };
+            0.5 * tap(played[0], 20) + 0.4 * tap(played[1], 45)
+        };
+
+        let mut plain = MultichannelFdafAec::<FFT_SIZE, 2>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut decorrelated = plain.clone();
+        decorrelated
+            .enable_decorrelation(DecorrelationConfig::default())
+            .unwrap();
+        let (plain_out, plain_mic) = run_stereo(&mut plain, [&left, &right], echo_paths);
+        let (decorrelated_out, decorrelated_mic) =
+            run_stereo(&mut decorrelated, [&left, &right], echo_paths);
+
+        // Right after the talker moved, the plainly identified echo paths no longer fit.
+        let after = change..change + FRAME_SIZE * 4;
+        let plain_erle = energy(&plain_mic[after.clone()]) / energy(&plain_out[after.clone()]);
+        let decorrelated_erle =
+            energy(&decorrelated_mic[after.clone()]) / energy(&decorrelated_out[after]);
+        assert!(
+            decorrelated_erle > 10.0 * plain_erle,
+            "{decorrelated_erle} <= 10 * {plain_erle}"
+        );
+    }
+
+    #[test]
+    fn f64_matches_f32() {
+        let mut single = MultichannelFdafAec::<FFT_SIZE, 2>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut double = MultichannelFdafAec::<FFT_SIZE, 2, f64>::new(0.5, 0.9, 10e-4, 10e-4);
+        let left = noise(FRAME_SIZE * 30, 97);
+        let right = noise(FRAME_SIZE * 30, 98);
+        for frame in 0..left.len() / FRAME_SIZE {
+            let range = frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE;
+            let far: [[f32; FRAME_SIZE]; 2] = [
+                *left[range.clone()].first_chunk().unwrap(),
+                *right[range.clone()].first_chunk().unwrap(),
+            ];
+            let mic: [f32; FRAME_SIZE] =
+                core::array::from_fn(|i| 0.5 * far[0][i] - 0.3 * far[1][i]);
+            let mut expected = [0.0; FRAME_SIZE];
+            let mut actual = [0.0; FRAME_SIZE];
+            single.process(&mut expected, &far, &mic);
+            double.process(
+                &mut actual,
+                &far.map(|frame| frame.map(f64::from)),
+                &mic.map(f64::from),
+            );
+            for (a, b) in actual.iter().zip(&expected) {
+                assert!((a - f64::from(*b)).abs() < 1e-4, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_decorrelation() {
+        let mut aec = MultichannelFdafAec::<FFT_SIZE, 2>::new(0.5, 0.9, 10e-4, 10e-4);
+        for strength in [-0.1, 1.5] {
+            assert_eq!(
+                aec.enable_decorrelation(DecorrelationConfig { strength }),
+                Err(ConfigError::InvalidDecorrelationStrength(strength))
+            );
+        }
+        assert!(matches!(
+            aec.enable_decorrelation(DecorrelationConfig { strength: f32::NAN }),
+            Err(ConfigError::InvalidDecorrelationStrength(_))
+        ));
+
+        // The far end still passes the pre-processor unchanged.
+        let mut frames = [[0.5; FRAME_SIZE], [-0.5; FRAME_SIZE]];
+        aec.decorrelate(&mut frames);
+        assert_eq!(frames, [[0.5; FRAME_SIZE], [-0.5; FRAME_SIZE]]);
+    }
+}
@@ -0,0 +1,410 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::{
+    check_leak, check_regularization_factor, check_smoothing_factor, check_step_size,
+};
+#[cfg(feature = "rustfft")]
+use crate::RustFft;
+use crate::{
+    constrain_gradient, float, leaky_update, normalize_gradient, ConfigError, FdafAecConfig,
+    FftBackend, Float,
+};
+
+/// Implements an Acoustic Echo Canceller using the Multi-Delay block Frequency domain
+/// adaptive Filter (MDF), a partitioned-block variant of the Overlap-Save FDAF.
+///
+/// Where [`FdafAec`](crate::FdafAec) ties the echo tail length to its FFT size, this
+/// canceller splits a filter of `BLOCK * PARTITIONS` taps into `PARTITIONS` frequency-domain
+/// partitions of `BLOCK` taps each. Every partition is applied to the far-end spectrum of a
+/// progressively older block, so the algorithmic latency stays at one `BLOCK` while the
+/// covered tail grows with `PARTITIONS`.
+///
+/// It computes in the sample type `T` on the [`FftBackend`] `F`, which default to `f32` and
+/// [`RustFft`](crate::RustFft). Without the `rustfft` feature both must be named, e.g.
+/// `PartitionedFdafAec<64, 8, f32, FixedFft<f32, 128>>`: the FFT size `2 * BLOCK` cannot
+/// parameterize a default [`FixedFft`](crate::FixedFft) on stable Rust.
+#[derive(Clone)]
+pub struct PartitionedFdafAec<
+    const BLOCK: usize,
+    const PARTITIONS: usize,
+    #[cfg(feature = "rustfft")] T: Float = f32,
+    #[cfg(feature = "rustfft")] F: FftBackend<T> = RustFft<T>,
+    #[cfg(not(feature = "rustfft"))] T: Float,
+    #[cfg(not(feature = "rustfft"))] F: FftBackend<T>,
+> {
+    fft: F,
+    weights: Vec<Vec<Complex<T>>>,
+    far_end_spectra: Vec<Vec<Complex<T>>>,
+    newest: usize,
+    far_end_buffer: Vec<T>,
+    y_f: Vec<Complex<T>>,
+    e_t: Vec<T>,
+    e_f: Vec<Complex<T>>,
+    gradient: Vec<Complex<T>>,
+    time_scratch: Vec<T>,
+    psd: Vec<T>,
+    mu: f32,
+    smoothing_factor: f32,
+    regularization_factor: f32,
+    leak: f32,
+}
+
+impl<const BLOCK: usize, const PARTITIONS: usize, T: Float, F: FftBackend<T>>
+    PartitionedFdafAec<BLOCK, PARTITIONS, T, F>
+{
+    /// The size of the FFT used for each partition.
+    pub const FFT_SIZE: usize = 2 * BLOCK;
+    /// The total number of filter taps, i.e. the longest echo tail that can be modelled.
+    pub const TAIL_LENGTH: usize = BLOCK * PARTITIONS;
+
+    /// Creates a new `PartitionedFdafAec` instance.
+    ///
+    /// The parameters have the same meaning as in [`FdafAec::new`](crate::FdafAec::new).
+    /// The PSD used for normalization is accumulated over all partitions, so the same
+    /// `step_size` gives a comparable convergence behaviour regardless of `PARTITIONS`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any parameter is invalid; use [`FdafAecConfig::try_build_partitioned_as`] to
+    /// handle invalid parameters without panicking.
+    pub fn new(
+        step_size: f32,
+        smoothing_factor: f32,
+        regularization_factor: f32,
+        leak: f32,
+    ) -> Self {
+        FdafAecConfig::new()
+            .step_size(step_size)
+            .smoothing_factor(smoothing_factor)
+            .regularization_factor(regularization_factor)
+            .leak(leak)
+            .try_build_partitioned_as()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Fails the build of a canceller whose FFT backend is fixed to another size.
+    const BACKEND_FITS: () = assert!(
+        match F::FIXED_SIZE {
+            Some(size) => size == 2 * BLOCK,
+            None => true,
+        },
+        "the FFT backend is built for another FFT size"
+    );
+
+    /// Creates the canceller from an already validated configuration.
+    pub(crate) fn from_config(config: FdafAecConfig) -> Self {
+        let () = Self::BACKEND_FITS;
+        let fft_size = Self::FFT_SIZE;
+        let bins = fft_size / 2 + 1;
+
+        let spectrum = vec![Complex::zero(); bins];
+        Self {
+            fft: F::new(fft_size),
+            weights: vec![spectrum.clone(); PARTITIONS],
+            far_end_spectra: vec![spectrum.clone(); PARTITIONS],
+            newest: 0,
+            far_end_buffer: vec![T::zero(); fft_size],
+            y_f: spectrum.clone(),
+            e_t: vec![T::zero(); fft_size],
+            e_f: spectrum.clone(),
+            gradient: spectrum,
+            time_scratch: vec![T::zero(); fft_size],
+            psd: vec![T::one(); bins], // Initialize with 1 to avoid division by zero
+            mu: config.step_size,
+            smoothing_factor: config.smoothing_factor,
+            regularization_factor: config.regularization_factor,
+            leak: config.leak,
+        }
+    }
+
+    /// Returns the step size (mu).
+    pub fn step_size(&self) -> f32 {
+        self.mu
+    }
+
+    /// Sets the step size (mu), taking effect from the next block.
+    ///
+    /// See [`FdafAec::set_step_size`](crate::FdafAec::set_step_size).
+    pub fn set_step_size(&mut self, step_size: f32) -> Result<(), ConfigError> {
+        self.mu = check_step_size(step_size)?;
+        Ok(())
+    }
+
+    /// Returns the smoothing factor of the far-end PSD estimate.
+    pub fn smoothing_factor(&self) -> f32 {
+        self.smoothing_factor
+    }
+
+    /// Sets the smoothing factor of the far-end PSD estimate, taking effect from the next
+    /// block.
+    ///
+    /// See [`FdafAec::set_smoothing_factor`](crate::FdafAec::set_smoothing_factor).
+    pub fn set_smoothing_factor(&mut self, smoothing_factor: f32) -> Result<(), ConfigError> {
+        self.smoothing_factor = check_smoothing_factor(smoothing_factor)?;
+        Ok(())
+    }
+
+    /// Returns the regularization added to the PSD before normalizing the gradient.
+    pub fn regularization_factor(&self) -> f32 {
+        self.regularization_factor
+    }
+
+    /// Sets the regularization added to the PSD before normalizing the gradient, taking effect
+    /// from the next block.
+    ///
+    /// Must be finite and non-negative; otherwise returns
+    /// [`ConfigError::InvalidRegularizationFactor`]. See
+    /// [`FdafAec::set_regularization_factor`](crate::FdafAec::set_regularization_factor).
+    pub fn set_regularization_factor(
+        &mut self,
+        regularization_factor: f32,
+    ) -> Result<(), ConfigError> {
+        self.regularization_factor = check_regularization_factor(regularization_factor)?;
+        Ok(())
+    }
+
+    /// Returns the leak applied to the weights on every update.
+    pub fn leak(&self) -> f32 {
+        self.leak
+    }
+
+    /// Sets the leak applied to the weights on every update, taking effect from the next block.
+    ///
+    /// See [`FdafAec::set_leak`](crate::FdafAec::set_leak).
+    pub fn set_leak(&mut self, leak: f32) -> Result<(), ConfigError> {
+        self.leak = check_leak(leak)?;
+        Ok(())
+    }
+
+    /// Clears the adapted weights of every partition, keeping the far-end PSD and history.
+    pub fn reset_weights(&mut self) {
+        for weights in &mut self.weights {
+            weights.fill(Complex::zero());
+        }
+    }
+
+    /// Returns the canceller to its freshly constructed state, keeping the parameters. Does
+    /// not allocate.
+    pub fn reset(&mut self) {
+        self.reset_weights();
+        for spectrum in &mut self.far_end_spectra {
+            spectrum.fill(Complex::zero());
+        }
+        self.newest = 0;
+        self.far_end_buffer.fill(T::zero());
+        self.psd.fill(T::one());
+    }
+
+    /// Processes a block of audio data to remove echo.
+    ///
+    /// This follows the same Overlap-Save contract as [`FdafAec::process`](crate::FdafAec::process):
+    /// every call consumes one block of far-end and microphone samples and produces one block
+    /// of echo-cancelled output, without allocating.
+    pub fn process(
+        &mut self,
+        error_signal: &mut [T; BLOCK],
+        far_end_frame: &[T; BLOCK],
+        mic_frame: &[T; BLOCK],
+    ) {
+        let fft_size = Self::FFT_SIZE;
+
+        // 1. Update far-end buffer (shift old data, add new data)
+        self.far_end_buffer.as_mut_slice().copy_within(BLOCK.., 0);
+        self.far_end_buffer[BLOCK..].copy_from_slice(far_end_frame);
+
+        // 2. FFT of the far-end block into the history slot of the oldest partition
+        self.newest = (self.newest + PARTITIONS - 1) % PARTITIONS;
+        self.fft.forward(
+            self.far_end_buffer.as_slice(),
+            self.far_end_spectra[self.newest].as_mut_slice(),
+        );
+
+        // 3. Update the PSD with the far-end power summed over the whole filter span
+        let far_end_spectra = &self.far_end_spectra;
+        let a: T = float(self.smoothing_factor);
+        for (k, psd) in self.psd.iter_mut().enumerate() {
+            let power = far_end_spectra
+                .iter()
+                .fold(T::zero(), |power, x| power + x[k].norm_sqr());
+            *psd = a * *psd + (T::one() - a) * power;
+        }
+
+        // 4. Estimate echo in frequency domain by summing the contribution of every partition
+        self.y_f.fill(Complex::zero());
+        for p in 0..PARTITIONS {
+            let x_f = &self.far_end_spectra[(self.newest + p) % PARTITIONS];
+            for ((y, w), x) in self
+                .y_f
+                .iter_mut()
+                .zip(self.weights[p].iter())
+                .zip(x_f.iter())
+            {
+                *y += w * x;
+            }
+        }
+
+        // 5. Inverse FFT of the estimated echo
+        self.fft
+            .inverse(self.y_f.as_slice(), &mut self.time_scratch);
+
+        // 6 & 7. Keep the valid part of the convolution and calculate the error signal
+        let scale = T::one() / float(fft_size as f64);
+        for ((e, mic), y) in error_signal
+            .iter_mut()
+            .zip(mic_frame.iter())
+            .zip(&self.time_scratch[BLOCK..])
+        {
+            *e = *mic - *y * scale;
+        }
+
+        // 8. FFT of the zero-padded error signal for weight update
+        let (padding, frame) = self.e_t.split_at_mut(BLOCK);
+        padding.fill(T::zero());
+        frame.copy_from_slice(error_signal);
+        self.fft.forward(&self.e_t, self.e_f.as_mut_slice());
+
+        // 9. Update every partition with its own constrained, normalized gradient
+        let mu = float(self.mu);
+        for p in 0..PARTITIONS {
+            let x_f = &self.far_end_spectra[(self.newest + p) % PARTITIONS];
+            for ((g, x), e) in self
+                .gradient
+                .iter_mut()
+                .zip(x_f.iter())
+                .zip(self.e_f.iter())
+            {
+                *g = x.conj() * e;
+            }
+
+            normalize_gradient(
+                self.gradient.as_mut_slice(),
+                self.psd.as_slice(),
+                self.regularization_factor,
+            );
+
+            constrain_gradient(
+                &mut self.fft,
+                self.gradient.as_mut_slice(),
+                &mut self.time_scratch,
+            );
+
+            leaky_update(
+                self.weights[p].as_mut_slice(),
+                self.gradient.as_slice(),
+                mu,
+                self.leak,
+            );
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{energy, noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn single_partition_matches_fdaf() {
+        const BLOCK: usize = 128;
+        let mut fdaf = FdafAec::<{ 2 * BLOCK }>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut mdf = PartitionedFdafAec::<BLOCK, 1>::new(0.5, 0.9, 10e-4, 10e-4);
+
+        let far_end = noise(BLOCK * 20, 1);
+        let mut mic = vec![0.0; far_end.len()];
+        for i in 40..mic.len() {
+            mic[i] = 0.5 * far_end[i - 40];
+        }
+
+        for (far, mic) in far_end.chunks_exact(BLOCK).zip(mic.chunks_exact(BLOCK)) {
+            let mut expected = [0.0; BLOCK];
+            let mut actual = [0.0; BLOCK];
+            fdaf.process(
+                &mut expected,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            mdf.process(
+                &mut actual,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            for (a, b) in actual.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-6, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn cancels_echo_longer_than_block() {
+        const BLOCK: usize = 64;
+        const DELAY: usize = 300;
+        let mut aec = PartitionedFdafAec::<BLOCK, 8>::new(0.5, 0.9, 10e-4, 10e-4);
+
+        let far_end = noise(BLOCK * 400, 7);
+        let mut mic = vec![0.0; far_end.len()];
+        for i in DELAY..mic.len() {
+            mic[i] = 0.6 * far_end[i - DELAY] - 0.2 * far_end[i - DELAY + 25];
+        }
+
+        let mut output = Vec::with_capacity(mic.len());
+        for (far, mic) in far_end.chunks_exact(BLOCK).zip(mic.chunks_exact(BLOCK)) {
+            let mut error = [0.0; BLOCK];
+            aec.process(
+                &mut error,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            output.extend_from_slice(&error);
+        }
+
+        let tail = mic.len() - BLOCK * 50;
+        let erle = energy(&mic[tail..]) / energy(&output[tail..]);
+        assert!(erle > 100.0, "ERLE too low: {erle}");
+        assert!(output.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_new_with_non_power_of_two_block() {
+        PartitionedFdafAec::<100, 4>::new(0.5, 0.9, 10e-4, 10e-4);
+    }
+
+    #[test]
+    fn fixed_backend_matches_rustfft() {
+        const BLOCK: usize = 64;
+        let mut rustfft = PartitionedFdafAec::<BLOCK, 4>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut fixed =
+            PartitionedFdafAec::<BLOCK, 4, f32, crate::FixedFft<f32, { 2 * BLOCK }>>::new(
+                0.5, 0.9, 10e-4, 10e-4,
+            );
+
+        let far_end = noise(BLOCK * 40, 3);
+        let mut mic = vec![0.0; far_end.len()];
+        for i in 150..mic.len() {
+            mic[i] = 0.5 * far_end[i - 150];
+        }
+
+        for (far, mic) in far_end.chunks_exact(BLOCK).zip(mic.chunks_exact(BLOCK)) {
+            let mut expected = [0.0; BLOCK];
+            let mut actual = [0.0; BLOCK];
+            rustfft.process(
+                &mut expected,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            fixed.process(
+                &mut actual,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            for (a, b) in actual.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-4, "{a} != {b}");
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::check_smoothing_factor;
+use crate::real_fft::FftBackend;
+use crate::{bin_multiplicity, float, ConfigError, Float};
+
+/// Smoothing factor of the echo leakage estimate, which needs a longer memory than the PSDs.
+const LEAKAGE_SMOOTHING: f32 = 0.95;
+
+/// Configuration of the residual echo suppressor post-filter.
+///
+/// See [`FdafAec::enable_residual_echo_suppressor`](crate::FdafAec::enable_residual_echo_suppressor).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResidualEchoSuppressorConfig {
+    /// Factor applied to the residual echo estimate before computing the gain, at least
+    /// `0.0`. Values above `1.0` suppress more aggressively at the cost of near-end
+    /// distortion during double talk.
+    pub over_suppression: f32,
+    /// The lowest gain applied to any bin, in `[0, 1]`, e.g. `0.1` limits the suppression
+    /// to 20 dB.
+    pub gain_floor: f32,
+    /// Smoothing factor of the recursive echo and error PSD estimates, in `[0, 1)`.
+    pub smoothing_factor: f32,
+}
+
+impl Default for ResidualEchoSuppressorConfig {
+    fn default() -> Self {
+        Self {
+            over_suppression: 1.5,
+            gain_floor: 0.1,
+            smoothing_factor: 0.6,
+        }
+    }
+}
+
+impl ResidualEchoSuppressorConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.over_suppression.is_finite() && self.over_suppression >= 0.0) {
+            return Err(ConfigError::InvalidOverSuppression(self.over_suppression));
+        }
+        if !(0.0..=1.0).contains(&self.gain_floor) {
+            return Err(ConfigError::InvalidGainFloor(self.gain_floor));
+        }
+        check_smoothing_factor(self.smoothing_factor)?;
+        Ok(())
+    }
+}
+
+/// A frequency-domain post-filter that attenuates the echo left by the linear filter.
+///
+/// The residual echo in every bin is modelled as a fraction (the leakage) of the estimated
+/// echo power, where the leakage is tracked by regressing the error power spectrum onto the
+/// echo power spectrum. The suppression gain is the Wiener-style ratio of the estimated
+/// near-end power to the error power.
+#[derive(Clone)]
+pub(crate) struct ResidualEchoSuppressor<T: Float> {
+    config: ResidualEchoSuppressorConfig,
+    echo_frame: Vec<T>,
+    echo_spectrum: Vec<Complex<T>>,
+    echo_psd: Vec<T>,
+    error_psd: Vec<T>,
+    echo_error_covariance: T,
+    echo_variance: T,
+}
+
+impl<T: Float> ResidualEchoSuppressor<T> {
+    pub(crate) fn new(config: ResidualEchoSuppressorConfig, fft_size: usize) -> Self {
+        let bins = fft_size / 2 + 1;
+        Self {
+            config,
+            echo_frame: vec![T::zero(); fft_size],
+            echo_spectrum: vec![Complex::zero(); bins],
+            echo_psd: vec![T::zero(); bins],
+            error_psd: vec![T::zero(); bins],
+            echo_error_covariance: T::zero(),
+            echo_variance: T::zero(),
+        }
+    }
+
+    /// Forgets all statistics, as if the suppressor had just been created.
+    pub(crate) fn reset(&mut self) {
+        self.echo_psd.fill(T::zero());
+        self.error_psd.fill(T::zero());
+        self.echo_error_covariance = T::zero();
+        self.echo_variance = T::zero();
+    }
+
+    /// Returns the current leakage estimate, i.e. the fraction of the estimated echo power
+    /// that is assumed to remain in the error signal.
+    pub(crate) fn leakage(&self) -> T {
+        if self.echo_variance > T::zero() {
+            (self.echo_error_covariance / self.echo_variance).clamp(T::zero(), T::one())
+        } else {
+            T::zero()
+        }
+    }
+
+    /// Computes the per-bin gains for this frame and writes the suppressed error spectrum.
+    ///
+    /// `estimated_echo` holds one frame, and `error_spectrum` is the half spectrum of the
+    /// zero-padded error frame used for the weight update.
+    pub(crate) fn process(
+        &mut self,
+        fft: &mut impl FftBackend<T>,
+        estimated_echo: &[T],
+        error_spectrum: &[Complex<T>],
+        output_spectrum: &mut [Complex<T>],
+    ) {
+        let fft_size = self.echo_frame.len();
+        let frame_size = estimated_echo.len();
+
+        // Spectrum of the estimated echo, zero-padded exactly like the error frame.
+        let (padding, frame) = self.echo_frame.split_at_mut(fft_size - frame_size);
+        padding.fill(T::zero());
+        frame.copy_from_slice(estimated_echo);
+        fft.forward(&self.echo_frame, &mut self.echo_spectrum);
+
+        // Smoothed PSDs and the regression of error power onto echo power. The statistics
+        // are taken over the full spectrum, in which every bin but DC and Nyquist appears
+        // twice.
+        let a: T = float(self.config.smoothing_factor);
+        let mut mean_echo = T::zero();
+        let mut mean_error = T::zero();
+        for (k, (((echo_psd, error_psd), y), e)) in self
+            .echo_psd
+            .iter_mut()
+            .zip(self.error_psd.iter_mut())
+            .zip(&self.echo_spectrum)
+            .zip(error_spectrum)
+            .enumerate()
+        {
+            let echo_power = y.norm_sqr();
+            let error_power = e.norm_sqr();
+            *echo_psd = a * *echo_psd + (T::one() - a) * echo_power;
+            *error_psd = a * *error_psd + (T::one() - a) * error_power;
+            let multiplicity: T = bin_multiplicity(k, fft_size);
+            mean_echo += multiplicity * echo_power;
+            mean_error += multiplicity * error_power;
+        }
+        mean_echo /= float(fft_size as f64);
+        mean_error /= float(fft_size as f64);
+
+        let mut covariance = T::zero();
+        let mut variance = T::zero();
+        for (k, (y, e)) in self.echo_spectrum.iter().zip(error_spectrum).enumerate() {
+            let multiplicity: T = bin_multiplicity(k, fft_size);
+            let echo_power = y.norm_sqr() - mean_echo;
+            let error_power = e.norm_sqr() - mean_error;
+            covariance += multiplicity * echo_power * error_power;
+            variance += multiplicity * echo_power * echo_power;
+        }
+        let b: T = float(LEAKAGE_SMOOTHING);
+        self.echo_error_covariance = b * self.echo_error_covariance + (T::one() - b) * covariance;
+        self.echo_variance = b * self.echo_variance + (T::one() - b) * variance;
+        let leakage = self.leakage();
+
+        // Wiener-style gain: estimated near-end power over error power.
+        let over_suppression: T = float(self.config.over_suppression);
+        let gain_floor: T = float(self.config.gain_floor);
+        for (((out, e), &echo_psd), &error_psd) in output_spectrum
+            .iter_mut()
+            .zip(error_spectrum)
+            .zip(&self.echo_psd)
+            .zip(&self.error_psd)
+        {
+            let residual_echo = over_suppression * leakage * echo_psd;
+            let gain = if error_psd > T::zero() {
+                T::one() - residual_echo / error_psd
+            } else {
+                T::one()
+            };
+            *out = e * gain.clamp(gain_floor, T::one());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{energy, noise, run};
+    use crate::FdafAec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn unity_gain_matches_linear_output() {
+        let far_end = noise(FRAME_SIZE * 40, 5);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 10 { 0.4 * far_end[i - 10] } else { 0.0 })
+            .collect();
+
+        let mut linear = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut post_filtered = linear.clone();
+        post_filtered
+            .enable_residual_echo_suppressor(ResidualEchoSuppressorConfig {
+                over_suppression: 0.0,
+                gain_floor: 1.0,
+                smoothing_factor: 0.6,
+            })
+            .unwrap();
+
+        let expected = run(&mut linear, &far_end, &mic);
+        let actual = run(&mut post_filtered, &far_end, &mic);
+        for (a, b) in actual.iter().zip(&expected) {
+            assert!((a - b).abs() < 1e-5, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn suppresses_nonlinear_residual_echo() {
+        let frames = 200;
+        let far_end = noise(FRAME_SIZE * frames, 9);
+        let near_end = noise(FRAME_SIZE * frames, 21);
+        // A saturating loudspeaker leaves echo the linear filter cannot model. The far-end
+        // is silent in the last quarter, where only the near-end talks.
+        let silent_from = FRAME_SIZE * frames * 3 / 4;
+        let mut far_end_played = far_end.clone();
+        far_end_played[silent_from..].fill(0.0);
+        let mut mic = vec![0.0; far_end.len()];
+        for i in 30..mic.len() {
+            let x = far_end_played[i - 30];
+            mic[i] = 0.5 * (3.0 * x).tanh() / 3.0 * 2.0;
+            if i >= silent_from {
+                mic[i] += 0.2 * near_end[i];
+            }
+        }
+
+        let mut linear = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut post_filtered = linear.clone();
+        post_filtered
+            .enable_residual_echo_suppressor(ResidualEchoSuppressorConfig::default())
+            .unwrap();
+
+        let linear_out = run(&mut linear, &far_end_played, &mic);
+        let post_out = run(&mut post_filtered, &far_end_played, &mic);
+
+        let echo_only = FRAME_SIZE * frames / 2..silent_from;
+        let extra_suppression =
+            energy(&linear_out[echo_only.clone()]) / energy(&post_out[echo_only]);
+        assert!(
+            extra_suppression > 4.0,
+            "residual echo only reduced by {extra_suppression}"
+        );
+
+        let near_end_only = silent_from + FRAME_SIZE * 8..mic.len();
+        let near_end_loss = energy(&mic[near_end_only.clone()]) / energy(&post_out[near_end_only]);
+        assert!(
+            near_end_loss < 1.5,
+            "near-end attenuated by {near_end_loss}"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = ResidualEchoSuppressorConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            ResidualEchoSuppressorConfig {
+                over_suppression: -1.0,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidOverSuppression(-1.0))
+        );
+        assert_eq!(
+            ResidualEchoSuppressorConfig {
+                gain_floor: 1.5,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidGainFloor(1.5))
+        );
+    }
+}
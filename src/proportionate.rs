@@ -0,0 +1,254 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+
+use crate::config::check_regularization_factor;
+use crate::real_fft::FftBackend;
+use crate::{float, ConfigError, Float};
+
+/// Configuration of the proportionate (IPNLMS-style) weight update.
+///
+/// Sparse echo paths, a direct path plus a few distinct reflections, concentrate their
+/// energy in a handful of taps. The uniform NLMS update spreads its step evenly over all
+/// taps, so most of it goes into taps that should stay near zero. The proportionate update
+/// gives every tap of the time-domain gradient a gain that grows with the magnitude of the
+/// corresponding filter tap, so the active taps converge much faster.
+///
+/// The gains are normalized to a mean of one, so the overall step size stays that of NLMS.
+/// Unlike the sample-by-sample IPNLMS, a block update applies a tap's whole step at once,
+/// so the gains are limited to the inverse of the step size actually applied, i.e. after
+/// step-size control and double-talk scaling: the step of any single tap never exceeds
+/// one, which keeps the update stable.
+///
+/// See [`FdafAec::enable_proportionate_update`](crate::FdafAec::enable_proportionate_update).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProportionateConfig {
+    /// Balance between the uniform and the proportionate gain, in `[-1, 1]`. `-1.0` is the
+    /// plain NLMS update, `1.0` is purely proportionate (PNLMS). Values around `-0.5` suit
+    /// most echo paths, which are rarely perfectly sparse.
+    pub proportionality: f32,
+    /// Added to the tap magnitudes so the gains stay uniform while the filter is still
+    /// (close to) zero, e.g. right after construction or a reset. Must be finite and
+    /// positive.
+    pub regularization: f32,
+}
+
+impl Default for ProportionateConfig {
+    fn default() -> Self {
+        Self {
+            proportionality: -0.5,
+            regularization: 1e-4,
+        }
+    }
+}
+
+impl ProportionateConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(-1.0..=1.0).contains(&self.proportionality) {
+            return Err(ConfigError::InvalidProportionality(self.proportionality));
+        }
+        // Unlike the PSD regularization, it is the only term keeping the gain normalization
+        // of a zero filter from dividing by zero.
+        if check_regularization_factor(self.regularization)? == 0.0 {
+            return Err(ConfigError::InvalidRegularizationFactor(
+                self.regularization,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The tap gains of the proportionate update.
+#[derive(Clone)]
+pub(crate) struct ProportionateUpdate<T: Float> {
+    config: ProportionateConfig,
+    gains: Vec<T>,
+}
+
+impl<T: Float> ProportionateUpdate<T> {
+    pub(crate) fn new(config: ProportionateConfig, fft_size: usize) -> Self {
+        Self {
+            config,
+            gains: vec![T::one(); fft_size / 2],
+        }
+    }
+
+    /// Applies the gradient constraint like [`constrain_gradient`](crate::constrain_gradient),
+    /// additionally weighting the causal gradient taps by the gains derived from the current
+    /// `weights`. No gain exceeds `max_gain`. `time` (`fft_size` samples) is used as
+    /// scratch space.
+    pub(crate) fn constrain(
+        &mut self,
+        fft: &mut impl FftBackend<T>,
+        weights: &[Complex<T>],
+        gradient: &mut [Complex<T>],
+        time: &mut [T],
+        max_gain: T,
+    ) {
+        let fft_size = time.len();
+        let scale = T::one() / float(fft_size as f64);
+
+        // Impulse response of the filter; only its causal half is non-zero.
+        fft.inverse(weights, time);
+        self.update_gains(&time[..fft_size / 2], scale, max_gain);
+
+        fft.inverse(gradient, time);
+        let (causal, wrapped) = time.split_at_mut(fft_size / 2);
+        for (g, &gain) in causal.iter_mut().zip(&self.gains) {
+            *g *= scale * gain;
+        }
+        wrapped.fill(T::zero());
+
+        fft.forward(time, gradient);
+    }
+
+    /// Derives the gain of every tap from the impulse response `taps`, scaled by `scale`.
+    fn update_gains(&mut self, taps: &[T], scale: T, max_gain: T) {
+        for (gain, &h) in self.gains.iter_mut().zip(taps) {
+            *gain = (h * scale).abs();
+        }
+        let mean = self.gains.iter().copied().sum::<T>() / float(self.gains.len() as f64);
+
+        // k_n = (1 - a) / 2 * mean|h| + (1 + a) / 2 * |h_n| + delta, whose mean is
+        // mean|h| + delta.
+        let proportionality: T = float(self.config.proportionality);
+        let uniform = (T::one() - proportionality) / float(2.0);
+        let proportional = (T::one() + proportionality) / float(2.0);
+        let regularization: T = float(self.config.regularization);
+        let norm = mean + regularization;
+        if !(norm > T::zero() && norm.is_finite()) {
+            // Nothing to be proportionate to: fall back to the uniform update.
+            self.gains.fill(T::one());
+            return;
+        }
+        for gain in self.gains.iter_mut() {
+            *gain = ((uniform * mean + proportional * *gain + regularization) / norm).min(max_gain);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::noise;
+    use crate::{AnnealingConfig, FdafAec, StepSizeControl};
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Runs the canceller on a sparse echo path with a few reflections and returns the ERLE
+    /// of each of the first `frames` frames, in dB.
+    fn erle(aec: &mut FdafAec<FFT_SIZE>, frames: usize) -> Vec<f32> {
+        let far_end = noise(frames * FRAME_SIZE, 11);
+        let near_end = noise(frames * FRAME_SIZE, 12);
+        let path = [(10, 0.6), (70, -0.15), (110, 0.08), (150, 0.03)];
+        (0..frames)
+            .map(|frame| {
+                let mic: [f32; FRAME_SIZE] = core::array::from_fn(|i| {
+                    let n = frame * FRAME_SIZE + i;
+                    let echo: f32 = path
+                        .iter()
+                        .filter(|&&(delay, _)| n >= delay)
+                        .map(|&(delay, gain)| gain * far_end[n - delay])
+                        .sum();
+                    echo + 1e-3 * near_end[n]
+                });
+                let far = far_end[frame * FRAME_SIZE..].first_chunk().unwrap();
+                let mut error = [0.0; FRAME_SIZE];
+                aec.process(&mut error, far, &mic);
+                let mic_energy: f32 = mic.iter().map(|x| x * x).sum();
+                let error_energy: f32 = error.iter().map(|e| e * e).sum();
+                10.0 * (mic_energy / error_energy).log10()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converges_faster_on_sparse_echo_paths() {
+        // A small step size, as used for robustness against double talk.
+        let mut nlms = FdafAec::<FFT_SIZE>::new(0.05, 0.9, 10e-4, 0.0);
+        let mut proportionate = FdafAec::<FFT_SIZE>::new(0.05, 0.9, 10e-4, 0.0);
+        proportionate
+            .enable_proportionate_update(ProportionateConfig::default())
+            .unwrap();
+
+        let nlms = erle(&mut nlms, 40);
+        let proportionate = erle(&mut proportionate, 40);
+        assert!(
+            proportionate[20] > nlms[20] + 8.0,
+            "{} vs {}",
+            proportionate[20],
+            nlms[20]
+        );
+        assert!(proportionate[39] > nlms[39], "{}", proportionate[39]);
+    }
+
+    #[test]
+    fn uniform_proportionality_matches_nlms() {
+        let mut nlms = FdafAec::<FFT_SIZE>::new(0.2, 0.9, 10e-4, 0.0);
+        let mut uniform = FdafAec::<FFT_SIZE>::new(0.2, 0.9, 10e-4, 0.0);
+        uniform
+            .enable_proportionate_update(ProportionateConfig {
+                proportionality: -1.0,
+                ..Default::default()
+            })
+            .unwrap();
+
+        for (expected, actual) in erle(&mut nlms, 20).iter().zip(erle(&mut uniform, 20)) {
+            assert!((expected - actual).abs() < 0.01, "{expected} vs {actual}");
+        }
+    }
+
+    #[test]
+    fn limits_gains_by_the_applied_step() {
+        // The annealing schedule ends far above the base step size.
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.05, 0.9, 10e-4, 0.0);
+        aec.enable_step_size_control(StepSizeControl::Annealing(AnnealingConfig {
+            final_step_size: 1.5,
+            time_constant: 1.0,
+        }))
+        .unwrap();
+        aec.enable_proportionate_update(ProportionateConfig::default())
+            .unwrap();
+
+        let erle = erle(&mut aec, 40);
+        assert!(erle.iter().all(|e| e.is_finite()), "{erle:?}");
+        assert!(erle[39] > 20.0, "{}", erle[39]);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = ProportionateConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            ProportionateConfig {
+                proportionality: 1.5,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidProportionality(1.5))
+        );
+        // Unlike the PSD regularization, this one must not be zero.
+        assert_eq!(
+            ProportionateConfig {
+                regularization: 0.0,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidRegularizationFactor(0.0))
+        );
+
+        // A rejected config leaves the plain NLMS update in place.
+        let mut nlms = FdafAec::<FFT_SIZE>::new(0.2, 0.9, 10e-4, 0.0);
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.2, 0.9, 10e-4, 0.0);
+        assert!(aec
+            .enable_proportionate_update(ProportionateConfig {
+                proportionality: 1.5,
+                ..config
+            })
+            .is_err());
+        assert_eq!(erle(&mut aec, 5), erle(&mut nlms, 5));
+    }
+}
@@ -0,0 +1,372 @@
+#[cfg(feature = "rustfft")]
+use alloc::sync::Arc;
+#[cfg(feature = "rustfft")]
+use alloc::vec;
+#[cfg(feature = "rustfft")]
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+#[cfg(feature = "rustfft")]
+use num_traits::Zero;
+#[cfg(feature = "rustfft")]
+use rustfft::{Fft, FftPlanner};
+
+use crate::{float, Float};
+
+/// A real-to-complex FFT of `fft_size` points and its complex-to-real inverse, as used by the
+/// cancellers.
+///
+/// A real signal has a Hermitian spectrum, so only the `fft_size / 2 + 1` bins from DC to
+/// Nyquist are computed and stored. Neither direction is normalized: `inverse(forward(x))`
+/// gives `fft_size * x`.
+///
+/// [`RustFft`] is the default backend. [`FixedFft`] needs neither a heap nor a planner and
+/// is the default without the `rustfft` feature. Other FFT libraries, e.g. a vendor DSP
+/// library, can be plugged in by implementing this trait.
+pub trait FftBackend<T: Float>: Clone {
+    /// The only FFT size the backend supports, if it is fixed at compile time. An
+    /// [`FdafAec`](crate::FdafAec) of any other size fails to compile.
+    const FIXED_SIZE: Option<usize> = None;
+
+    /// Prepares the transforms for a power-of-two `fft_size`.
+    fn new(fft_size: usize) -> Self;
+
+    /// Transforms `fft_size` real samples into the `fft_size / 2 + 1` bins of their half
+    /// spectrum.
+    fn forward(&mut self, input: &[T], spectrum: &mut [Complex<T>]);
+
+    /// Transforms a half spectrum of `fft_size / 2 + 1` bins back into `fft_size` real
+    /// samples.
+    ///
+    /// The imaginary parts of the DC and Nyquist bins are ignored, as they are for any real
+    /// signal.
+    fn inverse(&mut self, spectrum: &[Complex<T>], output: &mut [T]);
+}
+
+/// The default [`FftBackend`] for an FFT of `FFT_SIZE` points: [`RustFft`] with the
+/// `rustfft` feature, [`FixedFft`] without it.
+#[cfg(feature = "rustfft")]
+pub type DefaultFft<T, const FFT_SIZE: usize> = RustFft<T>;
+
+/// The default [`FftBackend`] for an FFT of `FFT_SIZE` points: [`RustFft`] with the
+/// `rustfft` feature, [`FixedFft`] without it.
+#[cfg(not(feature = "rustfft"))]
+pub type DefaultFft<T, const FFT_SIZE: usize> = FixedFft<T, FFT_SIZE>;
+
+/// The default [`FftBackend`] with the `rustfft` feature, built on rustfft.
+///
+/// The even and odd samples are packed into the real and imaginary parts of a complex signal
+/// of half the length, transformed with a complex FFT of `fft_size / 2` points and separated
+/// again with one twiddle per bin. This roughly halves the cost of a full complex FFT over
+/// the zero-imaginary signal. Planning allocates the transforms and their buffers on the
+/// heap.
+#[cfg(feature = "rustfft")]
+#[derive(Clone)]
+pub struct RustFft<T: Float> {
+    fft: Arc<dyn Fft<T>>,
+    ifft: Arc<dyn Fft<T>>,
+    twiddles: Vec<Complex<T>>,
+    packed: Vec<Complex<T>>,
+    scratch: Vec<Complex<T>>,
+}
+
+#[cfg(feature = "rustfft")]
+impl<T: Float> RustFft<T> {
+    /// Returns the number of bins of the half spectrum, `fft_size / 2 + 1`.
+    pub(crate) fn spectrum_len(&self) -> usize {
+        self.packed.len() + 1
+    }
+}
+
+#[cfg(feature = "rustfft")]
+impl<T: Float> FftBackend<T> for RustFft<T> {
+    fn new(fft_size: usize) -> Self {
+        assert!(
+            fft_size >= 2 && fft_size.is_multiple_of(2),
+            "fft_size must be even."
+        );
+        let half = fft_size / 2;
+        let mut fft_planner = FftPlanner::new();
+        let fft = fft_planner.plan_fft_forward(half);
+        let ifft = fft_planner.plan_fft_inverse(half);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+        let twiddles = (0..half).map(|k| twiddle(k, fft_size)).collect();
+
+        Self {
+            fft,
+            ifft,
+            twiddles,
+            packed: vec![Complex::zero(); half],
+            scratch: vec![Complex::zero(); scratch_len],
+        }
+    }
+
+    fn forward(&mut self, input: &[T], spectrum: &mut [Complex<T>]) {
+        pack(input, &mut self.packed);
+        self.fft
+            .process_with_scratch(&mut self.packed, &mut self.scratch);
+        split(&self.packed, &self.twiddles, spectrum);
+    }
+
+    fn inverse(&mut self, spectrum: &[Complex<T>], output: &mut [T]) {
+        merge(spectrum, &self.twiddles, &mut self.packed);
+        self.ifft
+            .process_with_scratch(&mut self.packed, &mut self.scratch);
+        unpack(&self.packed, output);
+    }
+}
+
+/// A heap-free [`FftBackend`] for a fixed `FFT_SIZE`.
+///
+/// It uses the same real-to-complex packing as [`RustFft`] with an in-place radix-2 complex
+/// FFT, and keeps its tables in arrays instead of on the heap. With it, an [`FdafAec`]
+/// without optional stages never allocates, not even when it is created, and builds
+/// without the `alloc` and `rustfft` features. It is slower than rustfft's planned
+/// transforms on desktop CPUs.
+///
+/// [`FdafAec`]: crate::FdafAec
+///
+/// ```
+/// use fdaf_aec::{FdafAec, FixedFft};
+///
+/// let mut aec = FdafAec::<512, f32, FixedFft<f32, 512>>::new(0.5, 0.9, 10e-4, 10e-4);
+/// let mut error = [0.0; 256];
+/// aec.process(&mut error, &[0.0; 256], &[0.0; 256]);
+/// ```
+///
+/// A canceller of another FFT size is rejected at compile time:
+///
+/// ```compile_fail
+/// use fdaf_aec::{FdafAecConfig, FixedFft};
+///
+/// let aec = FdafAecConfig::new().try_build_as::<512, f32, FixedFft<f32, 256>>();
+/// ```
+#[derive(Clone)]
+pub struct FixedFft<T: Float, const FFT_SIZE: usize> {
+    /// `exp(-2 pi i k / FFT_SIZE)` for the first `FFT_SIZE / 2` values of `k`, as
+    /// interleaved real and imaginary parts.
+    twiddles: [T; FFT_SIZE],
+    /// The `FFT_SIZE / 2` complex values of the packed signal, interleaved like `twiddles`.
+    packed: [T; FFT_SIZE],
+}
+
+impl<T: Float, const FFT_SIZE: usize> FixedFft<T, FFT_SIZE> {
+    /// Runs the radix-2 transform of the packed signal in place.
+    fn transform(&mut self, inverse: bool) {
+        let half = FFT_SIZE / 2;
+        let data = as_complex_mut(&mut self.packed);
+        let twiddles = as_complex(&self.twiddles);
+        if half < 2 {
+            return;
+        }
+        let bits = half.trailing_zeros();
+        for i in 0..half {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+        let mut len = 2;
+        while len <= half {
+            // The twiddles of a transform of `len` points are every `FFT_SIZE / len`-th
+            // entry of the table.
+            let step = FFT_SIZE / len;
+            for block in data.chunks_exact_mut(len) {
+                let (first, second) = block.split_at_mut(len / 2);
+                for (k, (a, b)) in first.iter_mut().zip(second).enumerate() {
+                    let twiddle = twiddles[k * step];
+                    let twiddle = if inverse { twiddle.conj() } else { twiddle };
+                    let product = *b * twiddle;
+                    *b = *a - product;
+                    *a += product;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
+
+impl<T: Float, const FFT_SIZE: usize> FftBackend<T> for FixedFft<T, FFT_SIZE> {
+    const FIXED_SIZE: Option<usize> = Some(FFT_SIZE);
+
+    /// # Panics
+    ///
+    /// Panics if `fft_size` is not `FFT_SIZE` or not a power of two of at least 2.
+    fn new(fft_size: usize) -> Self {
+        assert_eq!(fft_size, FFT_SIZE);
+        assert!(
+            fft_size >= 2 && fft_size.is_power_of_two(),
+            "fft_size must be a power of two."
+        );
+        Self {
+            twiddles: core::array::from_fn(|i| {
+                let twiddle = twiddle::<T>(i / 2, FFT_SIZE);
+                if i % 2 == 0 {
+                    twiddle.re
+                } else {
+                    twiddle.im
+                }
+            }),
+            packed: [T::zero(); FFT_SIZE],
+        }
+    }
+
+    fn forward(&mut self, input: &[T], spectrum: &mut [Complex<T>]) {
+        pack(input, as_complex_mut(&mut self.packed));
+        self.transform(false);
+        split(
+            as_complex(&self.packed),
+            as_complex(&self.twiddles),
+            spectrum,
+        );
+    }
+
+    fn inverse(&mut self, spectrum: &[Complex<T>], output: &mut [T]) {
+        merge(
+            spectrum,
+            as_complex(&self.twiddles),
+            as_complex_mut(&mut self.packed),
+        );
+        self.transform(true);
+        unpack(as_complex(&self.packed), output);
+    }
+}
+
+/// Views interleaved real and imaginary parts as complex values.
+fn as_complex<T: Float>(values: &[T]) -> &[Complex<T>] {
+    // SAFETY: `Complex<T>` is `#[repr(C)]` with the fields `re` and `im`, so it has the
+    // layout and alignment of `[T; 2]`.
+    unsafe { core::slice::from_raw_parts(values.as_ptr().cast(), values.len() / 2) }
+}
+
+/// The mutable version of [`as_complex`].
+fn as_complex_mut<T: Float>(values: &mut [T]) -> &mut [Complex<T>] {
+    // SAFETY: See `as_complex`.
+    unsafe { core::slice::from_raw_parts_mut(values.as_mut_ptr().cast(), values.len() / 2) }
+}
+
+/// Returns `exp(-2 pi i k / fft_size)`.
+fn twiddle<T: Float>(k: usize, fft_size: usize) -> Complex<T> {
+    let phase = float::<T>(-2.0) * T::PI() * float(k as f64) / float(fft_size as f64);
+    Complex::from_polar(T::one(), phase)
+}
+
+/// Packs the even and odd samples of `input` into the real and imaginary parts of `packed`.
+fn pack<T: Float>(input: &[T], packed: &mut [Complex<T>]) {
+    for (z, pair) in packed.iter_mut().zip(input.chunks_exact(2)) {
+        *z = Complex::new(pair[0], pair[1]);
+    }
+}
+
+/// Unpacks the real and imaginary parts of `packed` into the even and odd samples of `output`.
+fn unpack<T: Float>(packed: &[Complex<T>], output: &mut [T]) {
+    for (pair, z) in output.chunks_exact_mut(2).zip(packed) {
+        pair[0] = z.re;
+        pair[1] = z.im;
+    }
+}
+
+/// Separates the transform of a packed signal into the half spectrum of the real signal.
+fn split<T: Float>(packed: &[Complex<T>], twiddles: &[Complex<T>], spectrum: &mut [Complex<T>]) {
+    let half = packed.len();
+    let z0 = packed[0];
+    spectrum[0] = Complex::new(z0.re + z0.im, T::zero());
+    spectrum[half] = Complex::new(z0.re - z0.im, T::zero());
+    for (k, (x, twiddle)) in spectrum[..half]
+        .iter_mut()
+        .zip(twiddles)
+        .enumerate()
+        .skip(1)
+    {
+        let z = packed[k];
+        let z_mirror = packed[half - k].conj();
+        let even = (z + z_mirror) * float::<T>(0.5);
+        let odd = (z - z_mirror) * Complex::new(T::zero(), float(-0.5));
+        *x = even + twiddle * odd;
+    }
+}
+
+/// Combines a half spectrum into the spectrum of the packed signal, the inverse of [`split`]
+/// up to a factor of two.
+fn merge<T: Float>(spectrum: &[Complex<T>], twiddles: &[Complex<T>], packed: &mut [Complex<T>]) {
+    let half = packed.len();
+    for (k, (z, twiddle)) in packed.iter_mut().zip(twiddles).enumerate() {
+        let x = spectrum[k];
+        let x_mirror = spectrum[half - k].conj();
+        let even = x + x_mirror;
+        let odd = (x - x_mirror) * twiddle.conj();
+        *z = even + Complex::new(-odd.im, odd.re);
+    }
+    // The DC and Nyquist bins are purely real.
+    let (dc, nyquist) = (spectrum[0].re, spectrum[half].re);
+    packed[0] = Complex::new(dc + nyquist, dc - nyquist);
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_complex_fft_and_round_trips() {
+        const FFT_SIZE: usize = 64;
+        let signal: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| (i as f32 * 0.37).sin() + 0.25 * (i as f32 * 1.9).cos())
+            .collect();
+
+        let mut expected: Vec<Complex<f32>> =
+            signal.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        FftPlanner::new()
+            .plan_fft_forward(FFT_SIZE)
+            .process(&mut expected);
+
+        let mut real_fft = RustFft::new(FFT_SIZE);
+        let mut spectrum = vec![Complex::zero(); real_fft.spectrum_len()];
+        real_fft.forward(&signal, &mut spectrum);
+        for (a, b) in spectrum.iter().zip(&expected) {
+            assert!((a - b).norm() < 1e-4, "{a} != {b}");
+        }
+
+        let mut round_trip = vec![0.0; FFT_SIZE];
+        real_fft.inverse(&spectrum, &mut round_trip);
+        for (a, b) in round_trip.iter().zip(&signal) {
+            assert!((a / FFT_SIZE as f32 - b).abs() < 1e-5, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn fixed_fft_matches_rustfft() {
+        fn check<const FFT_SIZE: usize>() {
+            let signal: Vec<f64> = (0..FFT_SIZE)
+                .map(|i| (i as f64 * 0.37).sin() + 0.25 * (i as f64 * 1.9).cos())
+                .collect();
+            let mut rustfft = RustFft::new(FFT_SIZE);
+            let mut fixed = FixedFft::<f64, FFT_SIZE>::new(FFT_SIZE);
+            let mut expected = vec![Complex::zero(); FFT_SIZE / 2 + 1];
+            let mut spectrum = vec![Complex::zero(); FFT_SIZE / 2 + 1];
+            rustfft.forward(&signal, &mut expected);
+            fixed.forward(&signal, &mut spectrum);
+            for (a, b) in spectrum.iter().zip(&expected) {
+                assert!((a - b).norm() < 1e-9, "{a} != {b}");
+            }
+
+            let mut expected = vec![0.0; FFT_SIZE];
+            let mut round_trip = vec![0.0; FFT_SIZE];
+            rustfft.inverse(&spectrum, &mut expected);
+            fixed.inverse(&spectrum, &mut round_trip);
+            for (a, b) in round_trip.iter().zip(&expected) {
+                assert!((a - b).abs() < 1e-9, "{a} != {b}");
+            }
+        }
+        check::<2>();
+        check::<4>();
+        check::<64>();
+        check::<1024>();
+        // Both tables hold `FFT_SIZE / 2` complex values.
+        assert_eq!(
+            core::mem::size_of::<FixedFft<f64, 1024>>(),
+            2 * 1024 * core::mem::size_of::<f64>()
+        );
+    }
+}
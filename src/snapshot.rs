@@ -0,0 +1,315 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use num_complex::Complex;
+
+use crate::{float, to_f32, Float};
+
+/// Identifies a serialized [`FilterSnapshot`].
+const MAGIC: [u8; 4] = *b"FDAF";
+/// The current version of the binary format, bumped on every incompatible change.
+const VERSION: u16 = 1;
+/// Magic, version and FFT size.
+const HEADER_LEN: usize = 4 + 2 + 4;
+
+/// Error returned when a [`FilterSnapshot`] cannot be decoded or restored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotError {
+    /// The data does not start with the snapshot magic bytes.
+    InvalidMagic,
+    /// The data was written by an unsupported version of the format.
+    UnsupportedVersion(u16),
+    /// The data is not as long as its header announces.
+    InvalidLength {
+        /// The length implied by the header.
+        expected: usize,
+        /// The actual length of the data.
+        actual: usize,
+    },
+    /// The FFT size in the header is not a power of two of at least 2, or too large to
+    /// address on this target.
+    InvalidFftSize(usize),
+    /// The snapshot was taken from a canceller with a different FFT size.
+    FftSizeMismatch {
+        /// The FFT size of the canceller being restored.
+        expected: usize,
+        /// The FFT size stored in the snapshot.
+        actual: usize,
+    },
+    /// The snapshot contains NaN or infinite values, or a negative PSD.
+    InvalidValue,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => f.write_str("data is not a filter snapshot"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            Self::InvalidLength { expected, actual } => {
+                write!(f, "snapshot has {actual} bytes, expected {expected}")
+            }
+            Self::InvalidFftSize(size) => write!(f, "snapshot has invalid FFT size {size}"),
+            Self::FftSizeMismatch { expected, actual } => {
+                write!(f, "snapshot has FFT size {actual}, expected {expected}")
+            }
+            Self::InvalidValue => f.write_str("snapshot contains invalid values"),
+        }
+    }
+}
+
+impl core::error::Error for SnapshotError {}
+
+/// The adapted state of a canceller: its weights, far-end PSD and far-end history.
+///
+/// Restoring a snapshot taken at the end of one session into a fresh canceller lets it
+/// start from the last known room response instead of from zero. Snapshots are encoded
+/// with [`to_bytes`](Self::to_bytes) into a small versioned little-endian format, and with
+/// the `serde` feature they can be serialized with any serde format as well. Values are
+/// stored as `f32` whatever the sample type of the canceller.
+///
+/// ```
+/// use fdaf_aec::{FdafAec, FilterSnapshot};
+///
+/// let trained = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+/// let bytes = trained.snapshot().to_bytes();
+///
+/// let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+/// aec.restore(&FilterSnapshot::from_bytes(&bytes).unwrap())
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterSnapshot {
+    fft_size: usize,
+    /// Half-spectrum weights, interleaved real and imaginary parts.
+    weights: Vec<f32>,
+    psd: Vec<f32>,
+    far_end_buffer: Vec<f32>,
+}
+
+impl FilterSnapshot {
+    pub(crate) fn new<T: Float>(weights: &[Complex<T>], psd: &[T], far_end_buffer: &[T]) -> Self {
+        Self {
+            fft_size: far_end_buffer.len(),
+            weights: weights
+                .iter()
+                .flat_map(|w| [to_f32(w.re), to_f32(w.im)])
+                .collect(),
+            psd: psd.iter().copied().map(to_f32).collect(),
+            far_end_buffer: far_end_buffer.iter().copied().map(to_f32).collect(),
+        }
+    }
+
+    /// Returns the FFT size of the canceller the snapshot was taken from.
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    /// Encodes the snapshot into the versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(encoded_len(self.fft_size).unwrap_or(0));
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.fft_size as u32).to_le_bytes());
+        for value in self
+            .weights
+            .iter()
+            .chain(&self.psd)
+            .chain(&self.far_end_buffer)
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes a snapshot written by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let fft_size = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]) as usize;
+        if fft_size < 2 || !fft_size.is_power_of_two() {
+            return Err(SnapshotError::InvalidFftSize(fft_size));
+        }
+        let expected = encoded_len(fft_size).ok_or(SnapshotError::InvalidFftSize(fft_size))?;
+        if bytes.len() != expected {
+            return Err(SnapshotError::InvalidLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let mut values = bytes[HEADER_LEN..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let bins = fft_size / 2 + 1;
+        let snapshot = Self {
+            fft_size,
+            weights: values.by_ref().take(2 * bins).collect(),
+            psd: values.by_ref().take(bins).collect(),
+            far_end_buffer: values.collect(),
+        };
+        snapshot.validate(fft_size)?;
+        Ok(snapshot)
+    }
+
+    /// Checks that the snapshot fits a canceller with the given FFT size and holds sane
+    /// values.
+    pub(crate) fn validate(&self, fft_size: usize) -> Result<(), SnapshotError> {
+        if self.fft_size != fft_size {
+            return Err(SnapshotError::FftSizeMismatch {
+                expected: fft_size,
+                actual: self.fft_size,
+            });
+        }
+        let bins = fft_size / 2 + 1;
+        if self.weights.len() != 2 * bins
+            || self.psd.len() != bins
+            || self.far_end_buffer.len() != fft_size
+        {
+            // Only reachable through a hand-edited serde representation.
+            // The canceller's own buffers are larger than its encoding, so this cannot
+            // overflow.
+            return Err(SnapshotError::InvalidLength {
+                expected: encoded_len(fft_size).unwrap_or(usize::MAX),
+                actual: HEADER_LEN
+                    + 4 * (self.weights.len() + self.psd.len() + self.far_end_buffer.len()),
+            });
+        }
+        let finite = self
+            .weights
+            .iter()
+            .chain(&self.far_end_buffer)
+            .all(|x| x.is_finite());
+        if !finite || !self.psd.iter().all(|p| p.is_finite() && *p >= 0.0) {
+            return Err(SnapshotError::InvalidValue);
+        }
+        Ok(())
+    }
+
+    /// Copies the stored state into the given buffers, which must match the snapshot.
+    pub(crate) fn copy_to<T: Float>(
+        &self,
+        weights: &mut [Complex<T>],
+        psd: &mut [T],
+        far_end_buffer: &mut [T],
+    ) {
+        for (w, pair) in weights.iter_mut().zip(self.weights.chunks_exact(2)) {
+            *w = Complex::new(float(pair[0]), float(pair[1]));
+        }
+        for (target, &value) in psd.iter_mut().zip(&self.psd) {
+            *target = float(value);
+        }
+        for (target, &value) in far_end_buffer.iter_mut().zip(&self.far_end_buffer) {
+            *target = float(value);
+        }
+    }
+}
+
+/// Returns the encoded length of a snapshot of a canceller with the given FFT size, or
+/// `None` if it does not fit in a `usize`, as for a hostile header on a 32-bit target.
+fn encoded_len(fft_size: usize) -> Option<usize> {
+    let bins = fft_size / 2 + 1;
+    3usize
+        .checked_mul(bins)?
+        .checked_add(fft_size)?
+        .checked_mul(4)?
+        .checked_add(HEADER_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{noise, run};
+    use crate::FdafAec;
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn warm_start_continues_where_the_snapshot_left_off() {
+        let far_end = noise(FRAME_SIZE * 60, 31);
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 35 { 0.3 * far_end[i - 35] } else { 0.0 })
+            .collect();
+        let (first, second) = far_end.split_at(FRAME_SIZE * 40);
+        let (mic_first, mic_second) = mic.split_at(FRAME_SIZE * 40);
+
+        let mut trained = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        run(&mut trained, first, mic_first);
+        let bytes = trained.snapshot().to_bytes();
+
+        let mut restored = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        restored
+            .restore(&FilterSnapshot::from_bytes(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(
+            run(&mut restored, second, mic_second),
+            run(&mut trained, second, mic_second)
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_or_corrupt_snapshots() {
+        let bytes = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4)
+            .snapshot()
+            .to_bytes();
+        let snapshot = FilterSnapshot::from_bytes(&bytes).unwrap();
+
+        let mut other = FdafAec::<1024>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert_eq!(
+            other.restore(&snapshot),
+            Err(SnapshotError::FftSizeMismatch {
+                expected: 1024,
+                actual: FFT_SIZE
+            })
+        );
+
+        assert_eq!(
+            FilterSnapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::InvalidLength {
+                expected: bytes.len(),
+                actual: bytes.len() - 1
+            })
+        );
+        let mut corrupt = bytes.clone();
+        corrupt[4] = 9;
+        assert_eq!(
+            FilterSnapshot::from_bytes(&corrupt),
+            Err(SnapshotError::UnsupportedVersion(9))
+        );
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert_eq!(
+            FilterSnapshot::from_bytes(&corrupt),
+            Err(SnapshotError::InvalidValue)
+        );
+        assert_eq!(
+            FilterSnapshot::from_bytes(&[0; 3]),
+            Err(SnapshotError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn rejects_hostile_fft_sizes() {
+        let bytes = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4)
+            .snapshot()
+            .to_bytes();
+        for fft_size in [u32::MAX, 0, 1, 511] {
+            let mut corrupt = bytes.clone();
+            corrupt[6..HEADER_LEN].copy_from_slice(&fft_size.to_le_bytes());
+            assert_eq!(
+                FilterSnapshot::from_bytes(&corrupt),
+                Err(SnapshotError::InvalidFftSize(fft_size as usize))
+            );
+        }
+        assert_eq!(encoded_len(usize::MAX / 2 + 1), None);
+    }
+}
@@ -0,0 +1,349 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::{check_smoothing_factor, check_step_size};
+use crate::{bin_multiplicity, float, ConfigError, Float};
+
+/// A strategy that varies the step size of the weight update over time and frequency.
+///
+/// See [`FdafAec::enable_step_size_control`](crate::FdafAec::enable_step_size_control).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepSizeControl {
+    /// Per-bin optimal step size: the fraction of the error power that is residual echo,
+    /// bounded by the configured step size.
+    Optimal(OptimalStepSizeConfig),
+    /// A step size that decays exponentially from the configured step size to a final value.
+    Annealing(AnnealingConfig),
+}
+
+impl StepSizeControl {
+    /// Checks that every parameter of the strategy is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self {
+            Self::Optimal(config) => {
+                check_smoothing_factor(config.smoothing_factor)?;
+            }
+            Self::Annealing(config) => {
+                check_step_size(config.final_step_size)?;
+                if !(config.time_constant.is_finite() && config.time_constant > 0.0) {
+                    return Err(ConfigError::InvalidTimeConstant(config.time_constant));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of the per-bin optimal step size.
+///
+/// The optimal NLMS step in every bin is the fraction of the error power that is residual
+/// echo rather than near-end signal. The residual echo is the part of the error that is
+/// coherent with the far-end signal, so the step is the configured step size times the
+/// magnitude-squared coherence of the far-end and error spectra. It is large while the
+/// filter converges or after the echo path changes, and shrinks once the filter has
+/// converged or while the near-end talks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptimalStepSizeConfig {
+    /// Smoothing factor of the recursive (cross) power spectrum estimates behind the
+    /// coherence, in `[0, 1)`. Longer memories give smaller steps in the steady state but
+    /// react later to echo path changes.
+    pub smoothing_factor: f32,
+}
+
+impl Default for OptimalStepSizeConfig {
+    fn default() -> Self {
+        Self {
+            smoothing_factor: 0.9,
+        }
+    }
+}
+
+/// Configuration of the annealing step size schedule.
+///
+/// The step size of frame `n` is `final + (mu - final) * exp(-n / time_constant)`, where
+/// `mu` is the configured step size, so the filter converges fast at first and settles with
+/// little misadjustment. The schedule restarts on [`reset`](crate::FdafAec::reset).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnnealingConfig {
+    /// The step size the schedule converges to, in `[0, 2)` and usually well below the
+    /// configured one.
+    pub final_step_size: f32,
+    /// The time constant of the decay, in frames. Must be finite and positive.
+    pub time_constant: f32,
+}
+
+impl Default for AnnealingConfig {
+    fn default() -> Self {
+        Self {
+            final_step_size: 0.05,
+            time_constant: 50.0,
+        }
+    }
+}
+
+/// Computes the effective per-bin step sizes of every weight update.
+#[derive(Clone)]
+pub(crate) struct StepSizeController<T: Float> {
+    strategy: Strategy<T>,
+    frames: u32,
+    steps: Vec<T>,
+}
+
+/// A [`StepSizeControl`] with the state it needs.
+#[derive(Clone)]
+enum Strategy<T: Float> {
+    Optimal {
+        config: OptimalStepSizeConfig,
+        cross_psd: Vec<Complex<T>>,
+        far_end_psd: Vec<T>,
+        error_psd: Vec<T>,
+    },
+    Annealing(AnnealingConfig),
+}
+
+impl<T: Float> StepSizeController<T> {
+    pub(crate) fn new(control: StepSizeControl, fft_size: usize) -> Self {
+        let bins = fft_size / 2 + 1;
+        Self {
+            strategy: match control {
+                StepSizeControl::Optimal(config) => Strategy::Optimal {
+                    config,
+                    cross_psd: vec![Complex::zero(); bins],
+                    far_end_psd: vec![T::zero(); bins],
+                    error_psd: vec![T::zero(); bins],
+                },
+                StepSizeControl::Annealing(config) => Strategy::Annealing(config),
+            },
+            frames: 0,
+            steps: vec![T::zero(); bins],
+        }
+    }
+
+    /// Restarts the strategy, as if it had just been enabled.
+    pub(crate) fn reset(&mut self) {
+        if let Strategy::Optimal {
+            cross_psd,
+            far_end_psd,
+            error_psd,
+            ..
+        } = &mut self.strategy
+        {
+            cross_psd.fill(Complex::zero());
+            far_end_psd.fill(T::zero());
+            error_psd.fill(T::zero());
+        }
+        self.frames = 0;
+        self.steps.fill(T::zero());
+    }
+
+    /// Returns the step sizes of the last update, one per half-spectrum bin.
+    pub(crate) fn steps(&self) -> &[T] {
+        &self.steps
+    }
+
+    /// Returns the mean of the step sizes of the last update over the full spectrum.
+    pub(crate) fn mean_step(&self) -> T {
+        spectrum_mean(&self.steps)
+    }
+
+    /// Computes the step sizes of this frame's update from the configured step size `mu`,
+    /// scaled by `step_scale` (e.g. from a double-talk detector).
+    ///
+    /// `far_end_spectrum` is the half spectrum of the far-end block the echo was estimated
+    /// from, and `error_spectrum` the half spectrum of the zero-padded error frame used for
+    /// the weight update.
+    pub(crate) fn update(
+        &mut self,
+        far_end_spectrum: &[Complex<T>],
+        error_spectrum: &[Complex<T>],
+        mu: f32,
+        step_scale: f32,
+    ) {
+        let (mu, step_scale): (T, T) = (float(mu), float(step_scale));
+        match &mut self.strategy {
+            Strategy::Optimal {
+                config,
+                cross_psd,
+                far_end_psd,
+                error_psd,
+            } => {
+                let a: T = float(config.smoothing_factor);
+                for (k, (x, e)) in far_end_spectrum.iter().zip(error_spectrum).enumerate() {
+                    cross_psd[k] = cross_psd[k] * a + x.conj() * e * (T::one() - a);
+                    far_end_psd[k] = a * far_end_psd[k] + (T::one() - a) * x.norm_sqr();
+                    error_psd[k] = a * error_psd[k] + (T::one() - a) * e.norm_sqr();
+
+                    // The error frame covers only the second half of the far-end block, so
+                    // even an error that is all echo reaches a coherence of one half.
+                    let power = far_end_psd[k] * error_psd[k];
+                    let ratio = if power > T::zero() {
+                        (float::<T>(2.0) * cross_psd[k].norm_sqr() / power).min(T::one())
+                    } else {
+                        T::zero()
+                    };
+                    self.steps[k] = mu * ratio * step_scale;
+                }
+            }
+            Strategy::Annealing(config) => {
+                let decay = (-float::<T>(self.frames) / float(config.time_constant)).exp();
+                let final_step_size: T = float(config.final_step_size);
+                let step = final_step_size + (mu - final_step_size) * decay;
+                self.steps.fill(step * step_scale);
+            }
+        }
+        self.frames = self.frames.saturating_add(1);
+    }
+}
+
+/// Returns the mean of per-bin `values` of a half spectrum over the full spectrum.
+pub(crate) fn spectrum_mean<T: Float>(values: &[T]) -> T {
+    let fft_size = (values.len() - 1) * 2;
+    let sum: T = values
+        .iter()
+        .enumerate()
+        .map(|(k, &value)| bin_multiplicity::<T>(k, fft_size) * value)
+        .sum();
+    sum / float(fft_size as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{energy, noise};
+    use crate::{FdafAec, MetricsConfig};
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    /// Runs the canceller and returns its output and the mean step size of every frame.
+    fn run(aec: &mut FdafAec<FFT_SIZE>, far_end: &[f32], mic: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        aec.enable_metrics(MetricsConfig::default()).unwrap();
+        let mut output = Vec::with_capacity(mic.len());
+        let mut steps = Vec::new();
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let mut error = [0.0; FRAME_SIZE];
+            aec.process(
+                &mut error,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            output.extend_from_slice(&error);
+            steps.push(aec.metrics().unwrap().mean_step_size);
+        }
+        (output, steps)
+    }
+
+    #[test]
+    fn optimal_step_size_lowers_misadjustment_and_tracks_echo_path_changes() {
+        let frames = 300;
+        let far_end = noise(FRAME_SIZE * frames, 51);
+        let near_end = noise(FRAME_SIZE * frames, 52);
+        // The echo path changes halfway through.
+        let change = FRAME_SIZE * frames / 2;
+        let echo: Vec<f32> = (0..far_end.len())
+            .map(|i| match i {
+                _ if i < 40 => 0.0,
+                _ if i < change => 0.5 * far_end[i - 40],
+                _ => -0.3 * far_end[i - 40] + 0.2 * far_end[i - 25],
+            })
+            .collect();
+        let mic: Vec<f32> = echo
+            .iter()
+            .zip(&near_end)
+            .map(|(echo, near)| echo + 0.1 * near)
+            .collect();
+
+        let mut fixed = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        let mut optimal = fixed.clone();
+        optimal
+            .enable_step_size_control(StepSizeControl::Optimal(OptimalStepSizeConfig::default()))
+            .unwrap();
+        let (fixed_out, _) = run(&mut fixed, &far_end, &mic);
+        let (optimal_out, steps) = run(&mut optimal, &far_end, &mic);
+        assert_eq!(optimal.step_sizes().unwrap().len(), FFT_SIZE / 2 + 1);
+
+        // Residual echo in the steady state before the change.
+        let residual = |output: &[f32]| {
+            let range = change - FRAME_SIZE * 30..change;
+            let residual: Vec<f32> = output[range.clone()]
+                .iter()
+                .zip(&mic[range.clone()])
+                .zip(&echo[range])
+                .map(|((e, mic), echo)| e - (mic - echo))
+                .collect();
+            energy(&residual)
+        };
+        assert!(
+            residual(&optimal_out) < 0.5 * residual(&fixed_out),
+            "{} >= {}",
+            residual(&optimal_out),
+            residual(&fixed_out)
+        );
+
+        let before_change = steps[frames / 2 - 1];
+        let after_change = steps[frames / 2 + 5];
+        assert!(before_change < 0.1, "{before_change}");
+        assert!(after_change > 2.0 * before_change, "{after_change}");
+        assert!(optimal_out.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn annealing_follows_the_schedule() {
+        let far_end = noise(FRAME_SIZE * 20, 53);
+        let mic: Vec<f32> = far_end.iter().map(|x| 0.3 * x).collect();
+        let config = AnnealingConfig {
+            final_step_size: 0.1,
+            time_constant: 5.0,
+        };
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_step_size_control(StepSizeControl::Annealing(config))
+            .unwrap();
+        let (_, steps) = run(&mut aec, &far_end, &mic);
+
+        for (n, step) in steps.iter().enumerate() {
+            let expected = 0.1 + 0.4 * (-(n as f32) / 5.0).exp();
+            assert!(
+                (step - expected).abs() < 1e-6,
+                "frame {n}: {step} != {expected}"
+            );
+        }
+        let last = 0.1 + 0.4 * (-19.0f32 / 5.0).exp();
+        assert!(aec
+            .step_sizes()
+            .unwrap()
+            .iter()
+            .all(|s| (s - last).abs() < 1e-6));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let annealing = AnnealingConfig::default();
+        assert_eq!(StepSizeControl::Annealing(annealing).validate(), Ok(()));
+        assert_eq!(
+            StepSizeControl::Annealing(AnnealingConfig {
+                final_step_size: 2.0,
+                ..annealing
+            })
+            .validate(),
+            Err(ConfigError::InvalidStepSize(2.0))
+        );
+        let control = StepSizeControl::Annealing(AnnealingConfig {
+            time_constant: 0.0,
+            ..annealing
+        });
+        assert_eq!(
+            control.validate(),
+            Err(ConfigError::InvalidTimeConstant(0.0))
+        );
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert!(aec.enable_step_size_control(control).is_err());
+        assert!(aec.step_sizes().is_none());
+    }
+}
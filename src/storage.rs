@@ -0,0 +1,144 @@
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+use num_complex::Complex;
+
+use crate::Float;
+
+/// Where a canceller keeps its sample and spectrum buffers.
+pub(crate) trait Storage: Clone {
+    type Buffer<U: Copy>: Clone + Deref<Target = [U]> + DerefMut;
+    type Spectrum<T: Float>: Clone + Deref<Target = [Complex<T>]> + DerefMut;
+
+    /// Returns a buffer holding `len` copies of `value`.
+    fn buffer<U: Copy>(len: usize, value: U) -> Self::Buffer<U>;
+
+    /// Returns a complex spectrum holding `bins` copies of `value`.
+    fn spectrum<T: Float>(bins: usize, value: Complex<T>) -> Self::Spectrum<T>;
+}
+
+/// Buffers on the heap, for cancellers sized at runtime.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub(crate) struct Heap;
+
+#[cfg(feature = "alloc")]
+impl Storage for Heap {
+    type Buffer<U: Copy> = Vec<U>;
+    type Spectrum<T: Float> = Vec<Complex<T>>;
+
+    fn buffer<U: Copy>(len: usize, value: U) -> Vec<U> {
+        vec![value; len]
+    }
+
+    fn spectrum<T: Float>(bins: usize, value: Complex<T>) -> Vec<Complex<T>> {
+        vec![value; bins]
+    }
+}
+
+/// Buffers in arrays, for cancellers sized at compile time by an FFT of `N` points.
+///
+/// Signal buffers are arrays of `N` entries and complex spectra [`SpectrumArray`]s of
+/// `N / 2 + 1` bins. The PSD, the only real spectrum, uses the first `N / 2 + 1` entries
+/// of an array of `N`.
+#[derive(Clone)]
+pub(crate) struct Inline<const N: usize>;
+
+impl<const N: usize> Storage for Inline<N> {
+    type Buffer<U: Copy> = ArrayBuffer<U, N>;
+    type Spectrum<T: Float> = SpectrumArray<T, N>;
+
+    fn buffer<U: Copy>(len: usize, value: U) -> ArrayBuffer<U, N> {
+        assert!(len <= N);
+        ArrayBuffer {
+            data: [value; N],
+            len,
+        }
+    }
+
+    fn spectrum<T: Float>(bins: usize, value: Complex<T>) -> SpectrumArray<T, N> {
+        assert!(N.is_multiple_of(2) && bins <= N / 2 + 1);
+        SpectrumArray {
+            parts: core::array::from_fn(|i| if i % 2 == 0 { value.re } else { value.im }),
+            last: value,
+            bins,
+        }
+    }
+}
+
+/// The first `len` entries of an array.
+#[derive(Clone)]
+pub(crate) struct ArrayBuffer<U, const N: usize> {
+    data: [U; N],
+    len: usize,
+}
+
+impl<U, const N: usize> Deref for ArrayBuffer<U, N> {
+    type Target = [U];
+
+    fn deref(&self) -> &[U] {
+        &self.data[..self.len]
+    }
+}
+
+impl<U, const N: usize> DerefMut for ArrayBuffer<U, N> {
+    fn deref_mut(&mut self) -> &mut [U] {
+        &mut self.data[..self.len]
+    }
+}
+
+/// The first `bins` bins of a half spectrum of an FFT of `N` points, stored without the
+/// `N / 2 - 1` unused bins an array of `N` would add.
+///
+/// An array cannot be sized `N / 2 + 1` on stable Rust, so the first `N / 2` bins are
+/// stored as interleaved real and imaginary parts in an array of `N` values, directly
+/// followed by the last bin.
+#[derive(Clone)]
+#[repr(C)]
+pub(crate) struct SpectrumArray<T, const N: usize> {
+    parts: [T; N],
+    last: Complex<T>,
+    bins: usize,
+}
+
+impl<T, const N: usize> Deref for SpectrumArray<T, N> {
+    type Target = [Complex<T>];
+
+    fn deref(&self) -> &[Complex<T>] {
+        // SAFETY: The struct is `#[repr(C)]` and `Complex<T>` has the layout and alignment
+        // of `[T; 2]`, so `parts` and `last` are `N / 2 + 1` contiguous complex values at
+        // its start. Construction checks that `N` is even and `bins` at most `N / 2 + 1`.
+        unsafe { core::slice::from_raw_parts((self as *const Self).cast(), self.bins) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for SpectrumArray<T, N> {
+    fn deref_mut(&mut self) -> &mut [Complex<T>] {
+        // SAFETY: See `deref`.
+        unsafe { core::slice::from_raw_parts_mut((self as *mut Self).cast(), self.bins) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spectrum_array_stores_exactly_the_half_spectrum() {
+        let mut spectrum = Inline::<8>::spectrum(5, Complex::new(1.0f32, -1.0));
+        assert_eq!(*spectrum, [Complex::new(1.0, -1.0); 5]);
+
+        spectrum[0].im = 0.5;
+        spectrum[4] = Complex::new(2.0, 3.0);
+        assert_eq!(spectrum.parts[..2], [1.0, 0.5]);
+        assert_eq!(spectrum.last, Complex::new(2.0, 3.0));
+
+        assert_eq!(
+            core::mem::size_of::<SpectrumArray<f32, 512>>(),
+            257 * core::mem::size_of::<Complex<f32>>() + core::mem::size_of::<usize>()
+        );
+    }
+}
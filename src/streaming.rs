@@ -0,0 +1,112 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{DefaultFft, FdafAec, FftBackend, Float};
+
+/// Adapts an [`FdafAec`] to audio buffers of any length.
+///
+/// Audio callbacks rarely deliver exactly [`FdafAec::FRAME_SIZE`] samples. This wrapper
+/// collects the far-end and microphone samples of every call, runs the canceller whenever
+/// a full frame is available and hands out the processed samples in buffers of the same
+/// length as the input.
+///
+/// The output lags the input by exactly [`LATENCY`](Self::LATENCY) samples, independent
+/// of the buffer sizes: output sample `n` is the canceller's output for input sample
+/// `n - LATENCY`, and the first `LATENCY` output samples are silence. Does not allocate
+/// after construction.
+///
+/// ```
+/// use fdaf_aec::{FdafAec, StreamingFdafAec};
+///
+/// let mut aec = StreamingFdafAec::new(FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4));
+/// let far_end = [0.0; 480];
+/// let mic = [0.0; 480];
+/// let mut output = [0.0; 480];
+/// aec.process(&mut output, &far_end, &mic);
+/// ```
+#[derive(Clone)]
+pub struct StreamingFdafAec<
+    const FFT_SIZE: usize,
+    T: Float = f32,
+    F: FftBackend<T> = DefaultFft<T, FFT_SIZE>,
+> {
+    aec: FdafAec<FFT_SIZE, T, F>,
+    far_end: Vec<T>,
+    mic: Vec<T>,
+    output: Vec<T>,
+    /// The number of samples of the current frame collected so far.
+    filled: usize,
+}
+
+impl<const FFT_SIZE: usize, T: Float, F: FftBackend<T>> StreamingFdafAec<FFT_SIZE, T, F> {
+    /// The constant delay of the output relative to the input, in samples.
+    pub const LATENCY: usize = FFT_SIZE / 2;
+
+    /// Wraps a canceller, which may already have optional stages enabled.
+    pub fn new(aec: FdafAec<FFT_SIZE, T, F>) -> Self {
+        let frame_size = FFT_SIZE / 2;
+        Self {
+            aec,
+            far_end: vec![T::zero(); frame_size],
+            mic: vec![T::zero(); frame_size],
+            output: vec![T::zero(); frame_size],
+            filled: 0,
+        }
+    }
+
+    /// Returns the wrapped canceller.
+    pub fn get_ref(&self) -> &FdafAec<FFT_SIZE, T, F> {
+        &self.aec
+    }
+
+    /// Returns the wrapped canceller, e.g. to tune it or to enable optional stages.
+    pub fn get_mut(&mut self) -> &mut FdafAec<FFT_SIZE, T, F> {
+        &mut self.aec
+    }
+
+    /// Unwraps the canceller, dropping any partially collected frame.
+    pub fn into_inner(self) -> FdafAec<FFT_SIZE, T, F> {
+        self.aec
+    }
+
+    /// Resets the wrapped canceller with [`FdafAec::reset`] and drops any partially
+    /// collected frame, so the output starts with `LATENCY` samples of silence again.
+    pub fn reset(&mut self) {
+        self.aec.reset();
+        self.far_end.fill(T::zero());
+        self.mic.fill(T::zero());
+        self.output.fill(T::zero());
+        self.filled = 0;
+    }
+
+    /// Feeds a buffer of far-end and microphone samples and writes the same number of
+    /// echo-cancelled samples, delayed by [`LATENCY`](Self::LATENCY), to `output`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the three buffers do not have the same length.
+    pub fn process(&mut self, output: &mut [T], far_end: &[T], mic: &[T]) {
+        assert_eq!(far_end.len(), output.len());
+        assert_eq!(mic.len(), output.len());
+        let frame_size = Self::LATENCY;
+
+        let mut start = 0;
+        while start < output.len() {
+            // Every input sample takes the place of the output sample it is swapped with.
+            let count = (frame_size - self.filled).min(output.len() - start);
+            let buffered = self.filled..self.filled + count;
+            let input = start..start + count;
+            output[input.clone()].copy_from_slice(&self.output[buffered.clone()]);
+            self.far_end[buffered.clone()].copy_from_slice(&far_end[input.clone()]);
+            self.mic[buffered].copy_from_slice(&mic[input]);
+            self.filled += count;
+            start += count;
+
+            if self.filled == frame_size {
+                self.aec
+                    .process_frame(&mut self.output, &self.far_end, &self.mic);
+                self.filled = 0;
+            }
+        }
+    }
+}
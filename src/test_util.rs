@@ -0,0 +1,42 @@
+//! Signals and helpers shared by the unit tests.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::FdafAec;
+
+/// A small deterministic white noise source for the tests.
+pub(crate) fn noise(len: usize, mut state: u32) -> Vec<f32> {
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as f32 / u32::MAX as f32) - 0.5
+        })
+        .collect()
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn energy(signal: &[f32]) -> f32 {
+    signal.iter().map(|x| x * x).sum()
+}
+
+/// Runs the canceller over the whole signals, frame by frame, and returns its output.
+pub(crate) fn run<const FFT_SIZE: usize>(
+    aec: &mut FdafAec<FFT_SIZE>,
+    far_end: &[f32],
+    mic: &[f32],
+) -> Vec<f32> {
+    let frame_size = FFT_SIZE / 2;
+    let frames = far_end.len().min(mic.len()) / frame_size;
+    let mut output = vec![0.0; frames * frame_size];
+    for ((error, far), mic) in output
+        .chunks_exact_mut(frame_size)
+        .zip(far_end.chunks_exact(frame_size))
+        .zip(mic.chunks_exact(frame_size))
+    {
+        aec.process_frame(error, far, mic);
+    }
+    output
+}
@@ -0,0 +1,349 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::config::check_smoothing_factor;
+use crate::real_fft::FftBackend;
+use crate::{float, kernels, ConfigError, Float};
+
+/// Configuration of the foreground filter of the two-path canceller.
+///
+/// See [`FdafAec::enable_foreground_filter`](crate::FdafAec::enable_foreground_filter).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForegroundFilterConfig {
+    /// Smoothing factor of the recursive error energy estimates of both filters, in `[0, 1)`.
+    pub smoothing_factor: f32,
+    /// The background weights are copied to the foreground once the background error energy
+    /// has been below `copy_ratio` times the foreground error energy for `hold_frames`
+    /// consecutive frames. Must be in `(0, 1]`.
+    pub copy_ratio: f32,
+    /// The number of consecutive frames the background must be better before it is copied,
+    /// at least one.
+    pub hold_frames: u32,
+    /// The foreground weights are copied back to the background once the background error
+    /// energy of a frame exceeds `revert_ratio` times the foreground error energy of the same
+    /// frame, i.e. when the background has diverged. Must be finite and at least one.
+    pub revert_ratio: f32,
+}
+
+impl Default for ForegroundFilterConfig {
+    fn default() -> Self {
+        Self {
+            smoothing_factor: 0.8,
+            copy_ratio: 0.9,
+            hold_frames: 3,
+            revert_ratio: 4.0,
+        }
+    }
+}
+
+impl ForegroundFilterConfig {
+    /// Checks that every parameter is within its documented range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_smoothing_factor(self.smoothing_factor)?;
+        if !(self.copy_ratio > 0.0 && self.copy_ratio <= 1.0) {
+            return Err(ConfigError::InvalidPowerRatio(self.copy_ratio));
+        }
+        if self.hold_frames == 0 {
+            return Err(ConfigError::ZeroHoldFrames);
+        }
+        if !(self.revert_ratio.is_finite() && self.revert_ratio >= 1.0) {
+            return Err(ConfigError::InvalidPowerRatio(self.revert_ratio));
+        }
+        Ok(())
+    }
+}
+
+/// How often the weights were exchanged between the two filters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ForegroundFilterStats {
+    /// The number of times the background weights were copied to the foreground.
+    pub copies: u64,
+    /// The number of times the background was reverted to the foreground weights.
+    pub reverts: u64,
+}
+
+/// A non-adapting foreground filter that produces the output of a two-path canceller.
+///
+/// The canceller's own weights act as the background filter: they adapt on every frame and
+/// may be disturbed by double talk or diverge. The foreground only filters, and receives
+/// the background weights once they have proven to cancel more echo.
+#[derive(Clone)]
+pub(crate) struct ForegroundFilter<T: Float> {
+    config: ForegroundFilterConfig,
+    weights: Vec<Complex<T>>,
+    echo_f: Vec<Complex<T>>,
+    echo_t: Vec<T>,
+    background_energy: T,
+    foreground_energy: T,
+    better_frames: u32,
+    stats: ForegroundFilterStats,
+}
+
+impl<T: Float> ForegroundFilter<T> {
+    /// Creates the foreground filter, starting from the current background `weights`.
+    pub(crate) fn new(config: ForegroundFilterConfig, weights: &[Complex<T>]) -> Self {
+        let fft_size = (weights.len() - 1) * 2;
+        Self {
+            config,
+            weights: weights.to_vec(),
+            echo_f: vec![Complex::zero(); weights.len()],
+            echo_t: vec![T::zero(); fft_size],
+            background_energy: T::zero(),
+            foreground_energy: T::zero(),
+            better_frames: 0,
+            stats: ForegroundFilterStats::default(),
+        }
+    }
+
+    /// Forgets the energy estimates and statistics and sets the foreground weights.
+    pub(crate) fn reset(&mut self, weights: &[Complex<T>]) {
+        self.weights.copy_from_slice(weights);
+        self.background_energy = T::zero();
+        self.foreground_energy = T::zero();
+        self.better_frames = 0;
+        self.stats = ForegroundFilterStats::default();
+    }
+
+    /// Replaces the foreground weights, e.g. after restoring a snapshot.
+    pub(crate) fn set_weights(&mut self, weights: &[Complex<T>]) {
+        self.weights.copy_from_slice(weights);
+    }
+
+    pub(crate) fn stats(&self) -> ForegroundFilterStats {
+        self.stats
+    }
+
+    /// Filters the far-end spectrum with the foreground weights and replaces the background
+    /// `error_signal` and `estimated_echo` of this frame with the foreground's. Then copies
+    /// the weights from one filter to the other if the energy comparison calls for it.
+    ///
+    /// Returns whether the background was reverted to the foreground weights.
+    pub(crate) fn process(
+        &mut self,
+        fft: &mut impl FftBackend<T>,
+        far_end_spectrum: &[Complex<T>],
+        mic_frame: &[T],
+        background_weights: &mut [Complex<T>],
+        error_signal: &mut [T],
+        estimated_echo: &mut [T],
+    ) -> bool {
+        let fft_size = self.echo_t.len();
+        let frame_size = fft_size / 2;
+        let background_energy = energy(error_signal);
+
+        kernels::complex_mul(&mut self.echo_f, &self.weights, far_end_spectrum);
+        fft.inverse(&self.echo_f, &mut self.echo_t);
+        let scale = T::one() / float(fft_size as f64);
+        for (((e, echo), &y), &mic) in error_signal
+            .iter_mut()
+            .zip(estimated_echo.iter_mut())
+            .zip(&self.echo_t[frame_size..])
+            .zip(mic_frame)
+        {
+            *echo = y * scale;
+            *e = mic - *echo;
+        }
+        let foreground_energy = energy(error_signal);
+
+        let a: T = float(self.config.smoothing_factor);
+        self.background_energy = a * self.background_energy + (T::one() - a) * background_energy;
+        self.foreground_energy = a * self.foreground_energy + (T::one() - a) * foreground_energy;
+
+        // Divergence shows within a single frame, so reverting does not wait for the
+        // smoothed estimates, which may still remember e.g. a near-end burst.
+        if background_energy > float::<T>(self.config.revert_ratio) * foreground_energy {
+            background_weights.copy_from_slice(&self.weights);
+            self.background_energy = self.foreground_energy;
+            self.better_frames = 0;
+            self.stats.reverts += 1;
+            return true;
+        } else if self.background_energy
+            < float::<T>(self.config.copy_ratio) * self.foreground_energy
+        {
+            self.better_frames += 1;
+            if self.better_frames >= self.config.hold_frames {
+                self.weights.copy_from_slice(background_weights);
+                self.foreground_energy = self.background_energy;
+                self.better_frames = 0;
+                self.stats.copies += 1;
+            }
+        } else {
+            self.better_frames = 0;
+        }
+        false
+    }
+}
+
+fn energy<T: Float>(signal: &[T]) -> T {
+    signal.iter().map(|&x| x * x).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{noise, run};
+    use crate::{FdafAec, KalmanConfig};
+
+    const FFT_SIZE: usize = 512;
+    const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+    #[test]
+    fn foreground_protects_the_output_from_double_talk() {
+        let frames = 160;
+        let far_end = noise(FRAME_SIZE * frames, 71);
+        let near_end = noise(FRAME_SIZE * frames, 72);
+        // Loud near-end speech without a double-talk detector from frame 60 to 80.
+        let talk = FRAME_SIZE * 60..FRAME_SIZE * 80;
+        let echo: Vec<f32> = (0..far_end.len())
+            .map(|i| if i >= 25 { 0.5 * far_end[i - 25] } else { 0.0 })
+            .collect();
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                echo[i]
+                    + if talk.contains(&i) {
+                        near_end[i]
+                    } else {
+                        0.001 * near_end[i]
+                    }
+            })
+            .collect();
+
+        let mut single = FdafAec::<FFT_SIZE>::new(0.8, 0.9, 10e-4, 10e-4);
+        let mut two_path = single.clone();
+        two_path
+            .enable_foreground_filter(ForegroundFilterConfig::default())
+            .unwrap();
+        let single_out = run(&mut single, &far_end, &mic);
+        let two_path_out = run(&mut two_path, &far_end, &mic);
+        assert!(two_path_out.iter().all(|x| x.is_finite()));
+
+        // Residual echo in the output, i.e. the output minus the near-end signal.
+        let residual = |output: &[f32], frames: core::ops::Range<usize>| -> f32 {
+            (frames.start * FRAME_SIZE..frames.end * FRAME_SIZE)
+                .map(|i| {
+                    let r = output[i] - (mic[i] - echo[i]);
+                    r * r
+                })
+                .sum()
+        };
+        // The foreground keeps the converged weights while the near-end talks.
+        assert!(
+            residual(&two_path_out, 60..80) < 0.2 * residual(&single_out, 60..80),
+            "{} >= {}",
+            residual(&two_path_out, 60..80),
+            residual(&single_out, 60..80)
+        );
+        // And both converge to the same steady state afterwards.
+        assert!(residual(&two_path_out, 140..160) < 1e-3 * residual(&mic, 140..160));
+
+        let stats = two_path.foreground_filter_stats().unwrap();
+        assert!(stats.copies > 0);
+    }
+
+    #[test]
+    fn disturbed_background_is_reverted() {
+        let frames = 60;
+        let far_end = noise(FRAME_SIZE * frames, 73);
+        let near_end = noise(FRAME_SIZE * frames, 74);
+        // A short, very loud near-end burst throws the background far off the echo path.
+        let burst = FRAME_SIZE * 40..FRAME_SIZE * 42;
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                let echo = if i >= 20 { 0.4 * far_end[i - 20] } else { 0.0 };
+                echo + if burst.contains(&i) {
+                    50.0 * near_end[i]
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_foreground_filter(ForegroundFilterConfig::default())
+            .unwrap();
+        let output = run(&mut aec, &far_end, &mic);
+
+        assert!(aec.foreground_filter_stats().unwrap().reverts > 0);
+        // Right after the burst the reverted canceller cancels the echo again.
+        let after = &output[burst.end + FRAME_SIZE * 2..burst.end + FRAME_SIZE * 4];
+        let echo = &mic[burst.end + FRAME_SIZE * 2..burst.end + FRAME_SIZE * 4];
+        let energy = |signal: &[f32]| -> f32 { signal.iter().map(|x| x * x).sum() };
+        assert!(energy(after) < 1e-2 * energy(echo));
+    }
+
+    #[test]
+    fn revert_restarts_the_kalman_uncertainty() {
+        let frames = 60;
+        let far_end = noise(FRAME_SIZE * frames, 75);
+        let near_end = noise(FRAME_SIZE * frames, 76);
+        let burst = FRAME_SIZE * 40..FRAME_SIZE * 42;
+        let mic: Vec<f32> = (0..far_end.len())
+            .map(|i| {
+                let echo = if i >= 20 { 0.4 * far_end[i - 20] } else { 0.0 };
+                echo + if burst.contains(&i) {
+                    50.0 * near_end[i]
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        aec.enable_kalman_adaptation(KalmanConfig::default())
+            .unwrap();
+        aec.enable_foreground_filter(ForegroundFilterConfig::default())
+            .unwrap();
+
+        let mut reverts = 0;
+        for (far, mic) in far_end
+            .chunks_exact(FRAME_SIZE)
+            .zip(mic.chunks_exact(FRAME_SIZE))
+        {
+            let mut error = [0.0; FRAME_SIZE];
+            aec.process(
+                &mut error,
+                far.first_chunk().unwrap(),
+                mic.first_chunk().unwrap(),
+            );
+            let stats = aec.foreground_filter_stats().unwrap();
+            if stats.reverts > reverts {
+                reverts = stats.reverts;
+                // The reverted filter starts over like a fresh Kalman filter.
+                assert!(aec.step_sizes().unwrap().iter().all(|&step| step == 0.0));
+            }
+        }
+        assert!(reverts > 0);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = ForegroundFilterConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert!(matches!(
+            ForegroundFilterConfig {
+                copy_ratio: f32::NAN,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidPowerRatio(_))
+        ));
+        assert_eq!(
+            ForegroundFilterConfig {
+                revert_ratio: 0.5,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::InvalidPowerRatio(0.5))
+        );
+
+        let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+        assert!(aec
+            .enable_foreground_filter(ForegroundFilterConfig {
+                hold_frames: 0,
+                ..config
+            })
+            .is_err());
+        assert_eq!(aec.foreground_filter_stats(), None);
+    }
+}
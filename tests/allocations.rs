@@ -0,0 +1,337 @@
+//! Verifies that the per-frame processing path never touches the heap.
+//!
+//! A counting global allocator records every allocation made on the current thread, so the
+//! assertions are not disturbed by other tests running in parallel.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::Mutex;
+
+use fdaf_aec::{
+    CoherenceDetector, ComfortNoiseConfig, DecorrelationConfig, DelayEstimatorConfig,
+    DivergenceConfig, DriftCompensationConfig, FdafAec, FdafAecConfig, FixedFft,
+    ForegroundFilterConfig, KalmanConfig, MetricsConfig, MultiMicFdafAec, MultichannelFdafAec,
+    OptimalStepSizeConfig, PartitionedFdafAec, ProportionateConfig, ResidualEchoSuppressorConfig,
+    StepSizeControl, StreamingFdafAec,
+};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations made on this thread while running `f`.
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn test_frames<const N: usize>(frames: usize) -> Vec<([f32; N], [f32; N])> {
+    (0..frames)
+        .map(|frame| {
+            let mut far_end = [0.0; N];
+            let mut mic = [0.0; N];
+            for (i, (far, mic)) in far_end.iter_mut().zip(mic.iter_mut()).enumerate() {
+                let t = (frame * N + i) as f32;
+                *far = (t * 0.05).sin() * 0.5;
+                *mic = (t * 0.05 - 0.7).sin() * 0.3 + (t * 0.31).sin() * 0.1;
+            }
+            (far_end, mic)
+        })
+        .collect()
+}
+
+#[test]
+fn fdaf_process_does_not_allocate() {
+    let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+    let frames = test_frames::<256>(20);
+    let mut error_signal = [0.0; 256];
+
+    let allocations = count_allocations(|| {
+        for (far_end, mic) in &frames {
+            aec.process(&mut error_signal, far_end, mic);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn f64_and_i16_processing_does_not_allocate() {
+    let mut aec = FdafAec::<512, f64>::new(0.5, 0.9, 10e-4, 10e-4);
+    let frames = test_frames::<256>(20);
+    let frames_i16: Vec<_> = frames
+        .iter()
+        .map(|(far_end, mic)| {
+            (
+                far_end.map(|x| (x * 32767.0) as i16),
+                mic.map(|x| (x * 32767.0) as i16),
+            )
+        })
+        .collect();
+    let mut error_signal = [0.0; 256];
+    let mut error_i16 = [0; 256];
+
+    let allocations = count_allocations(|| {
+        for ((far_end, mic), (far_i16, mic_i16)) in frames.iter().zip(&frames_i16) {
+            aec.process(
+                &mut error_signal,
+                &far_end.map(f64::from),
+                &mic.map(f64::from),
+            );
+            aec.process_i16(&mut error_i16, far_i16, mic_i16);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn fixed_fft_canceller_does_not_allocate() {
+    static AEC: Mutex<Option<FdafAec<512, f32, FixedFft<f32, 512>>>> = Mutex::new(None);
+    let frames = test_frames::<256>(20);
+    let mut error_signal = [0.0; 256];
+
+    // Not even construction allocates, so the canceller can live in a `static`.
+    let allocations = count_allocations(|| {
+        let mut aec = AEC.lock().unwrap();
+        let aec = aec.insert(FdafAec::new(0.5, 0.9, 10e-4, 10e-4));
+        for (far_end, mic) in &frames {
+            aec.process(&mut error_signal, far_end, mic);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn dyn_process_does_not_allocate() {
+    let mut aec = FdafAecConfig::new().try_build_dyn(512).unwrap();
+    let frames = test_frames::<256>(20);
+    let mut error_signal = [0.0; 256];
+    let mut error_i16 = [0; 256];
+
+    let allocations = count_allocations(|| {
+        for (far_end, mic) in &frames {
+            aec.process(&mut error_signal, far_end, mic).unwrap();
+            let far_i16 = far_end.map(|x| (x * 32767.0) as i16);
+            let mic_i16 = mic.map(|x| (x * 32767.0) as i16);
+            aec.process_i16(&mut error_i16, &far_i16, &mic_i16).unwrap();
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn partitioned_process_does_not_allocate() {
+    let mut aec = PartitionedFdafAec::<128, 4>::new(0.5, 0.9, 10e-4, 10e-4);
+    let frames = test_frames::<128>(20);
+    let mut error_signal = [0.0; 128];
+
+    let allocations = count_allocations(|| {
+        for (far_end, mic) in &frames {
+            aec.process(&mut error_signal, far_end, mic);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn multichannel_process_does_not_allocate() {
+    let mut aec = MultichannelFdafAec::<512, 2>::new(0.5, 0.9, 10e-4, 10e-4);
+    aec.enable_decorrelation(DecorrelationConfig::default())
+        .unwrap();
+    let frames = test_frames::<256>(20);
+    let mut error_signal = [0.0; 256];
+
+    let allocations = count_allocations(|| {
+        for (far_end, mic) in &frames {
+            let mut far_end_frames = [*far_end, *mic];
+            aec.decorrelate(&mut far_end_frames);
+            aec.process(&mut error_signal, &far_end_frames, mic);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn multi_mic_process_does_not_allocate() {
+    let mut aec = MultiMicFdafAec::<512, 2>::new(0.5, 0.9, 10e-4, 10e-4);
+    let frames = test_frames::<256>(20);
+    let mut error_signals = [[0.0; 256]; 2];
+
+    let allocations = count_allocations(|| {
+        for (far_end, mic) in &frames {
+            aec.process(&mut error_signals, far_end, &[*mic, *far_end]);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn streaming_process_does_not_allocate() {
+    let mut aec = StreamingFdafAec::new(FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4));
+    let far_end: Vec<f32> = test_frames::<256>(20)
+        .iter()
+        .flat_map(|(far_end, _)| *far_end)
+        .collect();
+    let mut output = [0.0; 441];
+
+    let allocations = count_allocations(|| {
+        for chunk in far_end.chunks(441) {
+            aec.process(&mut output[..chunk.len()], chunk, chunk);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn process_with_detector_does_not_allocate() {
+    let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+    let mut detector = CoherenceDetector::new(512, 0.5, 0.5, 4).unwrap();
+    let frames = test_frames::<256>(20);
+    let mut error_signal = [0.0; 256];
+
+    let allocations = count_allocations(|| {
+        for (far_end, mic) in &frames {
+            aec.process_with_detector(&mut detector, &mut error_signal, far_end, mic)
+                .unwrap();
+        }
+    });
+    assert_eq!(allocations, 0);
+}
+
+/// Enables one optional stage of a canceller.
+type EnableStage = fn(&mut FdafAec<512>);
+
+/// Processes `frames` and reads the statistics of the optional stages, counting the
+/// allocations made along the way.
+fn count_processing_allocations(
+    aec: &mut FdafAec<512>,
+    frames: &[([f32; 256], [f32; 256])],
+) -> usize {
+    let mut error_signal = [0.0; 256];
+    count_allocations(|| {
+        for (far_end, mic) in frames {
+            aec.process(&mut error_signal, far_end, mic);
+            std::hint::black_box(aec.metrics());
+            std::hint::black_box(aec.step_sizes());
+        }
+    })
+}
+
+#[test]
+fn optional_stages_do_not_allocate() {
+    let stages: &[(&str, EnableStage)] = &[
+        ("post-processing", |aec| {
+            aec.enable_residual_echo_suppressor(ResidualEchoSuppressorConfig::default())
+                .unwrap();
+            aec.enable_comfort_noise(ComfortNoiseConfig::default())
+                .unwrap();
+        }),
+        ("delay compensation", |aec| {
+            aec.enable_delay_compensation(DelayEstimatorConfig::default())
+                .unwrap();
+        }),
+        ("drift compensation", |aec| {
+            aec.enable_drift_compensation(DriftCompensationConfig {
+                settle_frames: 5,
+                ..Default::default()
+            })
+            .unwrap();
+        }),
+        ("metrics", |aec| {
+            aec.enable_metrics(MetricsConfig::default()).unwrap();
+        }),
+        ("step size control", |aec| {
+            aec.enable_step_size_control(
+                StepSizeControl::Optimal(OptimalStepSizeConfig::default()),
+            )
+            .unwrap();
+        }),
+        ("Kalman adaptation", |aec| {
+            aec.enable_kalman_adaptation(KalmanConfig::default())
+                .unwrap();
+        }),
+        ("proportionate update", |aec| {
+            aec.enable_proportionate_update(ProportionateConfig::default())
+                .unwrap();
+        }),
+        ("foreground filter", |aec| {
+            aec.enable_foreground_filter(ForegroundFilterConfig::default())
+                .unwrap();
+        }),
+        ("divergence monitor", |aec| {
+            aec.enable_divergence_monitor(DivergenceConfig::default())
+                .unwrap();
+        }),
+    ];
+    let frames = test_frames::<256>(20);
+
+    for (stage, enable) in stages {
+        let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+        enable(&mut aec);
+        assert_eq!(
+            count_processing_allocations(&mut aec, &frames),
+            0,
+            "{stage}"
+        );
+    }
+}
+
+#[test]
+fn divergence_recovery_does_not_allocate() {
+    let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+    aec.enable_divergence_monitor(DivergenceConfig::default())
+        .unwrap();
+    let mut frames = test_frames::<256>(20);
+    // A corrupt frame forces a full recovery.
+    frames[10].0[3] = f32::NAN;
+
+    assert_eq!(count_processing_allocations(&mut aec, &frames), 0);
+    assert!(aec.divergence_events().unwrap() > 0);
+}
+
+#[test]
+fn tuning_and_reset_do_not_allocate() {
+    let mut aec = FdafAec::<512>::new(0.5, 0.9, 10e-4, 10e-4);
+    aec.enable_delay_compensation(DelayEstimatorConfig::default())
+        .unwrap();
+    aec.enable_metrics(MetricsConfig::default()).unwrap();
+    aec.enable_residual_echo_suppressor(ResidualEchoSuppressorConfig::default())
+        .unwrap();
+    aec.enable_comfort_noise(ComfortNoiseConfig::default())
+        .unwrap();
+    let frames = test_frames::<256>(20);
+    let mut error_signal = [0.0; 256];
+
+    let allocations = count_allocations(|| {
+        for (frame, (far_end, mic)) in frames.iter().enumerate() {
+            aec.set_step_size(0.1 + 0.05 * frame as f32).unwrap();
+            aec.set_smoothing_factor(0.8).unwrap();
+            aec.process(&mut error_signal, far_end, mic);
+            if frame == 10 {
+                aec.reset();
+            }
+        }
+    });
+    assert_eq!(allocations, 0);
+}
@@ -0,0 +1,120 @@
+//! Property tests of the streaming adapter: arbitrary buffer sizes must give exactly the
+//! output of whole-frame processing, delayed by the documented latency.
+
+use fdaf_aec::{FdafAec, FixedFft, StreamingFdafAec};
+use proptest::prelude::*;
+
+const FFT_SIZE: usize = 128;
+const FRAME_SIZE: usize = FFT_SIZE / 2;
+
+/// A far-end signal and a microphone signal holding its echo plus near-end noise.
+fn signals(frames: usize, seed: u32) -> (Vec<f32>, Vec<f32>) {
+    let mut state = seed.max(1);
+    let mut noise = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state as f32 / u32::MAX as f32) - 0.5
+    };
+    let len = frames * FRAME_SIZE;
+    let far_end: Vec<f32> = (0..len).map(|_| noise()).collect();
+    let mic = (0..len)
+        .map(|i| {
+            let echo = if i >= 9 { 0.6 * far_end[i - 9] } else { 0.0 };
+            echo + 0.01 * noise()
+        })
+        .collect();
+    (far_end, mic)
+}
+
+fn whole_frames(far_end: &[f32], mic: &[f32]) -> Vec<f32> {
+    let mut aec = FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4);
+    let mut output = Vec::with_capacity(mic.len());
+    for (far, mic) in far_end
+        .chunks_exact(FRAME_SIZE)
+        .zip(mic.chunks_exact(FRAME_SIZE))
+    {
+        let mut error = [0.0; FRAME_SIZE];
+        aec.process(
+            &mut error,
+            far.first_chunk().unwrap(),
+            mic.first_chunk().unwrap(),
+        );
+        output.extend_from_slice(&error);
+    }
+    output
+}
+
+/// Streams the signals in chunks of the given sizes, cycling through them.
+fn streamed(far_end: &[f32], mic: &[f32], chunk_sizes: &[usize]) -> Vec<f32> {
+    let mut aec = StreamingFdafAec::new(FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4));
+    let mut output = vec![0.0; mic.len()];
+    let mut start = 0;
+    for &size in chunk_sizes.iter().cycle() {
+        if start == mic.len() {
+            break;
+        }
+        let end = (start + size).min(mic.len());
+        aec.process(
+            &mut output[start..end],
+            &far_end[start..end],
+            &mic[start..end],
+        );
+        start = end;
+    }
+    output
+}
+
+proptest! {
+    #[test]
+    fn any_chunking_matches_whole_frames_delayed_by_the_latency(
+        frames in 2usize..12,
+        seed in any::<u32>(),
+        chunk_sizes in prop::collection::vec(0usize..3 * FRAME_SIZE, 1..16),
+    ) {
+        prop_assume!(chunk_sizes.iter().any(|&size| size > 0));
+        let (far_end, mic) = signals(frames, seed);
+        let expected = whole_frames(&far_end, &mic);
+        let output = streamed(&far_end, &mic, &chunk_sizes);
+
+        let latency = StreamingFdafAec::<FFT_SIZE>::LATENCY;
+        prop_assert!(output[..latency].iter().all(|&x| x == 0.0));
+        prop_assert_eq!(&output[latency..], &expected[..expected.len() - latency]);
+    }
+
+    #[test]
+    fn reset_restarts_the_stream(
+        seed in any::<u32>(),
+        first_chunk in 1usize..FRAME_SIZE,
+    ) {
+        let (far_end, mic) = signals(4, seed);
+        let mut aec = StreamingFdafAec::new(FdafAec::<FFT_SIZE>::new(0.5, 0.9, 10e-4, 10e-4));
+        let mut output = vec![0.0; mic.len()];
+        aec.process(&mut output[..first_chunk], &far_end[..first_chunk], &mic[..first_chunk]);
+        aec.reset();
+        aec.process(&mut output, &far_end, &mic);
+        prop_assert_eq!(output, streamed(&far_end, &mic, &[mic.len()]));
+    }
+}
+
+#[test]
+fn streams_with_any_fft_backend() {
+    let (far_end, mic) = signals(6, 7);
+    let mut aec = StreamingFdafAec::new(FdafAec::<FFT_SIZE, f32, FixedFft<f32, FFT_SIZE>>::new(
+        0.5, 0.9, 10e-4, 10e-4,
+    ));
+    let mut output = vec![0.0; mic.len()];
+    for ((output, far_end), mic) in output
+        .chunks_mut(37)
+        .zip(far_end.chunks(37))
+        .zip(mic.chunks(37))
+    {
+        aec.process(output, far_end, mic);
+    }
+
+    let expected = whole_frames(&far_end, &mic);
+    let latency = StreamingFdafAec::<FFT_SIZE>::LATENCY;
+    for (actual, expected) in output[latency..].iter().zip(&expected) {
+        assert!((actual - expected).abs() < 1e-4, "{actual} vs {expected}");
+    }
+}